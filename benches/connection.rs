@@ -0,0 +1,142 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Benchmarks for receiving, dispatching and sending messages.
+
+extern crate byteorder;
+#[macro_use]
+extern crate criterion;
+extern crate skylane;
+
+use byteorder::{NativeEndian, WriteBytesExt};
+use criterion::Criterion;
+
+use skylane::server::{Bundle, Connection, Message, Object, SkylaneError, Socket, Task};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Number of messages sent in one batch.
+const BATCH_SIZE: u32 = 64;
+
+/// Number of objects registered for lookup benchmarks.
+const NUM_OBJECTS: u32 = 1000;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Object doing nothing except for reading its only argument.
+struct Dummy;
+
+impl Object for Dummy {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        message.next_uint()?;
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Composes batch of messages with one `uint` argument addressed to objects from `1` to `range`.
+fn compose_batch(range: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for i in 0..BATCH_SIZE {
+        bytes.write_u32::<NativeEndian>(1 + i % range).unwrap();
+        bytes.write_u16::<NativeEndian>(0).unwrap();
+        bytes.write_u16::<NativeEndian>(12).unwrap();
+        bytes.write_u32::<NativeEndian>(i).unwrap();
+    }
+    bytes
+}
+
+/// Creates connection with `num_objects` registered objects and socket for sending messages to it.
+fn prepare_connection(num_objects: u32) -> (Connection, Socket) {
+    let (client, server) = Socket::pair().unwrap();
+    let mut connection = Connection::new(server);
+    for _ in 0..num_objects {
+        connection.add_next_client_object(Box::new(Dummy)).unwrap();
+    }
+    (connection, client)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn receive_parsing(c: &mut Criterion) {
+    let (mut connection, client) = prepare_connection(1);
+    let bytes = compose_batch(1);
+    c.bench_function("receive parsing", move |b| {
+        b.iter(|| {
+            client.write(&bytes).unwrap();
+            connection.process_events().unwrap();
+        })
+    });
+}
+
+fn dispatch_lookup(c: &mut Criterion) {
+    let (mut connection, client) = prepare_connection(NUM_OBJECTS);
+    let bytes = compose_batch(NUM_OBJECTS);
+    c.bench_function("dispatch lookup", move |b| {
+        b.iter(|| {
+            client.write(&bytes).unwrap();
+            connection.process_events().unwrap();
+        })
+    });
+}
+
+fn id_allocation(c: &mut Criterion) {
+    let (mut connection, _client) = prepare_connection(NUM_OBJECTS);
+    c.bench_function("id allocation", move |b| {
+        b.iter(|| {
+            let id = connection.add_next_server_object(Box::new(Dummy)).unwrap();
+            connection.remove_object(id);
+        })
+    });
+}
+
+fn send_batching(c: &mut Criterion) {
+    let (client, server) = Socket::pair().unwrap();
+    let bytes = compose_batch(1);
+    let mut buffer = vec![0; bytes.len()];
+    let mut fds = [0; 24];
+    c.bench_function("send batched", move |b| {
+        b.iter(|| {
+            server.write(&bytes).unwrap();
+            client.receive_message(&mut buffer, &mut fds).unwrap();
+        })
+    });
+
+    let (client, server) = Socket::pair().unwrap();
+    let bytes = compose_batch(1);
+    let mut buffer = vec![0; bytes.len()];
+    let mut fds = [0; 24];
+    c.bench_function("send unbatched", move |b| {
+        b.iter(|| {
+            for message in bytes.chunks(12) {
+                server.write(message).unwrap();
+            }
+            client.receive_message(&mut buffer, &mut fds).unwrap();
+        })
+    });
+}
+
+// -------------------------------------------------------------------------------------------------
+
+criterion_group!(benches, receive_parsing, dispatch_lookup, id_allocation, send_batching);
+criterion_main!(benches);
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,202 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Proxy sitting between Wayland client and compositor dumping the traffic.
+//!
+//! Creates display socket with given name (`wayland-inspect` by default) and forwards every
+//! accepted connection to compositor from `WAYLAND_DISPLAY`. Messages are decoded and printed
+//! using `Logger` of forwarding sockets. Usage:
+//!
+//! ```sh
+//! cargo run --example skylane-inspect -- wayland-inspect &
+//! WAYLAND_DISPLAY=wayland-inspect weston-terminal
+//! ```
+
+extern crate byteorder;
+extern crate nix;
+extern crate skylane;
+
+use std::os::unix::io::RawFd;
+use std::thread;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use skylane::server::{DisplaySocket, LogLevel, LogRecord, MessageIter, Shutdown, SkylaneError,
+                      Socket};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Default name of proxy display socket.
+const DEFAULT_NAME: &str = "wayland-inspect";
+
+/// Size of buffer for reading.
+const BUFFER_SIZE: usize = 4096;
+
+/// Maximal number of file descriptors received at once.
+const MAX_FDS: usize = 28;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Prints requests sent by client to compositor.
+fn log_request(record: &LogRecord) {
+    print_record("client", record);
+}
+
+/// Prints events sent by compositor to client.
+fn log_event(record: &LogRecord) {
+    print_record("server", record);
+}
+
+/// Prints message traces and errors.
+fn print_record(sender: &str, record: &LogRecord) {
+    match (record.level, record.object_id, record.opcode) {
+        (LogLevel::Trace, Some(object_id), Some(opcode)) => {
+            println!("{}: {}.{} ({})", sender, object_id, opcode, record.text);
+        }
+        (LogLevel::Trace, _, _) => {}
+        _ => println!("{}: {}", sender, record),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Forwards messages from `source` to `destination` until one of them disconnects or sends
+/// malformed message.
+///
+/// Only complete messages are forwarded, so they can be decoded by logger of `destination`. File
+/// descriptors are passed along with the first message sent after they were received.
+fn forward(source: Socket, destination: Socket) -> Result<(), SkylaneError> {
+    let mut pending = Vec::new();
+    let mut pending_fds: Vec<RawFd> = Vec::new();
+    let mut bytes = [0; BUFFER_SIZE];
+    let mut fds = [0; 4 * MAX_FDS];
+
+    loop {
+        let (num_bytes, num_fds) = source.receive_message(&mut bytes, &mut fds)?;
+        if num_bytes == 0 {
+            return Ok(());
+        }
+
+        pending.extend_from_slice(&bytes[..num_bytes]);
+        for i in 0..num_fds {
+            pending_fds.push(NativeEndian::read_i32(&fds[(4 * i)..]));
+        }
+
+        let end = match find_end(&pending) {
+            Ok(end) => end,
+            Err(err) => {
+                for fd in pending_fds.drain(..) {
+                    let _ = nix::unistd::close(fd);
+                }
+                return Err(err);
+            }
+        };
+
+        let mut written = 0;
+        while written < end {
+            match destination.write_with_control_data(&pending[written..end], &pending_fds) {
+                Ok(num_bytes) => {
+                    written += num_bytes;
+                    for fd in pending_fds.drain(..) {
+                        let _ = nix::unistd::close(fd);
+                    }
+                }
+                Err(ref err) if err.is_would_block() => thread::yield_now(),
+                Err(err) => return Err(err),
+            }
+        }
+        pending.drain(..end);
+    }
+}
+
+/// Returns end of complete messages at the beginning of `bytes`.
+///
+/// Messages shorter than header or with size not aligned to 32 bits are protocol errors: the
+/// stream can not be split into messages anymore, so the connection must be closed.
+fn find_end(bytes: &[u8]) -> Result<usize, SkylaneError> {
+    let mut messages = MessageIter::new(bytes);
+    loop {
+        match messages.next() {
+            Some(Ok((header, _))) if header.size % 4 != 0 => {
+                return Err(SkylaneError::Other(format!("Unaligned message: {:?}", header)));
+            }
+            Some(Ok(_)) => {}
+            Some(Err(err)) => return Err(err),
+            None => return Ok(messages.get_position()),
+        }
+    }
+}
+
+/// Connects to compositor and forwards traffic of `client` in both directions.
+fn proxy(client: Socket) -> Result<(), SkylaneError> {
+    let server = Socket::connect_default()?;
+    server.set_logger(Some(Box::new(log_request)));
+    server.set_nonblocking(false);
+    client.set_logger(Some(Box::new(log_event)));
+    client.set_nonblocking(false);
+
+    let (requests_source, requests_destination) = (client.clone(), server.clone());
+    let requests = thread::spawn(move || {
+        let result = forward(requests_source.clone(), requests_destination.clone());
+        shutdown(&requests_source, &requests_destination);
+        result
+    });
+
+    let result = forward(server.clone(), client.clone());
+    shutdown(&server, &client);
+    let _ = requests.join();
+    result
+}
+
+/// Shuts down both sockets so forwarding in the other direction stops too.
+fn shutdown(first: &Socket, second: &Socket) {
+    let _ = first.shutdown(Shutdown::Both);
+    let _ = second.shutdown(Shutdown::Both);
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn main() {
+    let name = std::env::args().nth(1).unwrap_or(DEFAULT_NAME.to_owned());
+    let display = match DisplaySocket::new_named(&name) {
+        Ok(display) => display,
+        Err(err) => {
+            eprintln!("Failed to create display socket '{}': {:?}", name, err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = display.set_nonblocking(false) {
+        eprintln!("Failed to set blocking mode: {:?}", err);
+        std::process::exit(1);
+    }
+
+    println!("Listening on {:?}", display.get_path());
+    loop {
+        match display.accept() {
+            Ok(client) => {
+                println!("Client connected");
+                thread::spawn(move || if let Err(err) = proxy(client) {
+                                  println!("Connection closed: {:?}", err);
+                              });
+            }
+            Err(err) => println!("Failed to accept client: {:?}", err),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
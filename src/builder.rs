@@ -0,0 +1,231 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Builder for `Connection`.
+
+use std::time::Duration;
+
+use defs::{Logger, Side};
+use bundle::BundleInternal;
+use connection::{Connection, ConnectionInternal};
+use dispatch::DispatchPolicy;
+use display::RegistryFactory;
+use limits::{FdLimit, RateLimit};
+use map::ObjectStore;
+use message::Utf8Policy;
+use reader::DEFAULT_BUFFER_SIZE;
+use sockets::Socket;
+use validation::ValidationMode;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Builder for `Connection`.
+///
+/// Collects all settings of connection. Settings not specified are the same as for
+/// `Connection::new`.
+pub struct ConnectionBuilder {
+    socket: Socket,
+    registry_factory: Option<RegistryFactory>,
+    read_buffer_size: usize,
+    dispatch_policy: DispatchPolicy,
+    logger: Logger,
+    serial_start: Option<u32>,
+    max_objects: Option<usize>,
+    blocking: bool,
+    rate_limit: Option<RateLimit>,
+    fd_limit: Option<FdLimit>,
+    validation_mode: ValidationMode,
+    utf8_policy: Utf8Policy,
+    side: Option<Side>,
+    strict: bool,
+    idle_timeout: Option<Duration>,
+    object_store: Option<Box<dyn ObjectStore>>,
+}
+
+impl ConnectionBuilder {
+    /// Constructs new `ConnectionBuilder` for connection on given socket.
+    pub fn new(socket: Socket) -> Self {
+        ConnectionBuilder {
+            socket,
+            registry_factory: None,
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            dispatch_policy: DispatchPolicy::default(),
+            logger: None,
+            serial_start: None,
+            max_objects: None,
+            blocking: false,
+            rate_limit: None,
+            fd_limit: None,
+            validation_mode: ValidationMode::default(),
+            utf8_policy: Utf8Policy::default(),
+            side: None,
+            strict: false,
+            idle_timeout: None,
+            object_store: None,
+        }
+    }
+
+    /// Makes the connection server-side one with built-in `wl_display` implementation.
+    ///
+    /// See `Connection::new_server`.
+    pub fn server(mut self, registry_factory: RegistryFactory) -> Self {
+        self.registry_factory = Some(registry_factory);
+        self
+    }
+
+    /// Sets maximal number of bytes read from socket at once.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size;
+        self
+    }
+
+    /// Sets dispatch error policy.
+    ///
+    /// See `Connection::set_dispatch_policy`.
+    pub fn dispatch_policy(mut self, policy: DispatchPolicy) -> Self {
+        self.dispatch_policy = policy;
+        self
+    }
+
+    /// Sets logger of the socket.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Sets serial returned by first call to `Connection::next_serial`.
+    pub fn serial_start(mut self, serial: u32) -> Self {
+        self.serial_start = Some(serial);
+        self
+    }
+
+    /// Sets maximal number of objects.
+    ///
+    /// See `Connection::set_max_objects`.
+    pub fn max_objects(mut self, max_objects: usize) -> Self {
+        self.max_objects = Some(max_objects);
+        self
+    }
+
+    /// Sets blocking mode of reading.
+    ///
+    /// See `Socket::set_nonblocking`.
+    pub fn blocking(mut self, blocking: bool) -> Self {
+        self.blocking = blocking;
+        self
+    }
+
+    /// Sets limits on traffic from the peer.
+    ///
+    /// See `Connection::set_rate_limit`.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Sets limit on received file descriptors not yet dispatched.
+    ///
+    /// See `Connection::set_fd_limit`.
+    pub fn fd_limit(mut self, limit: FdLimit) -> Self {
+        self.fd_limit = Some(limit);
+        self
+    }
+
+    /// Sets validation mode for outgoing messages.
+    ///
+    /// See `Connection::set_validation_mode`.
+    pub fn validation_mode(mut self, mode: ValidationMode) -> Self {
+        self.validation_mode = mode;
+        self
+    }
+
+    /// Sets handling of invalid UTF-8 in string arguments.
+    ///
+    /// See `Connection::set_utf8_policy`.
+    pub fn utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.utf8_policy = policy;
+        self
+    }
+
+    /// Sets side of connection. Server connections (see `server`) always have server side.
+    ///
+    /// See `Connection::set_side`.
+    pub fn side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    /// Enables strict mode.
+    ///
+    /// See `Connection::set_strict`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets idle timeout.
+    ///
+    /// See `Connection::set_idle_timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets storage of registered objects.
+    ///
+    /// See `Connection::set_object_store`.
+    pub fn object_store(mut self, store: Box<dyn ObjectStore>) -> Self {
+        self.object_store = Some(store);
+        self
+    }
+
+    /// Constructs the `Connection`.
+    pub fn build(self) -> Connection {
+        let socket = self.socket;
+        if self.logger.is_some() {
+            // Logger is shared by clones of the socket, so do not reset the one set on them.
+            socket.set_logger(self.logger);
+        }
+        socket.set_nonblocking(!self.blocking);
+
+        let mut connection = match self.registry_factory {
+            Some(registry_factory) => Connection::new_server(socket, registry_factory),
+            None => Connection::new(socket),
+        };
+
+        if self.side.is_some() && connection.get_side().is_none() {
+            connection.set_side(self.side);
+        }
+        connection.set_read_buffer_size(self.read_buffer_size);
+        connection.set_dispatch_policy(self.dispatch_policy);
+        connection.set_strict(self.strict);
+        connection.set_max_objects(self.max_objects);
+        connection.set_rate_limit(self.rate_limit);
+        connection.set_fd_limit(self.fd_limit);
+        connection.set_validation_mode(self.validation_mode);
+        connection.set_utf8_policy(self.utf8_policy);
+        connection.set_idle_timeout(self.idle_timeout);
+        if let Some(store) = self.object_store {
+            connection.set_object_store(store);
+        }
+        if let Some(serial) = self.serial_start {
+            connection.get_bundle().set_last_serial(serial.wrapping_sub(1));
+        }
+        connection
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
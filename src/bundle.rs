@@ -17,13 +17,120 @@
 
 //! Defines `Bundle`.
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Cursor;
+use std::os::unix::io::RawFd;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 
-use defs::SkylaneError;
-use object::{Object, ObjectId, DISPLAY_ID, SERVER_START_ID};
-use sockets::Socket;
+use byteorder::{ByteOrder, NativeEndian};
+
+use defs::{Direction, Header, LogLevel, LogRecord, Side, SkylaneError, Task};
+use clock::{Clock, MonotonicClock};
+use dispatch::{ConnectionState, DisconnectHandler, DisconnectReason};
+use display;
+use fd::OwnedFd;
+use introspect::{History, Introspection, MessageInfo, ObjectInfo, DEFAULT_HISTORY_SIZE};
+use object::{Object, ObjectId, CLIENT_END_ID, DISPLAY_ID, SERVER_END_ID, SERVER_START_ID};
+use map::{ObjectMap, ObjectRef, ObjectStore, WeakObjectRef};
+use marshal::Marshaller;
+use message::{Message, MessageInternal, MessageIter, Utf8Policy};
+use meta::InterfaceMeta;
+use placeholder::{PendingMessage, PendingQueue, Placeholder};
+use pool::BufferPool;
+use queue::{OutgoingQueue, Priority};
+use serials::{SerialHistory, SerialInfo};
+use stats::MetricsSink;
+use trace::{self, TraceRecord, TraceSink};
+use sockets::{Socket, SocketInternal, WeakSocket};
+use validation::{ObjectSnapshot, ValidationMode, Validator, VersionCheck};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Change of single object made during transaction along with state needed to revert it.
+struct TransactionEntry {
+    id: ObjectId,
+    previous: Option<ObjectRef>,
+    snapshot: ObjectSnapshot,
+    was_zombie: bool,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Maximal number of zombies kept for objects created by server. Core protocol has no way for
+/// client to confirm their removal, so the oldest ones are forgotten once there are more of them.
+const MAX_UNCONFIRMED_ZOMBIES: usize = 256;
+
+/// Set of IDs of removed objects which peer may still use.
+#[derive(Default)]
+struct Zombies {
+    ids: HashSet<ObjectId>,
+    unconfirmed: VecDeque<ObjectId>,
+}
+
+impl Zombies {
+    /// Adds zombie. Returns ID of the oldest zombie which will never be confirmed if it had to be
+    /// forgotten to make space.
+    fn insert(&mut self, id: ObjectId) -> Option<ObjectId> {
+        if !self.ids.insert(id) || id < SERVER_START_ID {
+            return None;
+        }
+        self.unconfirmed.push_back(id);
+        if self.unconfirmed.len() > MAX_UNCONFIRMED_ZOMBIES {
+            let oldest = self.unconfirmed.pop_front();
+            if let Some(oldest) = oldest {
+                self.ids.remove(&oldest);
+            }
+            oldest
+        } else {
+            None
+        }
+    }
+
+    /// Forgets zombie. Returns `true` if it was present.
+    fn remove(&mut self, id: &ObjectId) -> bool {
+        let removed = self.ids.remove(id);
+        if removed && *id >= SERVER_START_ID {
+            self.unconfirmed.retain(|zombie| zombie != id);
+        }
+        removed
+    }
+
+    /// Checks if object with given ID is a zombie.
+    fn contains(&self, id: &ObjectId) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Returns iterator over all zombies.
+    fn iter(&self) -> std::collections::hash_set::Iter<'_, ObjectId> {
+        self.ids.iter()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Marks nested dispatch loop as running. The dispatch depth is decremented when the guard is
+/// dropped, also if the handler panicked.
+pub struct DispatchGuard {
+    depth: Rc<Cell<usize>>,
+}
+
+impl DispatchGuard {
+    /// Increments dispatch depth and constructs new `DispatchGuard`.
+    fn new(depth: &Rc<Cell<usize>>) -> Self {
+        depth.set(depth.get() + 1);
+        DispatchGuard { depth: depth.clone() }
+    }
+}
+
+impl Drop for DispatchGuard {
+    fn drop(&mut self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+}
 
 // -------------------------------------------------------------------------------------------------
 
@@ -31,7 +138,30 @@ use sockets::Socket;
 /// add/remove new objects or access socket. It also serves this crate internally as data store.
 pub struct Bundle {
     socket: Socket,
-    objects: Rc<RefCell<HashMap<ObjectId, Rc<RefCell<Box<Object>>>>>>,
+    objects: Rc<RefCell<Box<dyn ObjectStore>>>,
+    serial: Rc<Cell<u32>>,
+    serials: Rc<RefCell<SerialHistory>>,
+    clock: Rc<RefCell<Box<dyn Clock>>>,
+    pool: Rc<RefCell<BufferPool>>,
+    emits_delete_id: Rc<Cell<bool>>,
+    validator: Rc<RefCell<Validator>>,
+    outgoing: Rc<RefCell<OutgoingQueue>>,
+    side: Rc<Cell<Option<Side>>>,
+    utf8_policy: Rc<Cell<Utf8Policy>>,
+    zombies: Rc<RefCell<Zombies>>,
+    history: Rc<RefCell<History>>,
+    dispatch_depth: Rc<Cell<usize>>,
+    transaction: Rc<RefCell<Option<Vec<TransactionEntry>>>>,
+    context: Rc<RefCell<Option<Box<dyn Any>>>>,
+    corked: Rc<Cell<usize>>,
+    detached: Rc<Cell<bool>>,
+    metrics: Rc<RefCell<Option<Box<dyn MetricsSink>>>>,
+    tracer: Rc<RefCell<Option<Box<dyn TraceSink>>>>,
+    placeholders: Rc<RefCell<HashMap<ObjectId, PendingQueue>>>,
+    state: Rc<Cell<ConnectionState>>,
+    max_objects: Rc<Cell<Option<usize>>>,
+    error_posted: Rc<Cell<bool>>,
+    disconnect_handler: Rc<RefCell<Option<DisconnectHandler>>>,
 }
 
 impl Bundle {
@@ -40,40 +170,72 @@ impl Bundle {
         self.socket.clone()
     }
 
+    /// Increments and returns next serial.
+    ///
+    /// Serials are shared by all objects of the connection so they are ordered globally per
+    /// connection.
+    pub fn next_serial(&self) -> u32 {
+        let serial = self.serial.get().wrapping_add(1);
+        self.serial.set(serial);
+        serial
+    }
+
+    /// Returns last serial returned by `next_serial`.
+    pub fn last_serial(&self) -> u32 {
+        self.serial.get()
+    }
+
+    /// Returns current time in milliseconds to be used as timestamp in events.
+    ///
+    /// By default `MonotonicClock` is used. Tests may replace it (see `Connection::set_clock`) to
+    /// get deterministic timestamps.
+    pub fn get_time_ms(&self) -> u32 {
+        self.clock.borrow().get_time_ms()
+    }
+
+    /// Increments and returns next serial remembering that it was issued for event `opcode` sent
+    /// to object `object_id`. Serials received later from the peer can be validated with
+    /// `find_serial`.
+    ///
+    /// Serials are remembered only if serial history is enabled (see
+    /// `Connection::set_serial_history`).
+    pub fn next_serial_for(&self, object_id: ObjectId, opcode: u16) -> u32 {
+        let serial = self.next_serial();
+        self.serials.borrow_mut().push(SerialInfo {
+                                           serial,
+                                           object_id,
+                                           opcode,
+                                           timestamp: Instant::now(),
+                                       });
+        serial
+    }
+
+    /// Returns information about event for which `serial` was issued with `next_serial_for`.
+    /// Returns `None` if the serial was not issued recently (or serial history is disabled).
+    pub fn find_serial(&self, serial: u32) -> Option<SerialInfo> {
+        self.serials.borrow_mut().find(serial)
+    }
+
     /// Returns next available client object ID.
     ///
-    /// If no objects are registered this will be `DISPLAY_ID`. Otherwise ID one bigger than the
-    /// biggest ID.
+    /// If no client objects are registered this will be `DISPLAY_ID`. Otherwise ID one bigger than
+    /// the biggest client ID in use (including zombies), so IDs are not reused as long as
+    /// possible. When the end of client range is reached the lowest free ID is reused.
     ///
-    /// TODO: This implementation is naive and invalid. Check how it is implemented in `libwayland`
-    /// and do the same here for compability. `get_next_available_server_object_id` may also need a
-    /// change.
+    /// Returns `SkylaneError::IdsExhausted` if all IDs in client range are in use. The ID never
+    /// leaks into server range.
     ///
     /// TODO: Move `get_next_available_client_object_id` and `get_next_available_server_object_id`
     /// to trait available only in celit or server side respectively.
-    pub fn get_next_available_client_object_id(&self) -> ObjectId {
-        if let Some(max) = self.objects.borrow().keys().max() {
-            if *max >= DISPLAY_ID {
-                max.incremented()
-            } else {
-                DISPLAY_ID
-            }
-        } else {
-            DISPLAY_ID
-        }
+    pub fn get_next_available_client_object_id(&self) -> Result<ObjectId, SkylaneError> {
+        self.get_next_available_id(DISPLAY_ID, CLIENT_END_ID, Side::Client)
     }
 
     /// Returns next available server object ID.
-    pub fn get_next_available_server_object_id(&self) -> ObjectId {
-        if let Some(max) = self.objects.borrow().keys().max() {
-            if *max >= SERVER_START_ID {
-                max.incremented()
-            } else {
-                SERVER_START_ID
-            }
-        } else {
-            SERVER_START_ID
-        }
+    ///
+    /// Works like `get_next_available_client_object_id` but in server range.
+    pub fn get_next_available_server_object_id(&self) -> Result<ObjectId, SkylaneError> {
+        self.get_next_available_id(SERVER_START_ID, SERVER_END_ID, Side::Server)
     }
 
     /// Adds new object. From now client requests or server events to object with given `id` will
@@ -83,27 +245,706 @@ impl Bundle {
     /// Here the only requirement for the object is to implement `Object` trait. In practical use
     /// one will pass implementations of `Interface` traits from protocol definitions wrapped in
     /// `Handler` structure with `Dispatcher` attached as defined in `skylane_protocols` crate.
-    pub fn add_object(&mut self, id: ObjectId, object: Box<Object>) {
-        self.objects.borrow_mut().insert(id, Rc::new(RefCell::new(object)));
+    pub fn add_object(&mut self, id: ObjectId, object: Box<dyn Object>) {
+        let entry = self.capture_object(id);
+        if self.zombies.borrow_mut().remove(&id) {
+            self.validator.borrow_mut().remove_signatures(id);
+        }
+        let previous = {
+            let mut objects = self.objects.borrow_mut();
+            let previous = objects.remove(id);
+            objects.insert(id, Rc::new(RefCell::new(object)));
+            previous
+        };
+        self.record_change(entry, previous);
+    }
+
+    /// Replaces handler of already registered object with given `id`, e.g. to upgrade placeholder
+    /// object to the real implementation after `bind`. Unlike `remove_object` followed by
+    /// `add_object` this keeps interface metadata, bound version and weak references (see
+    /// `get_weak_ref`) of the object and never lets messages to it fail with `WrongObject`.
+    ///
+    /// Handler may replace itself. It keeps running until it returns, but then weak references
+    /// obtained earlier are not valid anymore.
+    ///
+    /// Returns `SkylaneError::WrongObject` if no object is registered with given `id`.
+    pub fn replace_object(&mut self,
+                          id: ObjectId,
+                          object: Box<dyn Object>)
+                          -> Result<(), SkylaneError> {
+        let current = match self.objects.borrow().get(id) {
+            Some(current) => current.clone(),
+            None => return Err(SkylaneError::WrongObject { object_id: id }),
+        };
+
+        let entry = self.capture_object(id);
+        let previous = if current.try_borrow_mut().is_err() {
+            // The handler is being dispatched; it can not be swapped in place.
+            self.objects.borrow_mut().insert(id, Rc::new(RefCell::new(object)));
+            current
+        } else {
+            let previous = std::mem::replace(&mut *current.borrow_mut(), object);
+            Rc::new(RefCell::new(previous))
+        };
+
+        self.record_change(entry, Some(previous));
+        Ok(())
+    }
+
+    /// Registers placeholder for object with given `id` whose handler is not constructed yet
+    /// (e.g. client sent request with `new_id` but the handler needs data not available yet).
+    /// Messages received by the placeholder are queued until the real handler is attached with
+    /// `attach_object`.
+    ///
+    /// File descriptors are queued along with messages only if metadata were registered for the
+    /// object (see `set_interface_meta`).
+    pub fn add_placeholder(&mut self, id: ObjectId) {
+        let queue = PendingQueue::default();
+        self.placeholders.borrow_mut().insert(id, queue.clone());
+        self.add_object(id, Box::new(Placeholder::new(queue)));
+    }
+
+    /// Checks if object with given `id` is a placeholder waiting for its handler.
+    pub fn is_placeholder(&self, id: ObjectId) -> bool {
+        self.placeholders.borrow().contains_key(&id)
+    }
+
+    /// Replaces placeholder registered with `add_placeholder` with the real handler and dispatches
+    /// to it all messages queued so far, in order they were received.
+    ///
+    /// If the handler fails to dispatch a message, the remaining queued messages are dropped and
+    /// the error is returned. Returns `SkylaneError::WrongObject` if no placeholder is registered
+    /// with given `id`.
+    pub fn attach_object(&mut self,
+                         id: ObjectId,
+                         object: Box<dyn Object>)
+                         -> Result<(), SkylaneError> {
+        let queue = match self.placeholders.borrow_mut().remove(&id) {
+            Some(queue) => queue,
+            None => return Err(SkylaneError::WrongObject { object_id: id }),
+        };
+
+        self.replace_object(id, object)?;
+        loop {
+            let pending = match queue.borrow_mut().pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+            self.replay_message(id, pending)?;
+        }
+        Ok(())
+    }
+
+    /// Starts transaction. Objects added until `commit_transaction` can be removed at once with
+    /// `rollback_transaction`, so failure in the middle of creating several objects (e.g. a tree
+    /// of objects for one `bind`) does not leave some of them registered.
+    ///
+    /// Returns error if transaction is already started.
+    pub fn begin_transaction(&self) -> Result<(), SkylaneError> {
+        let mut transaction = self.transaction.borrow_mut();
+        if transaction.is_some() {
+            return Err(SkylaneError::Other("Transaction already started".to_owned()));
+        }
+        *transaction = Some(Vec::new());
+        Ok(())
+    }
+
+    /// Finishes transaction keeping all objects added during it.
+    pub fn commit_transaction(&self) {
+        *self.transaction.borrow_mut() = None;
+    }
+
+    /// Finishes transaction removing all objects added during it. Objects overridden during the
+    /// transaction are restored. Signatures, metadata, versions and zombie state of all added or
+    /// replaced objects are restored as well.
+    ///
+    /// Objects are removed silently: no `wl_display.delete_id` is sent and they do not become
+    /// zombies. They are dropped after the bundle was updated, so their handlers may use it.
+    pub fn rollback_transaction(&self) {
+        let entries = self.transaction.borrow_mut().take().unwrap_or_default();
+        let mut removed = Vec::with_capacity(entries.len());
+        {
+            let mut objects = self.objects.borrow_mut();
+            let mut validator = self.validator.borrow_mut();
+            let mut zombies = self.zombies.borrow_mut();
+            for entry in entries.into_iter().rev() {
+                removed.extend(objects.remove(entry.id));
+                validator.restore(entry.id, entry.snapshot);
+                if entry.was_zombie {
+                    if let Some(forgotten) = zombies.insert(entry.id) {
+                        validator.remove_signatures(forgotten);
+                    }
+                } else {
+                    zombies.remove(&entry.id);
+                }
+                if let Some(previous) = entry.previous {
+                    objects.insert(entry.id, previous);
+                }
+            }
+        }
+        drop(removed);
+    }
+
+    /// Checks if transaction is started.
+    pub fn is_in_transaction(&self) -> bool {
+        self.transaction.borrow().is_some()
+    }
+
+    /// Runs `f` in transaction. Transaction is committed if `f` succeeds and rolled back if it
+    /// fails.
+    pub fn transaction<F, T>(&mut self, f: F) -> Result<T, SkylaneError>
+        where F: FnOnce(&mut Bundle) -> Result<T, SkylaneError>
+    {
+        self.begin_transaction()?;
+        let result = f(self);
+        match result {
+            Ok(_) => self.commit_transaction(),
+            Err(_) => self.rollback_transaction(),
+        }
+        result
+    }
+
+    /// Adds new object created by this side of connection. If side of connection is known (see
+    /// `Connection::set_side`) checks if `id` is in range allocated by this side.
+    pub fn add_local_object(&mut self,
+                            id: ObjectId,
+                            object: Box<dyn Object>)
+                            -> Result<(), SkylaneError> {
+        if let Some(side) = self.side.get() {
+            display::check_id_range(id, side)?;
+        }
+        self.check_object_limit(id)?;
+        self.add_object(id, object);
+        Ok(())
+    }
+
+    /// Adds new object created on request of peer. If side of connection is known (see
+    /// `Connection::set_side`) checks if `id` is in range allocated by peer.
+    pub fn add_remote_object(&mut self,
+                             id: ObjectId,
+                             object: Box<dyn Object>)
+                             -> Result<(), SkylaneError> {
+        if let Some(side) = self.side.get() {
+            display::check_id_range(id, side.peer())?;
+        }
+        self.check_object_limit(id)?;
+        self.add_object(id, object);
+        Ok(())
+    }
+
+    /// Returns side of connection if known.
+    pub fn get_side(&self) -> Option<Side> {
+        self.side.get()
     }
 
     /// Gets next available client object ID and adds new object. Returns ID of newly added object.
-    pub fn add_next_client_object(&mut self, object: Box<Object>) -> ObjectId {
-        let id = self.get_next_available_client_object_id();
+    ///
+    /// Returns `SkylaneError::IdsExhausted` if there is no free ID.
+    pub fn add_next_client_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
+        let id = self.get_next_available_client_object_id()?;
+        self.check_object_limit(id)?;
         self.add_object(id, object);
-        id
+        Ok(id)
     }
 
     /// Gets next available server object ID and adds new object. Returns ID of newly added object.
-    pub fn add_next_server_object(&mut self, object: Box<Object>) -> ObjectId {
-        let id = self.get_next_available_server_object_id();
+    ///
+    /// Returns `SkylaneError::IdsExhausted` if there is no free ID.
+    pub fn add_next_server_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
+        let id = self.get_next_available_server_object_id()?;
+        self.check_object_limit(id)?;
         self.add_object(id, object);
-        id
+        Ok(id)
+    }
+
+    /// Registers signatures of messages sent on behalf of object with given `id` indexed by
+    /// opcode. They are used to check outgoing messages when validation is enabled (see
+    /// `Connection::set_validation_mode`).
+    pub fn set_signatures(&mut self, id: ObjectId, signatures: &'static [&'static str]) {
+        self.validator.borrow_mut().set_signatures(id, signatures);
+    }
+
+    /// Registers signatures of messages received by object with given `id` indexed by opcode.
+    /// They are used to find out how many file descriptors were attached to messages which could
+    /// not be dispatched (e.g. addressed to zombie object), so they can be closed.
+    pub fn set_incoming_signatures(&mut self, id: ObjectId, signatures: &'static [&'static str]) {
+        self.validator.borrow_mut().set_incoming_signatures(id, signatures);
+    }
+
+    /// Registers metadata of interface implemented by object with given `id`. Signatures of sent
+    /// and received messages are taken from it like they were registered with `set_signatures` and
+    /// `set_incoming_signatures`. If side of connection is not known it is assumed to be server.
+    ///
+    /// Metadata are forgotten when the object is removed. Interface is also used for resolving
+    /// message names in diagnostics of this connection, before interfaces registered with
+    /// `register_interface`.
+    pub fn set_interface_meta(&mut self, id: ObjectId, meta: &'static InterfaceMeta) {
+        let side = self.side.get().unwrap_or(Side::Server);
+        self.validator.borrow_mut().set_meta(id, meta, side);
+    }
+
+    /// Returns metadata of interface implemented by object with given `id` if registered.
+    pub fn get_interface_meta(&self, id: ObjectId) -> Option<&'static InterfaceMeta> {
+        self.validator.borrow().get_meta(id)
+    }
+
+    /// Sets version of interface bound for object with given `id`, e.g. version requested by
+    /// client in `wl_registry.bind` (see `GlobalRegistry`).
+    ///
+    /// Version is forgotten when the object is removed.
+    pub fn set_object_version(&mut self, id: ObjectId, version: u32) {
+        self.validator.borrow_mut().set_version(id, version);
+    }
+
+    /// Returns version of interface bound for object with given `id` if set.
+    pub fn get_object_version(&self, id: ObjectId) -> Option<u32> {
+        self.validator.borrow().get_version(id)
+    }
+
+    /// Checks if message with given opcode sent on behalf of object with given `id` is available
+    /// in version of interface bound for the object. Returns `true` if version or metadata of the
+    /// object are not known.
+    pub fn is_supported(&self, id: ObjectId, opcode: u16) -> bool {
+        let side = self.side.get().unwrap_or(Side::Server);
+        self.validator.borrow().check_version(id, opcode, side).is_none()
+    }
+
+    /// Returns listing of registered objects sorted by ID, one per line, with interface name,
+    /// version and reference count (if known, see `set_interface_meta`) followed by number of
+    /// live objects per interface.
+    ///
+    /// Meant for tracking object leaks, e.g. by dumping objects periodically.
+    pub fn dump_objects(&self) -> String {
+        let mut dump = String::new();
+        for info in self.get_object_infos() {
+            dump.push_str(&format!("{}\n", info));
+        }
+        for (interface, count) in self.count_objects_by_interface() {
+            dump.push_str(&format!("{}: {}\n", interface.unwrap_or("<unknown>"), count));
+        }
+        dump
+    }
+
+    /// Returns number of live objects per interface. Objects without registered metadata are
+    /// counted under `None`.
+    pub fn count_objects_by_interface(&self) -> BTreeMap<Option<&'static str>, usize> {
+        let mut counts = BTreeMap::new();
+        for info in self.get_object_infos() {
+            *counts.entry(info.interface).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Sets context of the connection available to all handlers (see `with_context`). Replaces
+    /// previous context.
+    ///
+    /// Useful for compositor state which would otherwise have to be cloned into every object when
+    /// it is constructed.
+    pub fn set_context<T: Any>(&mut self, context: T) {
+        *self.context.borrow_mut() = Some(Box::new(context));
+    }
+
+    /// Removes context of the connection.
+    pub fn clear_context(&mut self) {
+        *self.context.borrow_mut() = None;
+    }
+
+    /// Calls `f` with context of the connection. Returns error if no context of type `T` was set
+    /// or if called from within another `with_context` call.
+    pub fn with_context<T, F, R>(&self, f: F) -> Result<R, SkylaneError>
+        where T: Any,
+              F: FnOnce(&mut T) -> R
+    {
+        let mut context = self.context
+            .try_borrow_mut()
+            .map_err(|_| {
+                         SkylaneError::Reentrancy {
+                             object_id: None,
+                             depth: self.dispatch_depth.get(),
+                         }
+                     })?;
+        match context.as_mut().and_then(|context| context.downcast_mut::<T>()) {
+            Some(context) => Ok(f(context)),
+            None => Err(SkylaneError::Other("No context of requested type".to_owned())),
+        }
+    }
+
+    /// Calls `f` with ID of every registered object implementing interface with given name, in
+    /// order of IDs. Only objects with registered metadata are taken into account (see
+    /// `set_interface_meta`). Stops on first error returned by `f`.
+    ///
+    /// Allows broadcasting events (e.g. `wl_seat.capabilities` to every bound seat) without
+    /// keeping external lists of object IDs.
+    pub fn for_each_object<F>(&self, interface: &str, mut f: F) -> Result<(), SkylaneError>
+        where F: FnMut(ObjectId) -> Result<(), SkylaneError>
+    {
+        let ids: Vec<ObjectId> = self.objects
+            .borrow()
+            .get_ids()
+            .into_iter()
+            .filter(|id| self.get_interface_meta(*id).map_or(false, |meta| meta.name == interface))
+            .collect();
+        for id in ids {
+            f(id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns weak reference to object with given `id` if it is registered. Always returns `None`
+    /// for `NULL_ID`.
+    ///
+    /// See `WeakObjectRef`.
+    pub fn get_weak_ref(&self, id: ObjectId) -> Option<WeakObjectRef> {
+        if id.is_null() {
+            return None;
+        }
+        self.objects
+            .borrow()
+            .get(id)
+            .map(|object| WeakObjectRef::new(id, object, &self.objects))
+    }
+
+    /// Returns weak reference to object with given `id` read as nullable argument (see
+    /// `Message::next_nullable_object`). Returns `Ok(None)` for null argument and
+    /// `SkylaneError::WrongObject` if no object is registered with given `id`.
+    pub fn get_nullable_weak_ref(&self,
+                                 id: Option<ObjectId>)
+                                 -> Result<Option<WeakObjectRef>, SkylaneError> {
+        match id {
+            Some(id) => {
+                self.get_weak_ref(id)
+                    .map(Some)
+                    .ok_or(SkylaneError::WrongObject { object_id: id })
+            }
+            None => Ok(None),
+        }
     }
 
     /// Removes object with given `id`.
+    ///
+    /// On server side (see `Connection::new_server`) if the object was created by client
+    /// `wl_display.delete_id` event is sent so client can reuse the ID.
+    ///
+    /// If the object was created by this side of connection (or side is not known) it becomes a
+    /// zombie: peer may still have messages to it in flight, so messages addressed to it are
+    /// silently dropped until the removal is confirmed (see `confirm_delete`) or the ID is reused.
+    /// Removal of objects created by server is never confirmed, so only a limited number of the
+    /// most recent such zombies is kept.
     pub fn remove_object(&mut self, id: ObjectId) {
-        self.objects.borrow_mut().remove(&id);
+        let removed = self.objects.borrow_mut().remove(id);
+        self.placeholders.borrow_mut().remove(&id);
+        let is_local = match self.side.get() {
+            Some(side) => id.is_in_range_of(side),
+            None => true,
+        };
+
+        if removed.is_some() && is_local && id != DISPLAY_ID {
+            // Incoming signatures are still needed to drop messages to the zombie.
+            let forgotten = self.zombies.borrow_mut().insert(id);
+            if let Some(forgotten) = forgotten {
+                self.validator.borrow_mut().remove_signatures(forgotten);
+            }
+            self.validator.borrow_mut().remove_outgoing_signatures(id);
+        } else {
+            self.validator.borrow_mut().remove_signatures(id);
+        }
+
+        if removed.is_some() && self.emits_delete_id.get() && id != DISPLAY_ID &&
+           id < SERVER_START_ID {
+            // Failure to write means the client is disconnecting; nothing to do about it here.
+            let _ = self.send_delete_id(id);
+        }
+    }
+
+    /// Handles confirmation of object removal by peer (`wl_display.delete_id`). Removes the object
+    /// if it is still registered and forgets the zombie, so the ID can be reused.
+    pub fn confirm_delete(&mut self, id: ObjectId) {
+        self.objects.borrow_mut().remove(id);
+        self.validator.borrow_mut().remove_signatures(id);
+        self.zombies.borrow_mut().remove(&id);
+    }
+
+    /// Checks if object with given ID was removed but peer did not confirm the removal yet.
+    pub fn is_zombie(&self, id: ObjectId) -> bool {
+        self.zombies.borrow().contains(&id)
+    }
+
+    /// Sends `wl_callback.done` event with given `data` (serial or timestamp) and removes the
+    /// callback object. `wl_display.delete_id` is sent even if the callback was not registered.
+    ///
+    /// This method is meant to be used on server side.
+    pub fn fire_callback(&mut self, id: ObjectId, data: u32) -> Result<(), SkylaneError> {
+        self.send_marshalled(id, display::CALLBACK_DONE_OPCODE, |marshaller| {
+            marshaller.put_uint(data);
+        })?;
+        self.objects.borrow_mut().remove(id);
+        self.validator.borrow_mut().remove_signatures(id);
+        self.send_delete_id(id)
+    }
+
+    /// Sends `wl_display.delete_id` event informing client that it can reuse object ID.
+    ///
+    /// This method is meant to be used on server side.
+    pub fn send_delete_id(&self, id: ObjectId) -> Result<(), SkylaneError> {
+        self.send_marshalled(DISPLAY_ID, display::DELETE_ID_OPCODE, |marshaller| {
+            marshaller.put_uint(id.get_value());
+        })
+    }
+
+    /// Sends `wl_display.error` event informing client that request on object `object_id` caused
+    /// fatal error with given `code`. Codes defined by core protocol can be obtained from
+    /// `DisplayError::get_code`.
+    ///
+    /// This method is meant to be used on server side.
+    ///
+    /// The connection becomes `Erroring`: no more messages are dispatched nor sent and disconnect
+    /// handler is called.
+    pub fn post_error(&self,
+                      object_id: ObjectId,
+                      code: u32,
+                      message: &str)
+                      -> Result<(), SkylaneError> {
+        self.check_state()?;
+        let result = self.send_marshalled(DISPLAY_ID, display::ERROR_OPCODE, |marshaller| {
+            marshaller.put_object(object_id);
+            marshaller.put_uint(code);
+            marshaller.put_string(message);
+        });
+        self.error_posted.set(true);
+        self.notify_disconnect(DisconnectReason::ProtocolError {
+                                   object_id,
+                                   code,
+                                   message: message.to_owned(),
+                               });
+        result
+    }
+
+    /// Queues marshalled message (`bytes`) along with file descriptors `fds`. Queued messages are
+    /// written in order when the queue is flushed (see `flush`) or before next message is sent
+    /// immediately.
+    ///
+    /// `Connection` flushes the queue after dispatching received messages, so handlers can queue
+    /// events while iterating over their state.
+    pub fn queue_event(&self, bytes: &[u8], fds: &[RawFd]) {
+        self.queue_event_with_priority(bytes, fds, Priority::Normal);
+    }
+
+    /// Queues message like `queue_event` in lane of given priority. On flush high-priority
+    /// messages (e.g. input events) are written before normal ones queued earlier, so they do not
+    /// wait behind bulk traffic. Messages are never split.
+    ///
+    /// Queueing can not fail, so messages not available in version bound for the object are dropped
+    /// regardless of `VersionCheck` mode, as are messages queued after the connection ended or
+    /// fatal protocol error was posted (see `Connection::state`).
+    pub fn queue_event_with_priority(&self, bytes: &[u8], fds: &[RawFd], priority: Priority) {
+        if self.check_state().is_err() || self.check_versions(bytes).is_err() {
+            return;
+        }
+        self.record_outgoing(bytes);
+        self.outgoing.borrow_mut().push_with_priority(bytes, fds, priority);
+    }
+
+    /// Writes marshalled message (`bytes`) along with file descriptors `fds` immediately. Queued
+    /// messages are written first to keep order.
+    ///
+    /// If socket buffer is full and queued messages could not be written entirely, the message is
+    /// queued after them. Returns `SkylaneError::Closed` if the connection ended or fatal protocol
+    /// error was posted.
+    pub fn send_event(&self, bytes: &[u8], fds: &[RawFd]) -> Result<(), SkylaneError> {
+        self.check_state()?;
+        if let Err(err) = self.check_versions(bytes) {
+            return self.handle_unsupported(err);
+        }
+        self.record_outgoing(bytes);
+        self.flush()?;
+        self.write_or_queue(bytes, fds)
+    }
+
+    /// Validates message composed by `marshaller` and queues it like `queue_event`. Unlike
+    /// queueing bytes returned by `Marshaller::finalize`, this takes ownership of descriptors
+    /// appended with `Marshaller::put_owned_fd`, so they are closed only after they were passed.
+    pub fn queue_message(&self, marshaller: Marshaller) {
+        self.queue_composed(marshaller);
+    }
+
+    /// Marshals message with given `opcode` for object `object_id` and queues it like
+    /// `queue_event`. `compose` appends arguments; header with size of the message is filled in
+    /// automatically. This lets handlers respond without accessing the socket.
+    ///
+    /// Messages are validated the same way as the ones sent by `Bundle` itself (see
+    /// `Connection::set_validation_mode`).
+    pub fn send<F>(&self, object_id: ObjectId, opcode: u16, compose: F)
+        where F: FnOnce(&mut Marshaller)
+    {
+        let mut marshaller = self.compose_message(object_id, opcode);
+        compose(&mut marshaller);
+        self.queue_composed(marshaller);
+    }
+
+    /// Like `send` but issues new serial for the message (see `next_serial_for`), passes it to
+    /// `compose` and returns it.
+    pub fn send_with_serial<F>(&self, object_id: ObjectId, opcode: u16, compose: F) -> u32
+        where F: FnOnce(&mut Marshaller, u32)
+    {
+        let serial = self.next_serial_for(object_id, opcode);
+        let mut marshaller = self.compose_message(object_id, opcode);
+        compose(&mut marshaller, serial);
+        self.queue_composed(marshaller);
+        serial
+    }
+
+    /// Writes all queued messages. Data which could not be written because socket buffer is full
+    /// stay queued. Nothing is written while outgoing messages are corked (see `cork`).
+    ///
+    /// Messages carrying many file descriptors may need more than one `sendmsg` call.
+    pub fn flush(&self) -> Result<(), SkylaneError> {
+        if self.is_corked() || self.is_detached() {
+            return Ok(());
+        }
+
+        while !self.outgoing.borrow().is_empty() {
+            let result = {
+                let outgoing = self.outgoing.borrow();
+                let (slices, fds) = outgoing.get_data();
+                let len = slices.iter().map(|slice| slice.len()).sum::<usize>();
+                self.socket.write_vectored(&slices, &fds).map(|written| (written, len))
+            };
+            match result {
+                Ok((written, len)) => {
+                    self.outgoing.borrow_mut().consume(written);
+                    if written < len {
+                        break;
+                    }
+                }
+                Err(ref err) if err.is_would_block() => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Corks outgoing messages: until `uncork` is called all sent messages are queued, even by
+    /// `send_event` or `flush`. `uncork` then writes them together, so a group of events (e.g.
+    /// `enter`, `configure` and `done`) is batched into as few `sendmsg` calls as possible.
+    ///
+    /// This is only an optimization. Delivery is not atomic: if the socket buffer fills up or the
+    /// messages carry many file descriptors, they are split between several writes and the peer
+    /// may read part of the group before the rest arrives.
+    ///
+    /// Calls can be nested; messages are written when the outermost `uncork` is called.
+    pub fn cork(&self) {
+        self.corked.set(self.corked.get() + 1);
+    }
+
+    /// Reverts one call to `cork`. Writes queued messages if outgoing messages are not corked
+    /// anymore.
+    pub fn uncork(&self) -> Result<(), SkylaneError> {
+        let corked = self.corked.get();
+        if corked > 0 {
+            self.corked.set(corked - 1);
+        }
+        self.flush()
+    }
+
+    /// Checks if outgoing messages are corked.
+    pub fn is_corked(&self) -> bool {
+        self.corked.get() > 0
+    }
+
+    /// Checks if there are queued messages not written yet.
+    pub fn has_queued(&self) -> bool {
+        !self.outgoing.borrow().is_empty()
+    }
+
+    /// Checks if I/O is done by embedder instead of connection (see
+    /// `Connection::set_detached_io`). In this mode all sent messages are queued until taken with
+    /// `drain_output`.
+    pub fn is_detached(&self) -> bool {
+        self.detached.get()
+    }
+
+    /// Takes all queued messages along with their file descriptors. Messages are returned in the
+    /// order they should be written.
+    ///
+    /// The second returned vector contains all descriptors to be passed with the messages. They
+    /// are not duplicated: as with messages written to socket, they stay owned by whoever sent
+    /// them, except for the ones appended with `Marshaller::put_owned_fd`, which are returned
+    /// also in the third vector. Caller should keep these open until the messages were written and
+    /// drop them afterwards.
+    pub fn drain_output(&self) -> (Vec<u8>, Vec<RawFd>, Vec<OwnedFd>) {
+        self.outgoing.borrow_mut().drain()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reference to `Bundle` not keeping alive its socket, objects nor any other state, so it can be
+/// stored by objects shared between connections without keeping dropped connections alive or
+/// creating reference cycles.
+///
+/// See `BundleInternal::downgrade`.
+pub struct WeakBundle {
+    socket: WeakSocket,
+    objects: Weak<RefCell<Box<dyn ObjectStore>>>,
+    serial: Weak<Cell<u32>>,
+    serials: Weak<RefCell<SerialHistory>>,
+    clock: Weak<RefCell<Box<dyn Clock>>>,
+    pool: Weak<RefCell<BufferPool>>,
+    emits_delete_id: Weak<Cell<bool>>,
+    validator: Weak<RefCell<Validator>>,
+    outgoing: Weak<RefCell<OutgoingQueue>>,
+    side: Weak<Cell<Option<Side>>>,
+    utf8_policy: Weak<Cell<Utf8Policy>>,
+    zombies: Weak<RefCell<Zombies>>,
+    history: Weak<RefCell<History>>,
+    dispatch_depth: Weak<Cell<usize>>,
+    transaction: Weak<RefCell<Option<Vec<TransactionEntry>>>>,
+    context: Weak<RefCell<Option<Box<dyn Any>>>>,
+    corked: Weak<Cell<usize>>,
+    detached: Weak<Cell<bool>>,
+    metrics: Weak<RefCell<Option<Box<dyn MetricsSink>>>>,
+    tracer: Weak<RefCell<Option<Box<dyn TraceSink>>>>,
+    placeholders: Weak<RefCell<HashMap<ObjectId, PendingQueue>>>,
+    state: Weak<Cell<ConnectionState>>,
+    max_objects: Weak<Cell<Option<usize>>>,
+    error_posted: Weak<Cell<bool>>,
+    disconnect_handler: Weak<RefCell<Option<DisconnectHandler>>>,
+}
+
+impl WeakBundle {
+    /// Returns the `Bundle` if its connection still exists.
+    pub fn upgrade(&self) -> Option<Bundle> {
+        Some(Bundle {
+                 socket: self.socket.upgrade()?,
+                 objects: self.objects.upgrade()?,
+                 serial: self.serial.upgrade()?,
+                 serials: self.serials.upgrade()?,
+                 clock: self.clock.upgrade()?,
+                 pool: self.pool.upgrade()?,
+                 emits_delete_id: self.emits_delete_id.upgrade()?,
+                 validator: self.validator.upgrade()?,
+                 outgoing: self.outgoing.upgrade()?,
+                 side: self.side.upgrade()?,
+                 utf8_policy: self.utf8_policy.upgrade()?,
+                 zombies: self.zombies.upgrade()?,
+                 history: self.history.upgrade()?,
+                 dispatch_depth: self.dispatch_depth.upgrade()?,
+                 transaction: self.transaction.upgrade()?,
+                 context: self.context.upgrade()?,
+                 corked: self.corked.upgrade()?,
+                 detached: self.detached.upgrade()?,
+                 metrics: self.metrics.upgrade()?,
+                 tracer: self.tracer.upgrade()?,
+                 placeholders: self.placeholders.upgrade()?,
+                 state: self.state.upgrade()?,
+                 max_objects: self.max_objects.upgrade()?,
+                 error_posted: self.error_posted.upgrade()?,
+                 disconnect_handler: self.disconnect_handler.upgrade()?,
+             })
     }
 }
 
@@ -120,15 +961,146 @@ pub trait BundleInternal {
     /// helper structure and must be shared between `Connection` and `Controller`.
     fn duplicate(&self) -> Self;
 
-    /// Returns object of given ID.
-    fn get_handler(&self, object_id: ObjectId) -> Result<Rc<RefCell<Box<Object>>>, SkylaneError>;
+    /// Constructs new `Bundle` for new connection socket. Objects and serials are not carried over,
+    /// only settings. This `Bundle` and all its clones are left without objects and in `Closed`
+    /// state.
+    fn renew(&self, socket: Socket) -> Self;
+
+    /// Creates reference to the `Bundle` which does not keep it alive.
+    fn downgrade(&self) -> WeakBundle;
+
+    /// Returns object of given ID. Registered objects are not searched for `NULL_ID`.
+    fn get_handler(&self, object_id: ObjectId) -> Result<ObjectRef, SkylaneError>;
+
+    /// Takes buffer for marshalling outgoing message from the pool.
+    fn acquire_buffer(&self) -> Vec<u8>;
+
+    /// Returns buffer to the pool after the message was sent.
+    fn release_buffer(&self, buffer: Vec<u8>);
+
+    /// Composes message using `compose` and sends it.
+    fn send_marshalled<F>(&self,
+                          object_id: ObjectId,
+                          opcode: u16,
+                          compose: F)
+                          -> Result<(), SkylaneError>
+        where F: FnOnce(&mut Marshaller);
+
+    /// Sends message composed earlier. Validates it if validation is enabled and argument types
+    /// were recorded.
+    fn send_composed(&self, marshaller: Marshaller) -> Result<(), SkylaneError>;
+
+    /// Enables or disables emission of `wl_display.delete_id` on object removal.
+    fn set_emits_delete_id(&self, emits_delete_id: bool);
+
+    /// Sets validation mode for outgoing messages.
+    fn set_validation_mode(&self, mode: ValidationMode);
+
+    /// Sets what to do with messages not available in version bound for the object.
+    fn set_version_check(&self, version_check: VersionCheck);
+
+    /// Sets last serial. Next call to `next_serial` will return `serial + 1`.
+    fn set_last_serial(&self, serial: u32);
+
+    /// Sets source of timestamps.
+    fn set_clock(&self, clock: Box<dyn Clock>);
+
+    /// Sets number and maximal age of serials remembered by `next_serial_for`.
+    fn set_serial_history(&self, capacity: usize, max_age: Option<Duration>);
+
+    /// Returns metadata of interface with given name registered with `set_interface_meta`.
+    fn find_interface_meta(&self, name: &str) -> Option<&'static InterfaceMeta>;
+
+    /// Returns number of file descriptors carried by message with given opcode received by given
+    /// object or `None` if it is not known.
+    fn get_incoming_fd_count(&self, object_id: ObjectId, opcode: u16) -> Option<usize>;
+
+    /// Sets side of connection.
+    fn set_side(&self, side: Option<Side>);
+
+    /// Sets handling of invalid UTF-8 in string arguments of received messages.
+    fn set_utf8_policy(&self, policy: Utf8Policy);
+
+    /// Returns handling of invalid UTF-8 in string arguments of received messages.
+    fn get_utf8_policy(&self) -> Utf8Policy;
+
+    /// Sets number of recent messages kept for introspection.
+    fn set_history_size(&self, size: usize);
+
+    /// Enables or disables detached I/O mode.
+    fn set_detached(&self, detached: bool);
+
+    /// Sets sink for per-message metrics.
+    fn set_metrics_sink(&self, sink: Option<Box<dyn MetricsSink>>);
+
+    /// Sets sink for traces of messages.
+    fn set_trace_sink(&self, sink: Option<Box<dyn TraceSink>>);
+
+    /// Adds message to history of recent messages and reports it to metrics and trace sinks.
+    /// `args` contains raw message without header.
+    fn record_message(&self, direction: Direction, header: Header, args: &[u8]);
+
+    /// Returns snapshot of object table and recent messages.
+    fn introspect(&self) -> Introspection;
+
+    /// Returns number of nested dispatch loops currently running on this connection.
+    fn get_dispatch_depth(&self) -> usize;
+
+    /// Marks start of nested dispatch loop. The loop is considered running until the returned
+    /// guard is dropped.
+    fn enter_dispatch(&self) -> DispatchGuard;
+
+    /// Replaces storage of objects moving all registered objects to the new one.
+    fn set_object_store(&self, store: Box<dyn ObjectStore>);
+
+    /// Returns state of the connection.
+    fn get_state(&self) -> ConnectionState;
+
+    /// Sets state of the connection.
+    fn set_state(&self, state: ConnectionState);
+
+    /// Sets maximal number of objects added with checked methods (e.g. `add_remote_object`).
+    fn set_max_objects(&self, max_objects: Option<usize>);
+
+    /// Checks if fatal error was posted to the peer with `post_error`.
+    fn has_posted_error(&self) -> bool;
+
+    /// Sets handler called when the connection ends.
+    fn set_disconnect_handler(&self, handler: Option<DisconnectHandler>);
+
+    /// Updates state of the connection and calls disconnect handler unless connection already
+    /// ended.
+    fn notify_disconnect(&self, reason: DisconnectReason);
 }
 
 impl BundleInternal for Bundle {
     fn new(socket: Socket) -> Self {
         Bundle {
-            socket: socket,
-            objects: Rc::new(RefCell::new(HashMap::new())),
+            socket,
+            objects: Rc::new(RefCell::new(Box::new(ObjectMap::new()))),
+            serial: Rc::new(Cell::new(0)),
+            serials: Rc::new(RefCell::new(SerialHistory::new(0, None))),
+            clock: Rc::new(RefCell::new(Box::new(MonotonicClock))),
+            pool: Rc::new(RefCell::new(BufferPool::new())),
+            emits_delete_id: Rc::new(Cell::new(false)),
+            validator: Rc::new(RefCell::new(Validator::new())),
+            outgoing: Rc::new(RefCell::new(OutgoingQueue::new())),
+            side: Rc::new(Cell::new(None)),
+            utf8_policy: Rc::new(Cell::new(Utf8Policy::default())),
+            zombies: Rc::new(RefCell::new(Zombies::default())),
+            history: Rc::new(RefCell::new(History::new(DEFAULT_HISTORY_SIZE))),
+            dispatch_depth: Rc::new(Cell::new(0)),
+            transaction: Rc::new(RefCell::new(None)),
+            context: Rc::new(RefCell::new(None)),
+            corked: Rc::new(Cell::new(0)),
+            detached: Rc::new(Cell::new(false)),
+            metrics: Rc::new(RefCell::new(None)),
+            tracer: Rc::new(RefCell::new(None)),
+            placeholders: Rc::new(RefCell::new(HashMap::new())),
+            state: Rc::new(Cell::new(ConnectionState::Ready)),
+            max_objects: Rc::new(Cell::new(None)),
+            error_posted: Rc::new(Cell::new(false)),
+            disconnect_handler: Rc::new(RefCell::new(None)),
         }
     }
 
@@ -136,14 +1108,606 @@ impl BundleInternal for Bundle {
         Bundle {
             socket: self.socket.clone(),
             objects: self.objects.clone(),
+            serial: self.serial.clone(),
+            serials: self.serials.clone(),
+            clock: self.clock.clone(),
+            pool: self.pool.clone(),
+            emits_delete_id: self.emits_delete_id.clone(),
+            validator: self.validator.clone(),
+            outgoing: self.outgoing.clone(),
+            side: self.side.clone(),
+            utf8_policy: self.utf8_policy.clone(),
+            zombies: self.zombies.clone(),
+            history: self.history.clone(),
+            dispatch_depth: self.dispatch_depth.clone(),
+            transaction: self.transaction.clone(),
+            context: self.context.clone(),
+            corked: self.corked.clone(),
+            detached: self.detached.clone(),
+            metrics: self.metrics.clone(),
+            tracer: self.tracer.clone(),
+            placeholders: self.placeholders.clone(),
+            state: self.state.clone(),
+            max_objects: self.max_objects.clone(),
+            error_posted: self.error_posted.clone(),
+            disconnect_handler: self.disconnect_handler.clone(),
         }
     }
 
-    fn get_handler(&self, object_id: ObjectId) -> Result<Rc<RefCell<Box<Object>>>, SkylaneError> {
-        if let Some(object) = self.objects.borrow().get(&object_id) {
+    fn downgrade(&self) -> WeakBundle {
+        WeakBundle {
+            socket: self.socket.downgrade(),
+            objects: Rc::downgrade(&self.objects),
+            serial: Rc::downgrade(&self.serial),
+            serials: Rc::downgrade(&self.serials),
+            clock: Rc::downgrade(&self.clock),
+            pool: Rc::downgrade(&self.pool),
+            emits_delete_id: Rc::downgrade(&self.emits_delete_id),
+            validator: Rc::downgrade(&self.validator),
+            outgoing: Rc::downgrade(&self.outgoing),
+            side: Rc::downgrade(&self.side),
+            utf8_policy: Rc::downgrade(&self.utf8_policy),
+            zombies: Rc::downgrade(&self.zombies),
+            history: Rc::downgrade(&self.history),
+            dispatch_depth: Rc::downgrade(&self.dispatch_depth),
+            transaction: Rc::downgrade(&self.transaction),
+            context: Rc::downgrade(&self.context),
+            corked: Rc::downgrade(&self.corked),
+            detached: Rc::downgrade(&self.detached),
+            metrics: Rc::downgrade(&self.metrics),
+            tracer: Rc::downgrade(&self.tracer),
+            placeholders: Rc::downgrade(&self.placeholders),
+            state: Rc::downgrade(&self.state),
+            max_objects: Rc::downgrade(&self.max_objects),
+            error_posted: Rc::downgrade(&self.error_posted),
+            disconnect_handler: Rc::downgrade(&self.disconnect_handler),
+        }
+    }
+
+    fn renew(&self, socket: Socket) -> Self {
+        let mut bundle = Bundle::new(socket);
+
+        // Keep using store provided by embedder, but without old objects. They are dropped only
+        // after the store was released, as their handlers may still use the bundle.
+        let mut store = std::mem::replace(&mut *self.objects.borrow_mut(),
+                                          Box::new(ObjectMap::new()));
+        let old_objects = store.get_ids()
+            .into_iter()
+            .filter_map(|id| store.remove(id))
+            .collect::<Vec<_>>();
+        drop(old_objects);
+
+        // Move shared state instead of sharing it, so old handles can not reach new connection.
+        bundle.objects = Rc::new(RefCell::new(store));
+        bundle.context = Rc::new(RefCell::new(self.context.borrow_mut().take()));
+        bundle.metrics = Rc::new(RefCell::new(self.metrics.borrow_mut().take()));
+        bundle.tracer = Rc::new(RefCell::new(self.tracer.borrow_mut().take()));
+        bundle.disconnect_handler =
+            Rc::new(RefCell::new(self.disconnect_handler.borrow_mut().take()));
+        bundle.clock = Rc::new(RefCell::new(std::mem::replace(&mut *self.clock.borrow_mut(),
+                                                              Box::new(MonotonicClock))));
+        bundle.state.set(self.state.get());
+        bundle.error_posted.set(self.error_posted.get());
+        self.state.set(ConnectionState::Closed);
+        bundle.set_emits_delete_id(self.emits_delete_id.get());
+        bundle.set_validation_mode(self.validator.borrow().get_mode());
+        bundle.set_version_check(self.validator.borrow().get_version_check());
+        bundle.set_side(self.side.get());
+        bundle.set_utf8_policy(self.utf8_policy.get());
+        bundle.set_history_size(self.history.borrow().get_capacity());
+        {
+            let serials = self.serials.borrow();
+            bundle.set_serial_history(serials.get_capacity(), serials.get_max_age());
+        }
+        bundle.set_detached(self.detached.get());
+        bundle.set_max_objects(self.max_objects.get());
+        bundle
+    }
+
+    fn get_handler(&self, object_id: ObjectId) -> Result<ObjectRef, SkylaneError> {
+        if object_id.is_null() {
+            return Err(SkylaneError::WrongObject { object_id });
+        }
+        if let Some(object) = self.objects.borrow().get(object_id) {
             Ok(object.clone())
         } else {
-            Err(SkylaneError::WrongObject { object_id: object_id })
+            Err(SkylaneError::WrongObject { object_id })
+        }
+    }
+
+    fn acquire_buffer(&self) -> Vec<u8> {
+        self.pool.borrow_mut().acquire()
+    }
+
+    fn release_buffer(&self, buffer: Vec<u8>) {
+        self.pool.borrow_mut().release(buffer);
+    }
+
+    fn send_marshalled<F>(&self,
+                          object_id: ObjectId,
+                          opcode: u16,
+                          compose: F)
+                          -> Result<(), SkylaneError>
+        where F: FnOnce(&mut Marshaller)
+    {
+        let mut marshaller = self.compose_message(object_id, opcode);
+        compose(&mut marshaller);
+        self.send_composed(marshaller)
+    }
+
+    fn send_composed(&self, mut marshaller: Marshaller) -> Result<(), SkylaneError> {
+        let checked = self.check_state().and_then(|_| marshaller.finalize().map(|_| ()));
+        if let Err(err) = checked {
+            self.release_buffer(marshaller.into_buffer());
+            return Err(err);
+        }
+
+        let checked = marshaller.finalize().and_then(|(bytes, _)| self.check_versions(bytes));
+        if let Err(err) = checked {
+            self.release_buffer(marshaller.into_buffer());
+            return self.handle_unsupported(err);
+        }
+
+        let mode = self.validator.borrow().get_mode();
+        if mode != ValidationMode::Off && marshaller.get_signature().is_some() {
+            self.validate(&mut marshaller, mode);
+        }
+        let result = self.flush().and_then(|_| {
+            let (bytes, fds) = marshaller.finalize()?;
+            self.record_outgoing(bytes);
+            self.write_or_queue(bytes, fds)
+        });
+        self.outgoing.borrow_mut().adopt(marshaller.take_owned_fds());
+        self.release_buffer(marshaller.into_buffer());
+        result
+    }
+
+    fn set_emits_delete_id(&self, emits_delete_id: bool) {
+        self.emits_delete_id.set(emits_delete_id);
+    }
+
+    fn set_validation_mode(&self, mode: ValidationMode) {
+        self.validator.borrow_mut().set_mode(mode);
+    }
+
+    fn set_version_check(&self, version_check: VersionCheck) {
+        self.validator.borrow_mut().set_version_check(version_check);
+    }
+
+    fn set_last_serial(&self, serial: u32) {
+        self.serial.set(serial);
+    }
+
+    fn set_clock(&self, clock: Box<dyn Clock>) {
+        *self.clock.borrow_mut() = clock;
+    }
+
+    fn set_serial_history(&self, capacity: usize, max_age: Option<Duration>) {
+        *self.serials.borrow_mut() = SerialHistory::new(capacity, max_age);
+    }
+
+    fn find_interface_meta(&self, name: &str) -> Option<&'static InterfaceMeta> {
+        self.validator.borrow().find_interface(name)
+    }
+
+    fn get_incoming_fd_count(&self, object_id: ObjectId, opcode: u16) -> Option<usize> {
+        self.validator.borrow().get_incoming_fd_count(object_id, opcode)
+    }
+
+    fn set_side(&self, side: Option<Side>) {
+        self.side.set(side);
+    }
+
+    fn set_utf8_policy(&self, policy: Utf8Policy) {
+        self.utf8_policy.set(policy);
+    }
+
+    fn get_utf8_policy(&self) -> Utf8Policy {
+        self.utf8_policy.get()
+    }
+
+    fn set_history_size(&self, size: usize) {
+        self.history.borrow_mut().set_capacity(size);
+    }
+
+    fn set_detached(&self, detached: bool) {
+        self.detached.set(detached);
+    }
+
+    fn set_metrics_sink(&self, sink: Option<Box<dyn MetricsSink>>) {
+        *self.metrics.borrow_mut() = sink;
+    }
+
+    fn set_trace_sink(&self, sink: Option<Box<dyn TraceSink>>) {
+        *self.tracer.borrow_mut() = sink;
+    }
+
+    fn record_message(&self, direction: Direction, header: Header, args: &[u8]) {
+        let meta = self.get_interface_meta(ObjectId::new(header.object_id));
+        if let Some(ref mut sink) = *self.metrics.borrow_mut() {
+            sink.record_message(meta.map(|meta| meta.name),
+                                header.opcode,
+                                header.size as usize,
+                                direction);
+        }
+
+        let side = self.side.get().unwrap_or(Side::Server);
+        let message = meta.and_then(|meta| {
+            let messages = match direction {
+                Direction::Incoming => meta.get_incoming(side),
+                Direction::Outgoing => meta.get_outgoing(side),
+            };
+            messages.get(header.opcode as usize)
+        });
+        let name = message.map(|message| message.name);
+
+        if let Some(ref mut sink) = *self.tracer.borrow_mut() {
+            let args = message.and_then(|message| {
+                trace::decode_args(header, args, message.signature).ok()
+            });
+            sink.trace(&TraceRecord {
+                           direction,
+                           header,
+                           interface: meta.map(|meta| meta.name),
+                           name,
+                           args,
+                       });
+        }
+
+        if !self.history.borrow().is_enabled() {
+            return;
+        }
+
+        self.history.borrow_mut().push(MessageInfo {
+                                           direction,
+                                           header,
+                                           interface: meta.map(|meta| meta.name),
+                                           name,
+                                       });
+    }
+
+    fn get_dispatch_depth(&self) -> usize {
+        self.dispatch_depth.get()
+    }
+
+    fn enter_dispatch(&self) -> DispatchGuard {
+        DispatchGuard::new(&self.dispatch_depth)
+    }
+
+    fn set_object_store(&self, mut store: Box<dyn ObjectStore>) {
+        let mut objects = self.objects.borrow_mut();
+        for id in objects.get_ids() {
+            if let Some(object) = objects.remove(id) {
+                store.insert(id, object);
+            }
+        }
+        *objects = store;
+    }
+
+    fn introspect(&self) -> Introspection {
+        Introspection {
+            objects: self.get_object_infos(),
+            history: self.history.borrow().to_vec(),
+        }
+    }
+
+    fn get_state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.set(state);
+    }
+
+    fn set_max_objects(&self, max_objects: Option<usize>) {
+        self.max_objects.set(max_objects);
+    }
+
+    fn has_posted_error(&self) -> bool {
+        self.error_posted.get()
+    }
+
+    fn set_disconnect_handler(&self, handler: Option<DisconnectHandler>) {
+        *self.disconnect_handler.borrow_mut() = handler;
+    }
+
+    fn notify_disconnect(&self, reason: DisconnectReason) {
+        let state = self.state.get();
+        let was_alive = state.is_alive();
+        self.state.set(match reason {
+                           DisconnectReason::ProtocolError { .. } if was_alive => {
+                               ConnectionState::Erroring
+                           }
+                           DisconnectReason::ProtocolError { .. } => state,
+                           _ => ConnectionState::Closed,
+                       });
+        if was_alive {
+            // Release the borrow before calling, the handler may use the connection.
+            let handler = self.disconnect_handler.borrow_mut().take();
+            if let Some(mut handler) = handler {
+                handler(reason);
+            }
+        }
+    }
+}
+
+/// Private methods.
+impl Bundle {
+    /// Returns `SkylaneError::LimitExceeded` if adding object with given `id` would exceed the
+    /// limit on number of objects (see `Connection::set_max_objects`).
+    fn check_object_limit(&self, id: ObjectId) -> Result<(), SkylaneError> {
+        if let Some(max_objects) = self.max_objects.get() {
+            let objects = self.objects.borrow();
+            if objects.get(id).is_none() && objects.len() >= max_objects {
+                return Err(SkylaneError::LimitExceeded {
+                               description: format!("Too many objects (limit is {})", max_objects),
+                           });
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures metadata and zombie state of object `id` if transaction is started, so they can be
+    /// restored on rollback.
+    fn capture_object(&self, id: ObjectId) -> Option<TransactionEntry> {
+        if self.transaction.borrow().is_none() {
+            return None;
+        }
+        Some(TransactionEntry {
+                 id,
+                 previous: None,
+                 snapshot: self.validator.borrow().snapshot(id),
+                 was_zombie: self.zombies.borrow().contains(&id),
+             })
+    }
+
+    /// Adds change of object captured with `capture_object` to the transaction.
+    fn record_change(&self, entry: Option<TransactionEntry>, previous: Option<ObjectRef>) {
+        if let Some(mut entry) = entry {
+            if let Some(ref mut entries) = *self.transaction.borrow_mut() {
+                entry.previous = previous;
+                entries.push(entry);
+            }
+        }
+    }
+
+    /// Returns `SkylaneError::Closed` if the connection ended or is ending after fatal protocol
+    /// error, so no new messages may be sent.
+    fn check_state(&self) -> Result<(), SkylaneError> {
+        if self.state.get().is_alive() {
+            Ok(())
+        } else {
+            Err(SkylaneError::Closed)
+        }
+    }
+
+    /// Creates marshaller recording signature if outgoing messages are validated.
+    fn compose_message(&self, object_id: ObjectId, opcode: u16) -> Marshaller {
+        let mut marshaller = Marshaller::with_buffer(object_id, opcode, self.acquire_buffer());
+        if self.validator.borrow().get_mode() != ValidationMode::Off {
+            marshaller.record_signature();
+        }
+        marshaller
+    }
+
+    /// Validates composed message and queues it. Messages which can not be finalized are dropped.
+    fn queue_composed(&self, mut marshaller: Marshaller) {
+        if let Err(err) = marshaller.finalize() {
+            self.socket.log(|| LogRecord {
+                                direction: Some(Direction::Outgoing),
+                                ..LogRecord::new(LogLevel::Error,
+                                                 format!("Dropped message: {:?}", err))
+                            });
+            self.release_buffer(marshaller.into_buffer());
+            return;
+        }
+
+        let mode = self.validator.borrow().get_mode();
+        if mode != ValidationMode::Off && marshaller.get_signature().is_some() {
+            self.validate(&mut marshaller, mode);
+        }
+        if let Ok((bytes, fds)) = marshaller.finalize() {
+            self.queue_event(bytes, fds);
+        }
+        self.outgoing.borrow_mut().adopt(marshaller.take_owned_fds());
+        self.release_buffer(marshaller.into_buffer());
+    }
+
+    /// Returns descriptions of all registered objects sorted by ID.
+    fn get_object_infos(&self) -> Vec<ObjectInfo> {
+        let objects = self.objects.borrow();
+        objects.get_ids()
+            .into_iter()
+            .map(|id| {
+                let meta = self.get_interface_meta(id);
+                ObjectInfo {
+                    id,
+                    interface: meta.map(|meta| meta.name),
+                    version: self.get_object_version(id),
+                    refcount: objects.get(id).map_or(0, Rc::strong_count),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns ID following the biggest ID in use (including zombies) between `first` and `last`
+    /// or, if `last` is in use, the lowest free ID in this range.
+    fn get_next_available_id(&self,
+                             first: ObjectId,
+                             last: ObjectId,
+                             side: Side)
+                             -> Result<ObjectId, SkylaneError> {
+        let objects = self.objects.borrow();
+        let zombies = self.zombies.borrow();
+        let in_range = |id: &ObjectId| first <= *id && *id <= last;
+        let max = objects.max_id_in(first, last)
+            .into_iter()
+            .chain(zombies.iter().cloned().filter(&in_range))
+            .max();
+
+        match max {
+            None => Ok(first),
+            Some(max) if max < last => Ok(ObjectId::new(max.get_value() + 1)),
+            Some(_) => {
+                let mut used: Vec<ObjectId> = objects.get_ids()
+                    .into_iter()
+                    .filter(&in_range)
+                    .chain(zombies.iter().cloned().filter(&in_range))
+                    .collect();
+                used.sort();
+                used.dedup();
+
+                let mut candidate = first;
+                for id in used {
+                    if id != candidate {
+                        return Ok(candidate);
+                    }
+                    if candidate == last {
+                        break;
+                    }
+                    candidate = ObjectId::new(candidate.get_value() + 1);
+                }
+                Err(SkylaneError::IdsExhausted { side })
+            }
+        }
+    }
+
+    /// Dispatches message queued by placeholder to handler of object `id` and executes returned
+    /// task. File descriptors not taken by the handler are closed.
+    fn replay_message(&mut self,
+                      id: ObjectId,
+                      pending: PendingMessage)
+                      -> Result<(), SkylaneError> {
+        let handler_ref = self.get_handler(id)?;
+        let mut handler = handler_ref.try_borrow_mut()
+            .map_err(|_| {
+                         SkylaneError::Reentrancy {
+                             object_id: Some(id),
+                             depth: self.dispatch_depth.get(),
+                         }
+                     })?;
+
+        let mut fds_bytes = vec![0; 4 * pending.fds.len()];
+        for (i, fd) in pending.fds.iter().enumerate() {
+            NativeEndian::write_i32(&mut fds_bytes[(4 * i)..(4 * i + 4)], fd.get_fd());
+        }
+
+        let mut fds_cursor = Cursor::new(&fds_bytes[..]);
+        let result = {
+            let mut message = Message::new(pending.header, &pending.args, &mut fds_cursor);
+            message.set_side(self.side.get());
+            message.set_utf8_policy(self.utf8_policy.get());
+            handler.dispatch_message(self, &mut message)
+        };
+
+        let num_taken = fds_cursor.position() as usize / 4;
+        for fd in pending.fds.into_iter().take(num_taken) {
+            fd.into_raw();
+        }
+
+        match result? {
+            Task::Create { id, object } => self.add_remote_object(id, object),
+            Task::Destroy { id } => {
+                self.remove_object(id);
+                Ok(())
+            }
+            Task::None => Ok(()),
+        }
+    }
+
+    /// Writes data to socket passing file descriptors if there are any. If socket buffer is full
+    /// the rest of data is queued to be written on next flush.
+    fn write(&self, bytes: &[u8], fds: &[RawFd]) -> Result<(), SkylaneError> {
+        let written = match self.socket.write_with_control_data(bytes, fds) {
+            Ok(written) => written,
+            Err(ref err) if err.is_would_block() => 0,
+            Err(err) => return Err(err),
+        };
+
+        if written < bytes.len() {
+            self.outgoing.borrow_mut().push_unwritten(bytes, fds, written);
+        }
+        Ok(())
+    }
+
+    /// Writes data unless there are queued messages or outgoing messages are corked. Otherwise
+    /// queues data after queued messages to keep order.
+    fn write_or_queue(&self, bytes: &[u8], fds: &[RawFd]) -> Result<(), SkylaneError> {
+        if self.outgoing.borrow().is_empty() && !self.is_corked() && !self.is_detached() {
+            self.write(bytes, fds)
+        } else {
+            self.outgoing.borrow_mut().push(bytes, fds);
+            Ok(())
+        }
+    }
+
+    /// Adds outgoing messages to history and reports them to metrics and trace sinks.
+    fn record_outgoing(&self, bytes: &[u8]) {
+        if self.history.borrow().is_enabled() || self.metrics.borrow().is_some() ||
+           self.tracer.borrow().is_some() {
+            for (header, args) in MessageIter::new(bytes).filter_map(|message| message.ok()) {
+                self.record_message(Direction::Outgoing, header, args);
+            }
+        }
+    }
+
+    /// Checks if all messages in `bytes` are available in versions bound for their objects.
+    /// Returns error describing the first unsupported message. Always succeeds if version check
+    /// is disabled.
+    fn check_versions(&self, bytes: &[u8]) -> Result<(), SkylaneError> {
+        let validator = self.validator.borrow();
+        if validator.get_version_check() == VersionCheck::Off {
+            return Ok(());
+        }
+
+        let side = self.side.get().unwrap_or(Side::Server);
+        for (header, _) in MessageIter::new(bytes).filter_map(|message| message.ok()) {
+            let object_id = ObjectId::new(header.object_id);
+            let opcode = header.opcode;
+            if let Some((since, version)) = validator.check_version(object_id, opcode, side) {
+                return Err(SkylaneError::UnsupportedVersion {
+                               object_id,
+                               opcode,
+                               since,
+                               version,
+                           });
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs message dropped by version check and returns the error if check mode requires it.
+    fn handle_unsupported(&self, error: SkylaneError) -> Result<(), SkylaneError> {
+        self.socket.log(|| LogRecord {
+                            direction: Some(Direction::Outgoing),
+                            ..LogRecord::new(LogLevel::Warning,
+                                             format!("Dropped unsupported message: {:?}", error))
+                        });
+        if self.validator.borrow().get_version_check() == VersionCheck::Error {
+            Err(error)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parses back marshalled message and reports it if it is malformed.
+    fn validate(&self, marshaller: &mut Marshaller, mode: ValidationMode) {
+        let signature = marshaller.get_signature().unwrap_or(&[]).to_vec();
+        let (bytes, fds) = match marshaller.finalize() {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        if let Err(err) = self.validator.borrow().validate(bytes, fds.len(), &signature) {
+            let text = format!("Invalid outgoing message: {}; bytes: {:?}", err, bytes);
+            match mode {
+                ValidationMode::Panic => panic!("{}", text),
+                _ => {
+                    self.socket.log(|| LogRecord {
+                                        direction: Some(Direction::Outgoing),
+                                        ..LogRecord::new(LogLevel::Error, text)
+                                    });
+                }
+            }
         }
     }
 }
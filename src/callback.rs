@@ -0,0 +1,114 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Helpers for `wl_callback` objects.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use defs::{SkylaneError, Task};
+use bundle::Bundle;
+use display::CALLBACK_DONE_OPCODE;
+use message::Message;
+use object::{Object, ObjectId};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Name of `wl_callback` interface.
+pub const INTERFACE: &str = "wl_callback";
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side handle to state of `wl_callback`.
+///
+/// It is updated when `ClientCallback` registered with the same ID receives `done` event.
+#[derive(Clone)]
+pub struct Callback {
+    id: ObjectId,
+    data: Rc<Cell<Option<u32>>>,
+}
+
+impl Callback {
+    /// Constructs new `Callback` with given ID and object which should be registered with it.
+    pub fn new(id: ObjectId) -> (Callback, ClientCallback) {
+        let data = Rc::new(Cell::new(None));
+        (Callback { id, data: data.clone() }, ClientCallback { data })
+    }
+
+    /// Returns ID of the callback.
+    pub fn get_id(&self) -> ObjectId {
+        self.id
+    }
+
+    /// Checks if `done` event was received.
+    pub fn is_done(&self) -> bool {
+        self.data.get().is_some()
+    }
+
+    /// Returns data (serial or timestamp) received with `done` event.
+    pub fn get_data(&self) -> Option<u32> {
+        self.data.get()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side `wl_callback` object. Stores data from `done` event and destroys itself.
+pub struct ClientCallback {
+    data: Rc<Cell<Option<u32>>>,
+}
+
+impl Object for ClientCallback {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        if message.get_opcode() == CALLBACK_DONE_OPCODE {
+            self.data.set(Some(message.next_uint()?));
+            Ok(Task::Destroy { id: message.get_object_id() })
+        } else {
+            Err(SkylaneError::WrongOpcode {
+                    name: INTERFACE,
+                    object_id: message.get_object_id(),
+                    opcode: message.get_opcode(),
+                })
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Server-side `wl_callback` object (e.g. for frame callbacks).
+///
+/// `wl_callback` has no requests so this object only holds the ID until
+/// `Bundle::fire_callback` is called.
+pub struct ServerCallback;
+
+impl Object for ServerCallback {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Err(SkylaneError::WrongOpcode {
+                name: INTERFACE,
+                object_id: message.get_object_id(),
+                opcode: message.get_opcode(),
+            })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,89 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Time sources for timestamps sent in events.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use nix::libc;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Source of timestamps sent in events (e.g. `wl_pointer.motion`).
+///
+/// See `Connection::set_clock`.
+pub trait Clock {
+    /// Returns current time in milliseconds. Only differences between timestamps are meaningful;
+    /// the value wraps around.
+    fn get_time_ms(&self) -> u32;
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Clock based on `CLOCK_MONOTONIC`, the same as used for timestamps of input events by kernel
+/// and most compositors. This is the default clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn get_time_ms(&self) -> u32 {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+        (time.tv_sec as u64 * 1000 + time.tv_nsec as u64 / 1_000_000) as u32
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Clock advanced only explicitly. Meant for tests which need deterministic timestamps.
+///
+/// Clones share the time, so one clone can be passed to connection and the other kept by test.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    time: Rc<Cell<u32>>,
+}
+
+impl ManualClock {
+    /// Constructs new `ManualClock` showing `time_ms`.
+    pub fn new(time_ms: u32) -> Self {
+        ManualClock { time: Rc::new(Cell::new(time_ms)) }
+    }
+
+    /// Sets current time.
+    pub fn set_time_ms(&self, time_ms: u32) {
+        self.time.set(time_ms);
+    }
+
+    /// Moves current time forward.
+    pub fn advance(&self, duration: Duration) {
+        let millis = duration.as_secs() * 1000 + duration.subsec_millis() as u64;
+        self.time.set(self.time.get().wrapping_add(millis as u32));
+    }
+}
+
+impl Clock for ManualClock {
+    fn get_time_ms(&self) -> u32 {
+        self.time.get()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -17,14 +17,44 @@
 
 //! Functionality related to controlling connection.
 
-use std::io::{Cursor, SeekFrom, Seek};
+use std;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+use std::os::unix::io::RawFd;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use byteorder::{NativeEndian, ReadBytesExt};
+use byteorder::{NativeEndian, WriteBytesExt};
+use nix;
 
-use defs::{Header, SkylaneError, Task};
-use object::{Object, ObjectId};
+use credentials::Credentials;
+use defs::{Direction, DisplayError, Header, LogLevel, LogRecord, Side, SkylaneError, Task};
+use callback::Callback;
+use clock::Clock;
+use dispatch::{ConnectionState, DisconnectHandler, DisconnectReason, DispatchFailure,
+               DispatchPolicy, DispatchReport, FilterDecision, RequestFilter};
+use display::{self, DisplayObject, RegistryFactory};
+use endian::Endianness;
+use fd::OwnedFd;
+use introspect::Introspection;
+use object::{Object, ObjectId, DISPLAY_ID};
+use proxy::Proxy;
 use bundle::{Bundle, BundleInternal};
-use sockets::Socket;
+use map::{ObjectStore, WeakObjectRef};
+use marshal::Marshaller;
+use limits::{FdLimit, FdOverflowPolicy, RateLimit, RateLimiter};
+use message::{Message, MessageInternal, MessageIter, Utf8Policy};
+use meta::InterfaceMeta;
+use names;
+use reader::{ReadIntent, Reader, ReaderInternal};
+use reconnect::{RebindCallback, Reconnect, ReconnectPolicy};
+use remote::{RemoteController, RemoteQueue};
+use sockets::{Shutdown, Socket, SocketInternal};
+use serials::SerialInfo;
+use stats::{MetricsSink, Stats};
+use trace::TraceSink;
+use validation::{ValidationMode, VersionCheck};
 
 // -------------------------------------------------------------------------------------------------
 
@@ -38,7 +68,7 @@ impl Controller {
     /// Constructs new `Controller`.
     fn new(bundle: Bundle) -> Self {
         Controller {
-            bundle: bundle,
+            bundle,
         }
     }
 
@@ -47,40 +77,149 @@ impl Controller {
         self.bundle.get_socket()
     }
 
+    /// Increments and returns next serial.
+    ///
+    /// See `Bundle::next_serial`.
+    pub fn next_serial(&self) -> u32 {
+        self.bundle.next_serial()
+    }
+
+    /// Returns last serial.
+    ///
+    /// See `Bundle::last_serial`.
+    pub fn last_serial(&self) -> u32 {
+        self.bundle.last_serial()
+    }
+
     /// Returns next available client object ID.
     ///
     /// See `Bundle::get_next_available_client_object_id`.
-    pub fn get_next_available_client_object_id(&self) -> ObjectId {
+    pub fn get_next_available_client_object_id(&self) -> Result<ObjectId, SkylaneError> {
         self.bundle.get_next_available_client_object_id()
     }
 
     /// Returns next available server object ID.
     ///
     /// See `Bundle::get_next_available_server_object_id`.
-    pub fn get_next_available_server_object_id(&self) -> ObjectId {
+    pub fn get_next_available_server_object_id(&self) -> Result<ObjectId, SkylaneError> {
         self.bundle.get_next_available_server_object_id()
     }
 
     /// Adds new object.
     ///
     /// See `Bundle::add_object`.
-    pub fn add_object(&mut self, id: ObjectId, object: Box<Object>) {
+    pub fn add_object(&mut self, id: ObjectId, object: Box<dyn Object>) {
         self.bundle.add_object(id, object);
     }
 
+    /// Replaces handler of existing object.
+    ///
+    /// See `Bundle::replace_object`.
+    pub fn replace_object(&mut self,
+                          id: ObjectId,
+                          object: Box<dyn Object>)
+                          -> Result<(), SkylaneError> {
+        self.bundle.replace_object(id, object)
+    }
+
+    /// Registers placeholder for object whose handler is not constructed yet.
+    ///
+    /// See `Bundle::add_placeholder`.
+    pub fn add_placeholder(&mut self, id: ObjectId) {
+        self.bundle.add_placeholder(id);
+    }
+
+    /// Attaches handler to placeholder object and replays messages queued by it.
+    ///
+    /// See `Bundle::attach_object`.
+    pub fn attach_object(&mut self,
+                         id: ObjectId,
+                         object: Box<dyn Object>)
+                         -> Result<(), SkylaneError> {
+        self.bundle.attach_object(id, object)
+    }
+
     /// Adds next client object.
     ///
     /// See `Bundle::add_next_client_object`.
-    pub fn add_next_client_object(&mut self, object: Box<Object>) -> ObjectId {
+    pub fn add_next_client_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
         self.bundle.add_next_client_object(object)
     }
 
     /// Adds next server object.
     ///
     /// See `Bundle::add_next_server_object`.
-    pub fn add_next_server_object(&mut self, object: Box<Object>) -> ObjectId {
+    pub fn add_next_server_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
         self.bundle.add_next_server_object(object)
     }
+
+    /// Removes object with given `id`.
+    ///
+    /// See `Bundle::remove_object`.
+    pub fn remove_object(&mut self, id: ObjectId) {
+        self.bundle.remove_object(id);
+    }
+
+    /// Sends `wl_display.error` event. The connection becomes `Erroring`.
+    ///
+    /// See `Bundle::post_error`.
+    pub fn post_error(&self,
+                      object_id: ObjectId,
+                      code: u32,
+                      message: &str)
+                      -> Result<(), SkylaneError> {
+        self.bundle.post_error(object_id, code, message)
+    }
+
+    /// Queues marshalled message.
+    ///
+    /// See `Bundle::queue_event`.
+    pub fn queue_event(&self, bytes: &[u8], fds: &[RawFd]) {
+        self.bundle.queue_event(bytes, fds);
+    }
+
+    /// Queues message composed by `marshaller`.
+    ///
+    /// See `Bundle::queue_message`.
+    pub fn queue_message(&self, marshaller: Marshaller) {
+        self.bundle.queue_message(marshaller);
+    }
+
+    /// Writes marshalled message immediately.
+    ///
+    /// See `Bundle::send_event`.
+    pub fn send_event(&self, bytes: &[u8], fds: &[RawFd]) -> Result<(), SkylaneError> {
+        self.bundle.send_event(bytes, fds)
+    }
+
+    /// Marshals and queues message.
+    ///
+    /// See `Bundle::send`.
+    pub fn send<F>(&self, object_id: ObjectId, opcode: u16, compose: F)
+        where F: FnOnce(&mut Marshaller)
+    {
+        self.bundle.send(object_id, opcode, compose);
+    }
+
+    /// Marshals and queues message with new serial.
+    ///
+    /// See `Bundle::send_with_serial`.
+    pub fn send_with_serial<F>(&self, object_id: ObjectId, opcode: u16, compose: F) -> u32
+        where F: FnOnce(&mut Marshaller, u32)
+    {
+        self.bundle.send_with_serial(object_id, opcode, compose)
+    }
+
+    /// Writes all queued messages.
+    ///
+    /// See `Bundle::flush`.
+    pub fn flush(&self) -> Result<(), SkylaneError> {
+        self.bundle.flush()
+    }
 }
 
 /// `Bundle` does not implement `Clone`, so `Controller` must implement it manually.
@@ -96,61 +235,557 @@ impl Clone for Controller {
 /// registered listeners.
 pub struct Connection {
     bundle: Bundle,
+    rate_limiter: Option<RateLimiter>,
+    dispatch_policy: DispatchPolicy,
+    reader: Reader,
+    reconnect: Option<Reconnect>,
+    remote: Option<RemoteQueue>,
+    strict: bool,
+    error_context: bool,
+    last_activity: Instant,
+    idle_timeout: Option<Duration>,
+    paused: bool,
+    request_filter: Option<RequestFilter>,
+    max_dispatch_depth: usize,
+    remote_error: Option<(ObjectId, u32, String)>,
 }
 
 impl Connection {
     /// Constructs new `Connection`.
     pub fn new(socket: Socket) -> Connection {
         Connection {
+            reader: Reader::new(socket.clone()),
             bundle: Bundle::new(socket),
+            rate_limiter: None,
+            dispatch_policy: DispatchPolicy::default(),
+            reconnect: None,
+            remote: None,
+            strict: false,
+            error_context: false,
+            last_activity: Instant::now(),
+            idle_timeout: None,
+            paused: false,
+            request_filter: None,
+            max_dispatch_depth: 1,
+            remote_error: None,
         }
     }
 
+    /// Constructs new server-side `Connection` with built-in `wl_display` implementation
+    /// registered as `DISPLAY_ID`.
+    ///
+    /// Removing objects created by client will result in sending `wl_display.delete_id` event.
+    pub fn new_server(socket: Socket, registry_factory: RegistryFactory) -> Connection {
+        let mut connection = Connection::new(socket);
+        connection.bundle.set_emits_delete_id(true);
+        connection.bundle.set_side(Some(Side::Server));
+        connection.add_object(DISPLAY_ID, Box::new(DisplayObject::new(registry_factory)));
+        connection.set_interface_meta(DISPLAY_ID, &display::META);
+        connection
+    }
+
     /// Returns connection socket.
     pub fn get_socket(&self) -> Socket {
         self.bundle.get_socket()
     }
 
+    /// Returns traffic statistics.
+    ///
+    /// See `Socket::stats`.
+    pub fn stats(&self) -> Stats {
+        self.bundle.get_socket().stats()
+    }
+
+    /// Sets limits on traffic from the peer. If the limits are exceeded `process_events` returns
+    /// `SkylaneError::LimitExceeded` and the caller should disconnect the peer. `None` disables
+    /// limits.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limiter = limit.map(RateLimiter::new);
+    }
+
+    /// Sets limit on number of received file descriptors not yet consumed by dispatched messages.
+    /// Exceeding the limit is fatal for the connection (see `FdOverflowPolicy`). Descriptors
+    /// dropped because of the limit are counted in `Stats::fds_dropped`. `None` disables the limit.
+    ///
+    /// Limit is carried over when the connection is re-established.
+    pub fn set_fd_limit(&mut self, limit: Option<FdLimit>) {
+        self.reader.set_fd_limit(limit);
+    }
+
+    /// Sets policy deciding if processing should stop on first dispatch error.
+    pub fn set_dispatch_policy(&mut self, policy: DispatchPolicy) {
+        self.dispatch_policy = policy;
+    }
+
+    /// Sets validation mode for outgoing messages.
+    ///
+    /// Validation parses back every sent message and checks it against signatures registered with
+    /// `Bundle::set_signatures`. It is costly and meant for development builds.
+    pub fn set_validation_mode(&mut self, mode: ValidationMode) {
+        self.bundle.set_validation_mode(mode);
+    }
+
+    /// Sets what to do with sent messages not available in version of interface bound for their
+    /// objects (e.g. v4 event sent to client which bound v1 global). Default is
+    /// `VersionCheck::Error`.
+    ///
+    /// Only objects with known version (see `Bundle::set_object_version`) and interface metadata
+    /// are checked.
+    pub fn set_version_check(&mut self, version_check: VersionCheck) {
+        self.bundle.set_version_check(version_check);
+    }
+
+    /// Sets how many dispatch loops (`dispatch_pending`, `process_events`, `roundtrip`) may run
+    /// nested on this connection. Default is `1` - dispatching from within a handler returns
+    /// `SkylaneError::Reentrancy` instead of corrupting state. Regardless of this setting message
+    /// to object whose handler is already running also results in `Reentrancy` error instead of
+    /// panic.
+    ///
+    /// Handlers should not need nested dispatching. Safe alternatives are returning `Task` to let
+    /// connection create or destroy objects after the handler returns, using `Controller` or
+    /// `RemoteController` to act on connection later and waiting for replies (e.g. `sync`
+    /// callbacks) in the main loop rather than with `roundtrip`.
+    pub fn set_max_dispatch_depth(&mut self, depth: usize) {
+        self.max_dispatch_depth = depth;
+    }
+
+    /// Enables or disables strict mode.
+    ///
+    /// In strict mode requests to nonexistent objects do not produce `WrongObject` errors.
+    /// Instead `wl_display.error` with `invalid_object` code is posted to the client, the error is
+    /// reported in `DispatchReport::posted_error` and all further messages from the client are
+    /// discarded. The server should then close the connection without affecting other clients.
+    ///
+    /// This mode is meant to be used on server side.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Enables or disables attaching received messages to errors.
+    ///
+    /// When enabled, errors returned by handlers and errors of parsing malformed messages are
+    /// wrapped in `SkylaneError::Context` carrying header and raw bytes of the offending message,
+    /// so bug reports contain enough information to reproduce the failure. Disabled by default as
+    /// messages may contain sensitive data.
+    pub fn set_error_context(&mut self, enabled: bool) {
+        self.error_context = enabled;
+    }
+
+    /// Checks if fatal error was posted to the client, in strict mode or with `post_error`.
+    pub fn has_posted_error(&self) -> bool {
+        self.bundle.has_posted_error()
+    }
+
+    /// Sets side of connection. When side is known IDs of objects created by peer are checked to
+    /// be in the peer's range (see `Message::next_new_id` and `Bundle::add_remote_object`).
+    ///
+    /// Connections created by `new_server` and `connect` have side set.
+    pub fn set_side(&mut self, side: Option<Side>) {
+        self.bundle.set_side(side);
+    }
+
+    /// Sets how invalid UTF-8 in string arguments of received messages is handled by
+    /// `Message::next_string`. Default is `Utf8Policy::Error`.
+    pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.bundle.set_utf8_policy(policy);
+    }
+
+    /// Returns side of connection if known.
+    pub fn get_side(&self) -> Option<Side> {
+        self.bundle.get_side()
+    }
+
+    /// Sets maximal number of objects. Adding object with `Bundle::add_remote_object`,
+    /// `Bundle::add_local_object`, `Bundle::add_next_client_object`,
+    /// `Bundle::add_next_server_object` or `Task::Create` fails with `SkylaneError::LimitExceeded`
+    /// if the limit is reached; the object is not registered then. `Bundle::add_object` is not
+    /// checked.
+    ///
+    /// Meant to be used on server side to protect from clients creating objects endlessly.
+    pub fn set_max_objects(&mut self, max_objects: Option<usize>) {
+        self.bundle.set_max_objects(max_objects);
+    }
+
+    /// Sets number of recently sent and received messages kept for `introspect`. Zero disables
+    /// keeping history, which is the default.
+    pub fn set_history_size(&mut self, size: usize) {
+        self.bundle.set_history_size(size);
+    }
+
+    /// Sets sink receiving interface, opcode, size and direction of every sent and received
+    /// message. `None` disables reporting.
+    pub fn set_metrics_sink(&mut self, sink: Option<Box<dyn MetricsSink>>) {
+        self.bundle.set_metrics_sink(sink);
+    }
+
+    /// Replaces storage of registered objects. Objects registered so far are moved to `store`.
+    ///
+    /// See `ObjectStore`.
+    pub fn set_object_store(&mut self, store: Box<dyn ObjectStore>) {
+        self.bundle.set_object_store(store);
+    }
+
+    /// Sets sink receiving every sent and received message along with its arguments decoded
+    /// according to registered metadata. `None` disables tracing.
+    ///
+    /// See `JsonTraceSink`.
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.bundle.set_trace_sink(sink);
+    }
+
+    /// Sets source of timestamps returned by `Bundle::get_time_ms`.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.bundle.set_clock(clock);
+    }
+
+    /// Enables remembering up to `capacity` serials issued by `Bundle::next_serial_for`. Serials
+    /// older than `max_age` are forgotten. Zero `capacity` disables remembering serials.
+    ///
+    /// Lets server validate serials passed back by client (e.g. in `wl_shell_surface.move`) with
+    /// `find_serial`.
+    pub fn set_serial_history(&mut self, capacity: usize, max_age: Option<Duration>) {
+        self.bundle.set_serial_history(capacity, max_age);
+    }
+
+    /// Returns information about recently issued serial.
+    ///
+    /// See `Bundle::find_serial`.
+    pub fn find_serial(&self, serial: u32) -> Option<SerialInfo> {
+        self.bundle.find_serial(serial)
+    }
+
+    /// Returns credentials of the peer received along with the last read data. Requires receiving
+    /// credentials to be enabled with `Socket::set_pass_credentials` and the peer to send them
+    /// (see `Socket::set_send_credentials`).
+    ///
+    /// Credentials are tracked per read, not per message. Kernel never returns data sent with
+    /// different credentials in one read, so all messages of one read were sent with the returned
+    /// credentials. But every read replaces them, even if messages of previous reads were not
+    /// dispatched yet. They describe messages dispatched by the last `process_events` only if
+    /// nothing else (e.g. `Reader` in another thread) read from the socket in the meantime.
+    pub fn get_last_credentials(&self) -> Option<Credentials> {
+        self.reader.get_credentials()
+    }
+
+    /// Returns snapshot of registered objects (with interface names if metadata were registered,
+    /// see `Bundle::set_interface_meta`, and bound versions) and recent message history (if
+    /// enabled with `set_history_size`).
+    ///
+    /// Meant for debugging tools and inspectors.
+    pub fn introspect(&self) -> Introspection {
+        self.bundle.introspect()
+    }
+
+    /// Returns listing of registered objects.
+    ///
+    /// See `Bundle::dump_objects`.
+    pub fn dump_objects(&self) -> String {
+        self.bundle.dump_objects()
+    }
+
+    /// Returns number of live objects per interface.
+    ///
+    /// See `Bundle::count_objects_by_interface`.
+    pub fn count_objects_by_interface(&self) -> BTreeMap<Option<&'static str>, usize> {
+        self.bundle.count_objects_by_interface()
+    }
+
+    /// Sets filter evaluated for every received message before it is dispatched. `None` removes the
+    /// filter.
+    ///
+    /// Allows servers to implement security policies, e.g. forbidding some clients to bind
+    /// privileged globals. Messages denied by the filter are dropped along with their file
+    /// descriptors. If the filter returns `FilterDecision::Error` the error is posted to the peer
+    /// and no further messages are dispatched (like in strict mode).
+    pub fn set_request_filter(&mut self, filter: Option<RequestFilter>) {
+        self.request_filter = filter;
+    }
+
+    /// Sets time after which connection without incoming messages is considered idle. `None`
+    /// (default) means connection never becomes idle.
+    ///
+    /// See `is_idle` and `disconnect_if_idle`.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Returns time elapsed since the last message was received (or since the connection was
+    /// created if nothing was received yet).
+    pub fn time_since_last_message(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Checks if no message was received for longer than idle timeout.
+    pub fn is_idle(&self) -> bool {
+        match self.idle_timeout {
+            Some(timeout) => self.time_since_last_message() > timeout,
+            None => false,
+        }
+    }
+
+    /// Shuts down the socket if the connection is idle. Returns `true` if it was disconnected.
+    ///
+    /// Meant to be called periodically by server to drop clients which stopped responding (see
+    /// also `ConnectionSet::remove_idle`).
+    pub fn disconnect_if_idle(&mut self) -> Result<bool, SkylaneError> {
+        if self.is_idle() {
+            if !self.bundle.is_detached() {
+                self.bundle.get_socket().shutdown(Shutdown::Both)?;
+            }
+            self.notify_disconnect(DisconnectReason::IdleTimeout);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Sets handler called when the connection ends: the peer closes it, fatal protocol error is
+    /// posted or received, it is disconnected for being idle or terminated with `terminate`.
+    ///
+    /// The handler is called at most once, so cleanup of resources related to the client can be
+    /// done in one place instead of on every error path. It is not called if the connection is just
+    /// dropped or if it is re-established by automatic reconnection.
+    pub fn set_disconnect_handler(&mut self, handler: Option<DisconnectHandler>) {
+        self.bundle.set_disconnect_handler(handler);
+    }
+
+    /// Checks if the connection ended. See `set_disconnect_handler`.
+    pub fn is_disconnected(&self) -> bool {
+        !self.state().is_alive()
+    }
+
+    /// Returns state of the connection.
+    ///
+    /// Connection is `Ready` after construction. Posting or receiving fatal protocol error moves
+    /// it to `Erroring`. It becomes `Closed` when the peer closes it, it is disconnected for being
+    /// idle or terminated with `terminate`. While being re-established by `reconnect` it is
+    /// `Connecting`.
+    pub fn state(&self) -> ConnectionState {
+        self.bundle.get_state()
+    }
+
+    /// Checks if the connection is usable. See `ConnectionState::is_alive`.
+    pub fn is_alive(&self) -> bool {
+        self.state().is_alive()
+    }
+
+    /// Returns error received from server in `wl_display.error` event.
+    ///
+    /// On client side (see `set_side`) the event is handled by connection itself. Once it is
+    /// received the connection is marked as failed: further calls to `process_events`,
+    /// `dispatch_pending`, `roundtrip` and `flush` return `SkylaneError::Remote`.
+    pub fn get_remote_error(&self) -> Option<SkylaneError> {
+        self.remote_error.as_ref().map(|&(object_id, code, ref message)| {
+            SkylaneError::Remote {
+                object_id,
+                code,
+                message: message.clone(),
+            }
+        })
+    }
+
+    /// Flushes pending messages and shuts down the socket. Disconnect handler is called with
+    /// `DisconnectReason::Terminated` if the connection did not end earlier.
+    pub fn terminate(&mut self) -> Result<(), SkylaneError> {
+        let flushed = if self.state() == ConnectionState::Closed || self.remote_error.is_some() {
+            Ok(())
+        } else {
+            self.flush()
+        };
+        if !self.bundle.is_detached() {
+            let _ = self.bundle.get_socket().shutdown(Shutdown::Both);
+        }
+        self.notify_disconnect(DisconnectReason::Terminated);
+        flushed
+    }
+
+    /// Enables automatic reconnection.
+    ///
+    /// When server disconnects, instead of returning error `process_events` and
+    /// `process_events_with_report` will connect again according to `policy` and call `rebind` to
+    /// recreate objects. Reconnection is reported in `DispatchReport::reconnected`.
+    ///
+    /// This method is meant to be used on client side.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy, rebind: RebindCallback) {
+        self.reconnect = Some(Reconnect {
+                                  policy,
+                                  rebind: Some(rebind),
+                              });
+    }
+
+    /// Disables automatic reconnection.
+    pub fn disable_reconnect(&mut self) {
+        self.reconnect = None;
+    }
+
+    /// Returns new `RemoteController` which can be used to post messages from other threads.
+    ///
+    /// Posted operations are executed by `drain_remote`.
+    pub fn get_remote_controller(&mut self) -> Result<RemoteController, SkylaneError> {
+        if self.remote.is_none() {
+            self.remote = Some(RemoteQueue::new()?);
+        }
+        Ok(self.remote.as_ref().expect("remote queue").get_controller())
+    }
+
+    /// Returns file descriptor which becomes readable when operations are posted by
+    /// `RemoteController`s or `None` if no `RemoteController` was created.
+    pub fn get_remote_fd(&self) -> Option<RawFd> {
+        self.remote.as_ref().map(|remote| remote.get_fd())
+    }
+
+    /// Executes operations posted by `RemoteController`s. Returns number of executed operations.
+    pub fn drain_remote(&mut self) -> Result<usize, SkylaneError> {
+        if let Some(ref remote) = self.remote {
+            remote.drain(&mut self.bundle)
+        } else {
+            Ok(0)
+        }
+    }
+
     /// Returns new `Controller` for the connection.
     pub fn get_controller(&self) -> Controller {
         Controller::new(self.bundle.duplicate())
     }
 
+    /// Increments and returns next serial.
+    ///
+    /// See `Bundle::next_serial`.
+    pub fn next_serial(&self) -> u32 {
+        self.bundle.next_serial()
+    }
+
+    /// Returns last serial.
+    ///
+    /// See `Bundle::last_serial`.
+    pub fn last_serial(&self) -> u32 {
+        self.bundle.last_serial()
+    }
+
     /// Returns next available client object ID.
     ///
     /// See `Bundle::get_next_available_client_object_id`.
-    pub fn get_next_available_client_object_id(&self) -> ObjectId {
+    pub fn get_next_available_client_object_id(&self) -> Result<ObjectId, SkylaneError> {
         self.bundle.get_next_available_client_object_id()
     }
 
     /// Returns next available server object ID.
     ///
     /// See `Bundle::get_next_available_server_object_id`.
-    pub fn get_next_available_server_object_id(&self) -> ObjectId {
+    pub fn get_next_available_server_object_id(&self) -> Result<ObjectId, SkylaneError> {
         self.bundle.get_next_available_server_object_id()
     }
 
     /// Adds new object.
     ///
     /// See `Bundle::add_object`.
-    pub fn add_object(&mut self, id: ObjectId, object: Box<Object>) {
+    pub fn add_object(&mut self, id: ObjectId, object: Box<dyn Object>) {
         self.bundle.add_object(id, object);
     }
 
+    /// Registers metadata of interface implemented by object.
+    ///
+    /// See `Bundle::set_interface_meta`.
+    pub fn set_interface_meta(&mut self, id: ObjectId, meta: &'static InterfaceMeta) {
+        self.bundle.set_interface_meta(id, meta);
+    }
+
+    /// Replaces handler of existing object.
+    ///
+    /// See `Bundle::replace_object`.
+    pub fn replace_object(&mut self,
+                          id: ObjectId,
+                          object: Box<dyn Object>)
+                          -> Result<(), SkylaneError> {
+        self.bundle.replace_object(id, object)
+    }
+
+    /// Registers placeholder for object whose handler is not constructed yet.
+    ///
+    /// See `Bundle::add_placeholder`.
+    pub fn add_placeholder(&mut self, id: ObjectId) {
+        self.bundle.add_placeholder(id);
+    }
+
+    /// Attaches handler to placeholder object and replays messages queued by it.
+    ///
+    /// See `Bundle::attach_object`.
+    pub fn attach_object(&mut self,
+                         id: ObjectId,
+                         object: Box<dyn Object>)
+                         -> Result<(), SkylaneError> {
+        self.bundle.attach_object(id, object)
+    }
+
     /// Adds new client object.
     ///
     /// See `Bundle::add_next_client_object`.
-    pub fn add_next_client_object(&mut self, object: Box<Object>) -> ObjectId {
+    pub fn add_next_client_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
         self.bundle.add_next_client_object(object)
     }
 
     /// Adds next server object.
     ///
     /// See `Bundle::add_next_server_object`.
-    pub fn add_next_server_object(&mut self, object: Box<Object>) -> ObjectId {
+    pub fn add_next_server_object(&mut self,
+                                  object: Box<dyn Object>)
+                                  -> Result<ObjectId, SkylaneError> {
         self.bundle.add_next_server_object(object)
     }
 
+    /// Gets next available client object ID, adds new object and returns `Proxy` for sending
+    /// requests to it.
+    ///
+    /// This method is meant to be used on client side.
+    pub fn create_proxy<I>(&mut self, object: Box<dyn Object>) -> Result<Proxy<I>, SkylaneError> {
+        let id = self.add_next_client_object(object)?;
+        Ok(Proxy::new(&self.bundle, id))
+    }
+
+    /// Sets context available to all handlers.
+    ///
+    /// See `Bundle::set_context`.
+    pub fn set_context<T: Any>(&mut self, context: T) {
+        self.bundle.set_context(context);
+    }
+
+    /// Calls `f` with context of the connection.
+    ///
+    /// See `Bundle::with_context`.
+    pub fn with_context<T, F, R>(&self, f: F) -> Result<R, SkylaneError>
+        where T: Any,
+              F: FnOnce(&mut T) -> R
+    {
+        self.bundle.with_context(f)
+    }
+
+    /// Calls `f` with ID of every registered object implementing given interface.
+    ///
+    /// See `Bundle::for_each_object`.
+    pub fn for_each_object<F>(&self, interface: &str, f: F) -> Result<(), SkylaneError>
+        where F: FnMut(ObjectId) -> Result<(), SkylaneError>
+    {
+        self.bundle.for_each_object(interface, f)
+    }
+
+    /// Returns weak reference to object with given `id` if it is registered.
+    ///
+    /// See `Bundle::get_weak_ref`.
+    pub fn get_weak_ref(&self, id: ObjectId) -> Option<WeakObjectRef> {
+        self.bundle.get_weak_ref(id)
+    }
+
+    /// Returns weak reference to object with given nullable `id`.
+    ///
+    /// See `Bundle::get_nullable_weak_ref`.
+    pub fn get_nullable_weak_ref(&self,
+                                 id: Option<ObjectId>)
+                                 -> Result<Option<WeakObjectRef>, SkylaneError> {
+        self.bundle.get_nullable_weak_ref(id)
+    }
+
     /// Removes object with given `id`.
     ///
     /// See `Bundle::remove_object`.
@@ -158,30 +793,355 @@ impl Connection {
         self.bundle.remove_object(id);
     }
 
+    /// Writes all queued messages.
+    ///
+    /// See `Bundle::flush`.
+    pub fn flush(&mut self) -> Result<(), SkylaneError> {
+        self.check_state()?;
+        self.bundle.flush()
+    }
+
+    /// Corks outgoing messages.
+    ///
+    /// See `Bundle::cork`.
+    pub fn cork(&self) {
+        self.bundle.cork();
+    }
+
+    /// Uncorks outgoing messages and writes them if not corked anymore.
+    ///
+    /// See `Bundle::uncork`.
+    pub fn uncork(&self) -> Result<(), SkylaneError> {
+        self.bundle.uncork()
+    }
+
+    /// Sends `wl_display.sync` request. Returned `Callback` will be marked as done when server
+    /// processes all requests sent before.
+    ///
+    /// This method is meant to be used on client side.
+    pub fn sync(&mut self) -> Result<Callback, SkylaneError> {
+        self.check_state()?;
+        let id = self.get_next_available_client_object_id()?;
+        let (callback, object) = Callback::new(id);
+        self.bundle.send_marshalled(DISPLAY_ID, display::SYNC_OPCODE, |marshaller| {
+                marshaller.put_object(id);
+            })?;
+        self.add_object(id, Box::new(object));
+        Ok(callback)
+    }
+
+    /// Sends `wl_callback.done` event and removes the callback.
+    ///
+    /// See `Bundle::fire_callback`.
+    pub fn fire_callback(&mut self, id: ObjectId, data: u32) -> Result<(), SkylaneError> {
+        self.check_state()?;
+        self.bundle.fire_callback(id, data)
+    }
+
+    /// Sends `wl_display.error` event. The connection becomes `Erroring`: no more messages are
+    /// dispatched nor sent and disconnect handler is called.
+    ///
+    /// See `Bundle::post_error`.
+    pub fn post_error(&mut self,
+                      object_id: ObjectId,
+                      code: u32,
+                      message: &str)
+                      -> Result<(), SkylaneError> {
+        self.bundle.post_error(object_id, code, message)
+    }
+
+    /// If `error` is `SkylaneError::Protocol` (e.g. returned from `process_events`) translates it
+    /// to `wl_display.error` event and sends it to the client like `post_error`. Other errors are
+    /// not sent.
+    ///
+    /// Returns `true` if the error was posted.
+    pub fn post_protocol_error(&mut self, error: &SkylaneError) -> Result<bool, SkylaneError> {
+        if let SkylaneError::Protocol { object_id, code, ref message, .. } = *error {
+            self.post_error(object_id, code, message)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// Reads data from socket and dispatches messages to registered objects.
+    ///
+    /// Errors returned by handlers are passed to the caller. Server may pass them to
+    /// `post_protocol_error` to inform the client.
     pub fn process_events(&mut self) -> Result<(), SkylaneError> {
-        // TODO: What is more optimal - allocation these buffers here, or in struct? They don't
-        // have to be zeroed every time, right? What buffer sizes are enough?
-        let mut bytes: [u8; 1024] = [0; 1024];
-        let mut fds: [u8; 24] = [0; 24];
+        let report = self.process_events_with_report()?;
+        if let Some(failure) = report.failures.into_iter().next() {
+            Err(failure.error)
+        } else {
+            Ok(())
+        }
+    }
 
-        let (bytes_size, _fds_size) = self.bundle.get_socket()
-                                                 .receive_message(&mut bytes, &mut fds)?;
+    /// Reads data from socket and dispatches messages to registered objects. Operations posted by
+    /// `RemoteController`s are executed first. Events queued by handlers are flushed at the end.
+    ///
+    /// Errors returned by handlers are collected in returned report. Depending on dispatch policy
+    /// processing stops on first failure or continues. Errors not related to particular message
+    /// (e.g. reading from socket) are returned directly.
+    pub fn process_events_with_report(&mut self) -> Result<DispatchReport, SkylaneError> {
+        self.check_state()?;
+        self.drain_remote()?;
+        let bytes_read = match self.read_events() {
+            Ok(0) if self.reconnect.is_some() => {
+                return self.reconnect_and_report();
+            }
+            Err(ref err) if self.reconnect.is_some() && err.is_disconnected() => {
+                return self.reconnect_and_report();
+            }
+            result => result?,
+        };
 
-        let mut bytes_buf = Cursor::new(&bytes[..]);
-        let mut fds_buf = Cursor::new(&fds[..]);
+        let mut report = if self.state() == ConnectionState::Closed {
+            DispatchReport::default()
+        } else {
+            self.dispatch_pending()?
+        };
+        report.bytes_read = bytes_read;
+        if self.state() != ConnectionState::Closed {
+            self.flush()?;
+        }
+        Ok(report)
+    }
 
-        let mut position = 0;
-        while position < bytes_size {
-            bytes_buf.seek(SeekFrom::Start(position as u64))?;
-            let header = Header {
-                object_id: bytes_buf.read_u32::<NativeEndian>()?,
-                opcode: bytes_buf.read_u16::<NativeEndian>()?,
-                size: bytes_buf.read_u16::<NativeEndian>()?,
+    /// Connects to the server again according to reconnection policy and calls rebinding
+    /// callback.
+    ///
+    /// All registered objects and pending messages are dropped and the old socket is closed.
+    /// `Controller`s and `Reader`s obtained earlier must be obtained again. The connection is
+    /// `Connecting` until rebinding callback returns. If all attempts or rebinding fail it becomes
+    /// `Closed`.
+    ///
+    /// This method is meant to be used on client side.
+    pub fn reconnect(&mut self) -> Result<(), SkylaneError> {
+        let policy = match self.reconnect {
+            Some(ref reconnect) => reconnect.policy.clone(),
+            None => return Err(SkylaneError::Other("Reconnection not enabled".to_owned())),
+        };
+
+        self.bundle.set_state(ConnectionState::Connecting);
+        let mut attempt = 0;
+        let socket = loop {
+            attempt += 1;
+            let result = match policy.path {
+                Some(ref path) => Socket::connect(path),
+                None => Socket::connect_default(),
             };
+            match result {
+                Ok(socket) => break socket,
+                Err(err) => {
+                    if attempt >= policy.max_attempts {
+                        self.notify_disconnect(DisconnectReason::Closed);
+                        return Err(err);
+                    }
+                    thread::sleep(policy.interval);
+                }
+            }
+        };
+
+        let old_socket = self.bundle.get_socket();
+        socket.inherit_logger(&old_socket);
+        socket.set_nonblocking(old_socket.is_nonblocking());
+        // Clones of the old socket may still be alive; make sure they do not use it anymore.
+        let _ = old_socket.shutdown(Shutdown::Both);
+
+        let fd_limit = self.reader.get_fd_limit();
+        self.bundle = self.bundle.renew(socket.clone());
+        self.reader = Reader::new(socket);
+        self.reader.set_fd_limit(fd_limit);
+
+        let rebind = self.reconnect.as_mut().and_then(|reconnect| reconnect.rebind.take());
+        let result = if let Some(mut rebind) = rebind {
+            let result = rebind(self);
+            if let Some(ref mut reconnect) = self.reconnect {
+                reconnect.rebind = Some(rebind);
+            }
+            result
+        } else {
+            Ok(())
+        };
+        if result.is_ok() {
+            self.bundle.set_state(ConnectionState::Ready);
+        } else {
+            self.notify_disconnect(DisconnectReason::Closed);
+        }
+        result
+    }
+
+    /// Enables or disables detached I/O mode in which connection never reads from or writes to its
+    /// socket and only does framing and dispatching.
+    ///
+    /// Embedder owning the I/O (e.g. using `io_uring` or custom poller) passes received data to
+    /// `feed_bytes` and writes data taken with `drain_output`. All sent messages are queued until
+    /// drained. `read_events`, `process_events` and `roundtrip` fail in this mode.
+    pub fn set_detached_io(&mut self, detached: bool) {
+        self.bundle.set_detached(detached);
+    }
+
+    /// Checks if detached I/O mode is enabled. See `set_detached_io`.
+    pub fn is_detached_io(&self) -> bool {
+        self.bundle.is_detached()
+    }
+
+    /// Dispatches data received by embedder in detached I/O mode (see `set_detached_io`) along
+    /// with received file descriptors. Connection takes ownership of the descriptors. Incomplete
+    /// messages are kept until the rest of them is fed.
+    ///
+    /// Empty `bytes` mean that the peer closed the connection.
+    ///
+    /// Returns error if detached I/O mode is not enabled. Descriptors which are not fed (e.g. on
+    /// error or when the connection ended) are closed.
+    pub fn feed_bytes(&mut self,
+                      bytes: &[u8],
+                      fds: &[RawFd])
+                      -> Result<DispatchReport, SkylaneError> {
+        if !self.bundle.is_detached() {
+            close_fds(fds.iter());
+            return Err(SkylaneError::Other("Feeding enabled only in detached I/O mode".to_owned()));
+        }
+        if let Err(err) = self.check_state() {
+            close_fds(fds.iter());
+            return Err(err);
+        }
+        if bytes.is_empty() {
+            close_fds(fds.iter());
+            self.notify_disconnect(DisconnectReason::Closed);
+            return Ok(DispatchReport::default());
+        }
+
+        self.bundle.get_socket().update_stats(|stats| {
+            stats.bytes_received += bytes.len() as u64;
+            stats.fds_received += fds.len() as u64;
+        });
+        let fed = self.reader.feed(bytes, fds.iter().cloned().collect());
+        self.handle_fd_overflow(fed)?;
+        let mut report = self.dispatch_pending()?;
+        report.bytes_read = bytes.len();
+        Ok(report)
+    }
+
+    /// Takes all messages queued for sending in detached I/O mode (see `set_detached_io`) so the
+    /// embedder can write them.
+    ///
+    /// See `Bundle::drain_output`.
+    pub fn drain_output(&mut self) -> (Vec<u8>, Vec<RawFd>, Vec<OwnedFd>) {
+        let (bytes, fds, owned) = self.bundle.drain_output();
+        let num_messages = MessageIter::new(&bytes).count() as u64;
+        self.bundle.get_socket().update_stats(|stats| {
+            stats.messages_sent += num_messages;
+            stats.bytes_sent += bytes.len() as u64;
+            stats.fds_sent += fds.len() as u64;
+        });
+        (bytes, fds, owned)
+    }
+
+    /// Reads data from socket and stores it for dispatching by `dispatch_pending`. Returns number
+    /// of bytes read.
+    ///
+    /// This method does not coordinate with other threads. If data is read from many threads
+    /// `prepare_read` should be used instead.
+    pub fn read_events(&mut self) -> Result<usize, SkylaneError> {
+        self.check_state()?;
+        if self.bundle.is_detached() {
+            return Err(SkylaneError::Other("Reading disabled in detached I/O mode".to_owned()));
+        }
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            rate_limiter.check_pending_bytes(self.bundle.get_socket().get_pending_bytes()?)?;
+        }
+        let result = self.reader.read();
+        let result = self.handle_fd_overflow(result);
+        let is_closed = match result {
+            Ok(0) => true,
+            Err(ref err) => err.is_disconnected(),
+            Ok(_) => false,
+        };
+        if is_closed && self.reconnect.is_none() {
+            self.notify_disconnect(DisconnectReason::Closed);
+        }
+        result
+    }
+
+    /// Announces intention to read from socket. Returns `None` if there are messages which should
+    /// be dispatched first.
+    ///
+    /// See `Reader::prepare_read`.
+    pub fn prepare_read(&self) -> Option<ReadIntent> {
+        self.reader.prepare_read()
+    }
+
+    /// Returns `Reader` which can be used to read from socket in other threads.
+    pub fn get_reader(&self) -> Reader {
+        self.reader.clone()
+    }
+
+    /// Pauses dispatching. Incoming data are still read and buffered but handlers are not called
+    /// until `resume` is called.
+    ///
+    /// Useful when server needs to hold off requests of a client e.g. during modal operation.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resumes dispatching paused with `pause`. Buffered messages are dispatched by next call to
+    /// `dispatch_pending` or `process_events`.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Checks if dispatching is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Dispatches all complete messages read earlier by `read_events`. Incomplete messages are
+    /// kept until the rest of them is read.
+    ///
+    /// If processing stopped on failure (see `DispatchPolicy`) remaining messages are kept pending.
+    /// If dispatching is paused (see `pause`) nothing is dispatched.
+    ///
+    /// Returns `SkylaneError::Reentrancy` if called from a handler beyond allowed depth (see
+    /// `set_max_dispatch_depth`).
+    pub fn dispatch_pending(&mut self) -> Result<DispatchReport, SkylaneError> {
+        self.check_state()?;
+        if self.paused {
+            return Ok(DispatchReport::default());
+        }
+
+        let depth = self.bundle.get_dispatch_depth();
+        if depth >= self.max_dispatch_depth {
+            return Err(SkylaneError::Reentrancy {
+                           object_id: None,
+                           depth,
+                       });
+        }
+
+        let _guard = self.bundle.enter_dispatch();
+        self.dispatch_messages()
+    }
 
-            self.process_event(&header, &mut bytes_buf, &mut fds_buf)?;
-            position += header.size as usize;
+    /// Sends `wl_display.sync` request and processes events until server responds to it. After
+    /// this call all requests sent earlier are processed by server.
+    ///
+    /// This method is meant to be used on client side.
+    pub fn roundtrip(&mut self) -> Result<(), SkylaneError> {
+        self.check_state()?;
+        if self.bundle.is_detached() {
+            return Err(SkylaneError::Other("Roundtrip disabled in detached I/O mode".to_owned()));
+        }
+        let callback = self.sync()?;
+        while !callback.is_done() {
+            self.bundle.get_socket().wait_readable(None)?;
+            let report = self.process_events_with_report()?;
+            if let Some(failure) = report.failures.into_iter().next() {
+                return Err(failure.error);
+            }
+            if report.bytes_read == 0 {
+                return Err(SkylaneError::Other("Connection closed during roundtrip".to_owned()));
+            }
         }
         Ok(())
     }
@@ -189,28 +1149,318 @@ impl Connection {
 
 /// Private methods.
 impl Connection {
+    /// Dispatches all complete messages read earlier. See `dispatch_pending`.
+    fn dispatch_messages(&mut self) -> Result<DispatchReport, SkylaneError> {
+        let (bytes, mut in_fds) = self.reader.take_incoming();
+        if self.bundle.has_posted_error() {
+            // Client is already dead for us. Nothing it sends matters anymore.
+            close_fds(in_fds.iter());
+            return Ok(DispatchReport::default());
+        }
+
+        let mut fds = Vec::with_capacity(4 * in_fds.len());
+        for fd in in_fds.iter() {
+            fds.write_i32::<NativeEndian>(*fd)?;
+        }
+
+        let mut fds_buf = Cursor::new(&fds[..]);
+        let mut report = DispatchReport::default();
+        let mut position = 0;
+        let mut result = Ok(());
+        let mut messages = MessageIter::new(&bytes);
+        while let Some(item) = messages.next() {
+            let (header, args) = match item {
+                Ok(message) => message,
+                Err(err) => {
+                    result = Err(match Endianness::native().read_header(&bytes[position..]) {
+                                     Some(header) if self.error_context => {
+                                         // Size is malformed; attach everything left.
+                                         err.with_message_context(header, &bytes[position..])
+                                     }
+                                     _ => err,
+                                 });
+                    position = bytes.len();
+                    break;
+                }
+            };
+
+            let end = messages.get_position();
+            self.last_activity = Instant::now();
+
+            if let Some(ref mut rate_limiter) = self.rate_limiter {
+                if let Err(err) = rate_limiter.count_message() {
+                    result = Err(err);
+                    break;
+                }
+            }
+
+            self.bundle.record_message(Direction::Incoming, header, args);
+            let socket = self.bundle.get_socket();
+            socket.log(|| {
+                let name = self.describe_message(&header, None)
+                    .map(|name| name + " ")
+                    .unwrap_or_default();
+                LogRecord::for_message(LogLevel::Trace,
+                                       Direction::Incoming,
+                                       &header,
+                                       format!("Received {}{} bytes", name, header.size))
+            });
+
+            if self.is_remote_error(&header) {
+                let mut message = Message::new(header, args, &mut fds_buf);
+                result = self.read_remote_error(&mut message);
+                position = bytes.len();
+                break;
+            }
+
+            let object_id = ObjectId::new(header.object_id);
+            let decision = match self.request_filter {
+                Some(ref mut filter) => {
+                    let interface = self.bundle.get_interface_meta(object_id).map(|meta| meta.name);
+                    filter(&header, interface, header.opcode)
+                }
+                None => FilterDecision::Allow,
+            };
+
+            let is_denied = match decision {
+                FilterDecision::Allow => false,
+                FilterDecision::Deny => true,
+                FilterDecision::Error { code, message } => {
+                    let interface = self.bundle
+                        .get_interface_meta(object_id)
+                        .map_or(display::INTERFACE, |meta| meta.name);
+                    let error = SkylaneError::Protocol {
+                        interface,
+                        object_id,
+                        code,
+                        message,
+                    };
+                    self.post_protocol_error(&error)?;
+                    report.posted_error = Some(error);
+                    position = bytes.len();
+                    close_fds(in_fds.iter().skip(fds_buf.position() as usize / 4));
+                    fds_buf.set_position(fds.len() as u64);
+                    break;
+                }
+            };
+
+            let is_zombie = self.bundle.is_zombie(object_id);
+            let fds_start = fds_buf.position() as usize / 4;
+
+            let dispatch_result = if is_denied {
+                socket.log(|| {
+                    LogRecord::for_message(LogLevel::Debug,
+                                           Direction::Incoming,
+                                           &header,
+                                           "Message denied by filter".to_owned())
+                });
+                Ok(())
+            } else {
+                let mut message = Message::new(header, args, &mut fds_buf);
+                message.set_side(self.bundle.get_side());
+                message.set_utf8_policy(self.bundle.get_utf8_policy());
+                self.process_event(&mut message)
+            };
+
+            // File descriptors of dropped messages not taken by handler would leak.
+            if is_zombie || is_denied || dispatch_result.is_err() {
+                if let Some(num_fds) = self.bundle.get_incoming_fd_count(object_id, header.opcode) {
+                    let fds_taken = fds_buf.position() as usize / 4;
+                    let fds_end = std::cmp::min(fds_start + num_fds, in_fds.len());
+                    if fds_taken < fds_end {
+                        close_fds(in_fds.iter().skip(fds_taken).take(fds_end - fds_taken));
+                        fds_buf.set_position(4 * fds_end as u64);
+                    }
+                }
+            }
+
+            socket.update_stats(|stats| {
+                stats.messages_received += 1;
+                if dispatch_result.is_err() {
+                    stats.dispatch_errors += 1;
+                }
+            });
+            let name = match dispatch_result {
+                Ok(()) => None,
+                Err(ref err) => self.describe_message(&header, Some(err)),
+            };
+            if let Err(ref err) = dispatch_result {
+                socket.log(|| {
+                    let name = name.clone().map(|name| name + " ").unwrap_or_default();
+                    LogRecord::for_message(LogLevel::Warning,
+                                           Direction::Incoming,
+                                           &header,
+                                           format!("Dispatching {}failed: {:?}", name, err))
+                });
+            }
+            position = end;
+
+            match dispatch_result {
+                Ok(()) if is_denied => report.num_filtered += 1,
+                Ok(()) => report.num_dispatched += 1,
+                Err(SkylaneError::WrongObject { object_id }) if self.strict => {
+                    let error = SkylaneError::Protocol {
+                        interface: display::INTERFACE,
+                        object_id,
+                        code: DisplayError::InvalidObject.get_code(),
+                        message: format!("invalid object {}", object_id),
+                    };
+                    self.post_protocol_error(&error)?;
+                    report.posted_error = Some(error);
+                    position = bytes.len();
+                    close_fds(in_fds.iter().skip(fds_buf.position() as usize / 4));
+                    fds_buf.set_position(fds.len() as u64);
+                    break;
+                }
+                Err(error) => {
+                    let error = if self.error_context {
+                        let start = end - header.size as usize;
+                        error.with_message_context(header, &bytes[start..end])
+                    } else {
+                        error
+                    };
+                    report.failures.push(DispatchFailure {
+                                             header,
+                                             name,
+                                             error,
+                                         });
+                    if self.dispatch_policy == DispatchPolicy::FailFast {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Keep what was not dispatched for later.
+        let num_used_fds = fds_buf.position() as usize / 4;
+        in_fds.drain(..num_used_fds);
+        self.reader.return_incoming(&bytes[position..], in_fds);
+
+        result.map(|_| report)
+    }
+
+    /// Returns `SkylaneError::Remote` if server sent `wl_display.error`.
+    fn check_remote_error(&self) -> Result<(), SkylaneError> {
+        match self.get_remote_error() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `SkylaneError::Remote` if server sent `wl_display.error` or `SkylaneError::Closed`
+    /// if the connection ended.
+    fn check_state(&self) -> Result<(), SkylaneError> {
+        self.check_remote_error()?;
+        if self.state() == ConnectionState::Closed {
+            Err(SkylaneError::Closed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks if received message is `wl_display.error` event which should be handled by
+    /// connection. This is the case on client side or if side is not known and no object was
+    /// registered as display.
+    fn is_remote_error(&self, header: &Header) -> bool {
+        let is_client = match self.bundle.get_side() {
+            Some(side) => side == Side::Client,
+            None => self.bundle.get_weak_ref(DISPLAY_ID).is_none(),
+        };
+        is_client && header.object_id == DISPLAY_ID.get_value() &&
+        header.opcode == display::ERROR_OPCODE
+    }
+
+    /// Reads `wl_display.error` event, marks the connection as failed and returns the error.
+    fn read_remote_error(&mut self, message: &mut Message) -> Result<(), SkylaneError> {
+        let object_id = message.next_object()?;
+        let code = message.next_uint()?;
+        let text = message.next_string()?;
+        self.remote_error = Some((object_id, code, text.clone()));
+        self.notify_disconnect(DisconnectReason::ProtocolError {
+                                   object_id,
+                                   code,
+                                   message: text,
+                               });
+        self.check_remote_error()
+    }
+
+    /// Updates state of the connection and calls disconnect handler unless connection already
+    /// ended.
+    fn notify_disconnect(&mut self, reason: DisconnectReason) {
+        self.bundle.notify_disconnect(reason);
+    }
+
+    /// Terminates the connection if `result` is error caused by exceeding limit on pending file
+    /// descriptors with `FdOverflowPolicy::Disconnect` policy.
+    fn handle_fd_overflow<T>(&mut self,
+                             result: Result<T, SkylaneError>)
+                             -> Result<T, SkylaneError> {
+        if let Err(SkylaneError::LimitExceeded { .. }) = result {
+            let policy = self.reader.get_fd_limit().map(|limit| limit.policy);
+            if policy == Some(FdOverflowPolicy::Disconnect) {
+                let _ = self.terminate();
+            }
+        }
+        result
+    }
+
+    /// Returns human-readable name of received message (e.g. `wl_surface.attach`) if interface
+    /// of target object is known either from its metadata or from dispatch `error`.
+    fn describe_message(&self, header: &Header, error: Option<&SkylaneError>) -> Option<String> {
+        let side = self.bundle.get_side().unwrap_or(Side::Server);
+        let meta = self.bundle.get_interface_meta(ObjectId::new(header.object_id));
+        let interface = match (meta, error) {
+            (Some(meta), _) => meta.name,
+            (None, Some(&SkylaneError::WrongOpcode { name, .. })) => name,
+            _ => return None,
+        };
+        match meta.or_else(|| self.bundle.find_interface_meta(interface)) {
+            Some(meta) => Some(names::describe_with_meta(meta, header.opcode, side)),
+            None => Some(names::describe_message(interface, header.opcode, side)),
+        }
+    }
+
+    /// Reconnects and returns report informing about it.
+    fn reconnect_and_report(&mut self) -> Result<DispatchReport, SkylaneError> {
+        self.reconnect()?;
+        Ok(DispatchReport { reconnected: true, ..DispatchReport::default() })
+    }
+
     /// Processes events:
     ///
-    /// 1. searches for handler
-    /// 2. calls `dispatch` method on handler
-    /// 3. handles return code from `dispatch`.
+    /// 1. searches for handler (messages to zombie objects are dropped)
+    /// 2. calls `dispatch_message` method on handler
+    /// 3. handles return code from `dispatch_message`.
     ///
     /// TODO: Remove third step.
-    fn process_event(&mut self,
-                     header: &Header,
-                     mut bytes_buf: &mut Cursor<&[u8]>,
-                     mut fds_buf: &mut Cursor<&[u8]>)
-                     -> Result<(), SkylaneError> {
+    fn process_event(&mut self, message: &mut Message) -> Result<(), SkylaneError> {
+        if self.bundle.is_zombie(message.get_object_id()) {
+            let header = *message.get_header();
+            self.bundle.get_socket().log(|| {
+                LogRecord::for_message(LogLevel::Debug,
+                                       Direction::Incoming,
+                                       &header,
+                                       "Dropped message to zombie object".to_owned())
+            });
+            return Ok(());
+        }
+
         let task = {
-            let object_id = ObjectId::new(header.object_id);
+            let object_id = message.get_object_id();
             let handler_ref = self.bundle.get_handler(object_id)?;
-            let mut handler = handler_ref.borrow_mut();
-            handler.dispatch(&mut self.bundle, &header, bytes_buf, fds_buf)?
+            let mut handler = handler_ref.try_borrow_mut()
+                .map_err(|_| {
+                             SkylaneError::Reentrancy {
+                                 object_id: Some(object_id),
+                                 depth: self.bundle.get_dispatch_depth(),
+                             }
+                         })?;
+            handler.dispatch_message(&mut self.bundle, message)?
         };
 
         match task {
             Task::Create { id, object } => {
-                self.add_object(id, object);
+                self.bundle.add_remote_object(id, object)?;
             }
             Task::Destroy { id } => {
                 self.remove_object(id);
@@ -222,3 +1472,59 @@ impl Connection {
 }
 
 // -------------------------------------------------------------------------------------------------
+
+/// Closes received file descriptors which will not be passed to any handler.
+fn close_fds<'a, I>(fds: I)
+    where I: Iterator<Item = &'a RawFd>
+{
+    for fd in fds {
+        let _ = nix::unistd::close(*fd);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Methods of `Connection` available in this crate but not exported.
+pub trait ConnectionInternal {
+    /// Returns `Bundle` of the connection.
+    fn get_bundle(&self) -> &Bundle;
+
+    /// Appends data to be dispatched as if it was read from socket.
+    fn feed(&self, bytes: &[u8], fds: VecDeque<RawFd>) -> Result<(), SkylaneError>;
+
+    /// Sets maximal number of bytes read from socket at once.
+    fn set_read_buffer_size(&self, size: usize);
+
+    /// Checks if there is at least one complete message read but not dispatched.
+    fn has_pending_messages(&self) -> bool;
+
+    /// Drops all data read but not dispatched and closes received file descriptors.
+    #[cfg(feature = "fuzzing")]
+    fn discard_pending(&self);
+}
+
+impl ConnectionInternal for Connection {
+    fn get_bundle(&self) -> &Bundle {
+        &self.bundle
+    }
+
+    fn feed(&self, bytes: &[u8], fds: VecDeque<RawFd>) -> Result<(), SkylaneError> {
+        self.reader.feed(bytes, fds)
+    }
+
+    fn set_read_buffer_size(&self, size: usize) {
+        self.reader.set_buffer_size(size);
+    }
+
+    fn has_pending_messages(&self) -> bool {
+        self.reader.has_pending_messages()
+    }
+
+    #[cfg(feature = "fuzzing")]
+    fn discard_pending(&self) {
+        let (_, fds) = self.reader.take_incoming();
+        close_fds(fds.iter());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,118 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Passing process credentials (`SCM_CREDENTIALS`) along with messages.
+
+use std::io::{IoSlice, IoSliceMut};
+use std::os::unix::io::RawFd;
+
+use nix;
+use nix::libc;
+use nix::sys::socket;
+
+use sockets::SCM_MAX_FD;
+
+
+// -------------------------------------------------------------------------------------------------
+
+/// Credentials of process which sent message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// Process ID.
+    pub pid: libc::pid_t,
+
+    /// User ID.
+    pub uid: libc::uid_t,
+
+    /// Group ID.
+    pub gid: libc::gid_t,
+}
+
+impl Credentials {
+    /// Returns credentials of current process.
+    pub fn current() -> Self {
+        unsafe {
+            Credentials {
+                pid: libc::getpid(),
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Sends data gathered from `slices` with file descriptors `fds` and given credentials.
+///
+/// Kernel checks the credentials; only privileged processes may send other than their own.
+pub fn send(fd: RawFd,
+            slices: &[&[u8]],
+            fds: &[RawFd],
+            credentials: &Credentials)
+            -> nix::Result<usize> {
+    let iov: Vec<IoSlice> = slices.iter().map(|slice| IoSlice::new(slice)).collect();
+    let ucred = socket::UnixCredentials::from(libc::ucred {
+                                                  pid: credentials.pid,
+                                                  uid: credentials.uid,
+                                                  gid: credentials.gid,
+                                              });
+
+    let mut cmsgs = Vec::with_capacity(2);
+    if !fds.is_empty() {
+        cmsgs.push(socket::ControlMessage::ScmRights(fds));
+    }
+    cmsgs.push(socket::ControlMessage::ScmCredentials(&ucred));
+    socket::sendmsg::<()>(fd, &iov, &cmsgs, socket::MsgFlags::MSG_DONTWAIT, None)
+}
+
+/// Receives data to `bytes`. Returns number of received bytes, received file descriptors and
+/// credentials of the sender if they were passed (requires `SO_PASSCRED` option on the socket).
+pub fn receive(fd: RawFd,
+               bytes: &mut [u8],
+               nonblocking: bool)
+               -> nix::Result<(usize, Vec<RawFd>, Option<Credentials>)> {
+    let mut iov = [IoSliceMut::new(bytes)];
+    let mut control = cmsg_space!([RawFd; SCM_MAX_FD], libc::ucred);
+    let mut flags = socket::MsgFlags::MSG_CMSG_CLOEXEC;
+    if nonblocking {
+        flags |= socket::MsgFlags::MSG_DONTWAIT;
+    }
+    let msg = socket::recvmsg::<()>(fd, &mut iov, Some(&mut control), flags)?;
+
+    let mut received = Vec::new();
+    let mut credentials = None;
+    for cmsg in msg.cmsgs()? {
+        match cmsg {
+            socket::ControlMessageOwned::ScmRights(fds) => {
+                received.extend_from_slice(&fds);
+            }
+            socket::ControlMessageOwned::ScmCredentials(ucred) => {
+                credentials = Some(Credentials {
+                                       pid: ucred.pid(),
+                                       uid: ucred.uid(),
+                                       gid: ucred.gid(),
+                                   });
+            }
+            _ => {}
+        }
+    }
+
+    Ok((msg.bytes, received, credentials))
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -18,14 +18,19 @@
 //! Common definitions for server and client parts of `skylane` crate.
 
 use std;
-use std::error::Error;
 
 use nix;
+use nix::errno::Errno;
 
 use object::{Object, ObjectId};
 
 // -------------------------------------------------------------------------------------------------
 
+/// Maximal number of bytes of message attached to error (see `SkylaneError::with_message_context`).
+pub const MAX_CONTEXT_BYTES: usize = 256;
+
+// -------------------------------------------------------------------------------------------------
+
 /// Enumeration for all `skylane` errors.
 #[derive(Debug)]
 pub enum SkylaneError {
@@ -33,12 +38,16 @@ pub enum SkylaneError {
     IO {
         /// Description of the error.
         description: String,
+        /// Kind of the original error.
+        kind: std::io::ErrorKind,
     },
 
     /// Wrapper for `nix::Error`.
     Socket {
         /// Description of the error.
         description: String,
+        /// Error number if the original error was caused by system call.
+        errno: Option<Errno>,
     },
 
     /// Error emitted when trying to access not existing object.
@@ -52,30 +61,257 @@ pub enum SkylaneError {
         /// Name of interface.
         name: &'static str,
         /// Referred object ID.
-        object_id: u32,
+        object_id: ObjectId,
         /// Requested method.
         opcode: u16,
     },
 
+    /// Error defined by protocol, emitted when peer violated protocol. On server side it should be
+    /// posted to the client as `wl_display.error` event.
+    Protocol {
+        /// Name of interface.
+        interface: &'static str,
+        /// ID of object on which the error occurred.
+        object_id: ObjectId,
+        /// Interface-specific error code.
+        code: u32,
+        /// Human-readable description of the error.
+        message: String,
+    },
+
+    /// Error sent by server in `wl_display.error` event. After receiving it the client connection
+    /// is unusable.
+    Remote {
+        /// ID of object on which the error occurred.
+        object_id: ObjectId,
+        /// Interface-specific error code.
+        code: u32,
+        /// Human-readable description of the error.
+        message: String,
+    },
+
+    /// Error emitted when peer exceeded limits set for the connection.
+    LimitExceeded {
+        /// Description of the error.
+        description: String,
+    },
+
+    /// Error emitted when sending message not available in version of interface bound for the
+    /// object (see `Connection::set_version_check`).
+    UnsupportedVersion {
+        /// ID of the object.
+        object_id: ObjectId,
+        /// Opcode of the message.
+        opcode: u16,
+        /// Version since which the message is available.
+        since: u32,
+        /// Version bound for the object.
+        version: u32,
+    },
+
+    /// Error emitted when dispatching re-entered connection or object which is already being
+    /// dispatched (see `Connection::set_max_dispatch_depth`).
+    Reentrancy {
+        /// ID of object whose handler is already running if the error concerns single object.
+        object_id: Option<ObjectId>,
+        /// Depth of nested dispatching when the error occurred.
+        depth: usize,
+    },
+
+    /// Error emitted when all object IDs in range allocated by given side of connection are in use.
+    IdsExhausted {
+        /// Side of connection whose range is exhausted.
+        side: Side,
+    },
+
+    /// Error emitted when using connection which already ended (see `Connection::state`).
+    Closed,
+
+    /// Error which occurred while parsing or dispatching received message, along with the message
+    /// (see `Connection::set_error_context`).
+    Context {
+        /// Header of the message.
+        header: Header,
+        /// Raw bytes of the message including header, as hexadecimal 32-bit words, truncated to
+        /// `MAX_CONTEXT_BYTES`.
+        bytes: String,
+        /// The original error.
+        error: Box<SkylaneError>,
+    },
+
     /// Other errors.
     Other(String),
 }
 
+impl SkylaneError {
+    /// Wraps the error with header and raw bytes of message which caused it. Only the first
+    /// `MAX_CONTEXT_BYTES` bytes are kept; longer messages are marked as truncated.
+    pub fn with_message_context(self, header: Header, bytes: &[u8]) -> Self {
+        let kept = std::cmp::min(bytes.len(), MAX_CONTEXT_BYTES);
+        let mut hex = String::with_capacity(kept * 9 / 4 + 4);
+        for (i, chunk) in bytes[..kept].chunks(4).enumerate() {
+            if i > 0 {
+                hex.push(' ');
+            }
+            for byte in chunk {
+                hex.push_str(&format!("{:02x}", byte));
+            }
+        }
+        if kept < bytes.len() {
+            hex.push_str(" ...");
+        }
+
+        SkylaneError::Context {
+            header,
+            bytes: hex,
+            error: Box::new(self),
+        }
+    }
+
+    /// Returns the original error if the error was wrapped with context of message, otherwise the
+    /// error itself.
+    pub fn get_root_cause(&self) -> &SkylaneError {
+        match *self {
+            SkylaneError::Context { ref error, .. } => error.get_root_cause(),
+            _ => self,
+        }
+    }
+
+    /// Returns error number if the error was caused by failed system call.
+    pub fn get_errno(&self) -> Option<Errno> {
+        match *self.get_root_cause() {
+            SkylaneError::Socket { errno, .. } => errno,
+            _ => None,
+        }
+    }
+
+    /// Checks if the error means operation would block (e.g. there is no data to read on
+    /// non-blocking socket). Such error is not fatal and operation may be retried later.
+    pub fn is_would_block(&self) -> bool {
+        match *self.get_root_cause() {
+            SkylaneError::IO { kind, .. } => kind == std::io::ErrorKind::WouldBlock,
+            SkylaneError::Socket { errno: Some(errno), .. } => errno == Errno::EAGAIN,
+            _ => false,
+        }
+    }
+
+    /// Checks if the error means peer disconnected.
+    pub fn is_disconnected(&self) -> bool {
+        match *self.get_root_cause() {
+            SkylaneError::IO { kind, .. } => {
+                kind == std::io::ErrorKind::BrokenPipe ||
+                kind == std::io::ErrorKind::ConnectionReset ||
+                kind == std::io::ErrorKind::ConnectionAborted ||
+                kind == std::io::ErrorKind::NotConnected
+            }
+            SkylaneError::Socket { errno: Some(errno), .. } => {
+                errno == Errno::EPIPE || errno == Errno::ECONNRESET ||
+                errno == Errno::ECONNABORTED || errno == Errno::ENOTCONN
+            }
+            SkylaneError::Closed => true,
+            _ => false,
+        }
+    }
+}
+
 impl std::convert::From<std::io::Error> for SkylaneError {
     fn from(error: std::io::Error) -> Self {
-        SkylaneError::IO { description: error.description().to_owned() }
+        SkylaneError::IO {
+            description: error.to_string(),
+            kind: error.kind(),
+        }
     }
 }
 
 impl std::convert::From<nix::Error> for SkylaneError {
     fn from(error: nix::Error) -> Self {
-        SkylaneError::Socket { description: error.description().to_owned() }
+        SkylaneError::Socket {
+            description: error.desc().to_owned(),
+            errno: Some(error),
+        }
     }
 }
 
 impl std::convert::From<std::env::VarError> for SkylaneError {
     fn from(error: std::env::VarError) -> Self {
-        SkylaneError::Other(error.description().to_owned())
+        SkylaneError::Other(error.to_string())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Side of connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// Client side.
+    Client,
+
+    /// Server side.
+    Server,
+}
+
+impl Side {
+    /// Returns the other side.
+    pub fn peer(&self) -> Side {
+        match *self {
+            Side::Client => Side::Server,
+            Side::Server => Side::Client,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Error codes of `wl_display.error` event defined by the core protocol.
+///
+/// These codes are global and may be posted for object of any interface. Codes specific to other
+/// interfaces are defined by their protocols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayError {
+    /// Server could not find object or ID is not valid.
+    InvalidObject = 0,
+
+    /// Method does not exist on the object or its arguments are invalid.
+    InvalidMethod = 1,
+
+    /// Server is out of memory.
+    NoMemory = 2,
+
+    /// Implementation error in compositor.
+    Implementation = 3,
+}
+
+impl DisplayError {
+    /// Returns code of the error sent in `wl_display.error` event.
+    pub fn get_code(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Returns name of the error as defined in protocol.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            DisplayError::InvalidObject => "invalid_object",
+            DisplayError::InvalidMethod => "invalid_method",
+            DisplayError::NoMemory => "no_memory",
+            DisplayError::Implementation => "implementation",
+        }
+    }
+
+    /// Returns error for given code or `None` if code is not defined by core protocol.
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(DisplayError::InvalidObject),
+            1 => Some(DisplayError::InvalidMethod),
+            2 => Some(DisplayError::NoMemory),
+            3 => Some(DisplayError::Implementation),
+            _ => None,
+        }
+    }
+}
+
+impl std::convert::From<DisplayError> for u32 {
+    fn from(error: DisplayError) -> Self {
+        error.get_code()
     }
 }
 
@@ -83,7 +319,7 @@ impl std::convert::From<std::env::VarError> for SkylaneError {
 
 /// Header of Wayland message.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Header {
     /// ID of the referred object.
     pub object_id: u32,
@@ -97,8 +333,113 @@ pub struct Header {
 
 // -------------------------------------------------------------------------------------------------
 
-/// Type alias for logging function.
-pub type Logger = Option<fn(String) -> ()>;
+/// Severity of log record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Failures.
+    Error,
+
+    /// Unexpected situations which do not break the connection.
+    Warning,
+
+    /// Important events.
+    Info,
+
+    /// Information useful for debugging.
+    Debug,
+
+    /// Traces of all sent and received messages.
+    Trace,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Direction of message described by log record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Message received from peer.
+    Incoming,
+
+    /// Message sent to peer.
+    Outgoing,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Log record passed to `Logger`.
+///
+/// Fields other than `level` and `text` are filled in only if they are relevant.
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    /// Severity.
+    pub level: LogLevel,
+
+    /// Direction of described message.
+    pub direction: Option<Direction>,
+
+    /// ID of the object the described message is addressed to.
+    pub object_id: Option<ObjectId>,
+
+    /// Opcode of the described message.
+    pub opcode: Option<u16>,
+
+    /// Human-readable description.
+    pub text: String,
+}
+
+impl LogRecord {
+    /// Constructs new `LogRecord` not related to any message.
+    pub fn new(level: LogLevel, text: String) -> Self {
+        LogRecord {
+            level,
+            direction: None,
+            object_id: None,
+            opcode: None,
+            text,
+        }
+    }
+
+    /// Constructs new `LogRecord` describing message with given header.
+    pub fn for_message(level: LogLevel,
+                       direction: Direction,
+                       header: &Header,
+                       text: String)
+                       -> Self {
+        LogRecord {
+            level,
+            direction: Some(direction),
+            object_id: Some(ObjectId::new(header.object_id)),
+            opcode: Some(header.opcode),
+            text,
+        }
+    }
+}
+
+impl std::fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.level)?;
+        match self.direction {
+            Some(Direction::Incoming) => write!(f, " <-")?,
+            Some(Direction::Outgoing) => write!(f, " ->")?,
+            None => {}
+        }
+        if let Some(object_id) = self.object_id {
+            write!(f, " {}", object_id)?;
+        }
+        if let Some(opcode) = self.opcode {
+            write!(f, ".{}", opcode)?;
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Logging function. Closures may capture log target.
+pub type LogFn = dyn Fn(&LogRecord) + Send + Sync;
+
+/// Type alias for optional logging function.
+pub type Logger = Option<Box<LogFn>>;
 
 // -------------------------------------------------------------------------------------------------
 
@@ -112,7 +453,7 @@ pub enum Task {
         /// New object ID.
         id: ObjectId,
         /// Object to be added.
-        object: Box<Object>,
+        object: Box<dyn Object>,
     },
 
     /// Requests destruction of object.
@@ -0,0 +1,176 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Client-side helpers for connecting to display and discovering globals.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use defs::{Side, SkylaneError, Task};
+use bundle::{Bundle, BundleInternal};
+use connection::{Connection, ConnectionInternal};
+use display::{self, ClientDisplay};
+use message::Message;
+use object::{Object, ObjectId, DISPLAY_ID};
+use sockets::Socket;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Name of `wl_registry` interface.
+pub const INTERFACE: &str = "wl_registry";
+
+// -------------------------------------------------------------------------------------------------
+
+/// Global advertised by server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Global {
+    /// Numeric name of the global.
+    pub name: u32,
+
+    /// Name of interface of the global.
+    pub interface: String,
+
+    /// Maximal version supported by server.
+    pub version: u32,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side handle to `wl_registry`.
+///
+/// Keeps list of globals advertised by server up to date.
+#[derive(Clone)]
+pub struct Registry {
+    id: ObjectId,
+    globals: Rc<RefCell<BTreeMap<u32, Global>>>,
+}
+
+impl Registry {
+    /// Sends `wl_display.get_registry` request and registers object for receiving globals.
+    ///
+    /// Globals are available after server responds - see `Connection::roundtrip`.
+    pub fn new(connection: &mut Connection) -> Result<Registry, SkylaneError> {
+        let id = connection.get_next_available_client_object_id()?;
+        let globals = Rc::new(RefCell::new(BTreeMap::new()));
+        connection.get_bundle()
+            .send_marshalled(DISPLAY_ID, display::GET_REGISTRY_OPCODE, |marshaller| {
+                marshaller.put_object(id);
+            })?;
+        connection.add_object(id, Box::new(ClientRegistry { globals: globals.clone() }));
+        Ok(Registry {
+               id,
+               globals,
+           })
+    }
+
+    /// Returns ID of the registry.
+    pub fn get_id(&self) -> ObjectId {
+        self.id
+    }
+
+    /// Returns currently advertised globals ordered by name.
+    pub fn get_globals(&self) -> Vec<Global> {
+        self.globals.borrow().values().cloned().collect()
+    }
+
+    /// Returns first global with given interface.
+    pub fn find(&self, interface: &str) -> Option<Global> {
+        self.globals.borrow().values().find(|global| global.interface == interface).cloned()
+    }
+
+    /// Sends `wl_registry.bind` request and registers `object` as handler of the newly bound
+    /// global. Returns ID of the new object.
+    pub fn bind(&self,
+                connection: &mut Connection,
+                global: &Global,
+                version: u32,
+                object: Box<dyn Object>)
+                -> Result<ObjectId, SkylaneError> {
+        let id = connection.get_next_available_client_object_id()?;
+        connection.get_bundle()
+            .send_marshalled(self.id, display::REGISTRY_BIND_OPCODE, |marshaller| {
+                marshaller.put_uint(global.name);
+                marshaller.put_string(&global.interface);
+                marshaller.put_uint(version);
+                marshaller.put_object(id);
+            })?;
+        connection.add_object(id, object);
+        Ok(id)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side `wl_registry` object updating list of globals.
+struct ClientRegistry {
+    globals: Rc<RefCell<BTreeMap<u32, Global>>>,
+}
+
+impl Object for ClientRegistry {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        match message.get_opcode() {
+            display::REGISTRY_GLOBAL_OPCODE => {
+                let global = Global {
+                    name: message.next_uint()?,
+                    interface: message.next_string()?,
+                    version: message.next_uint()?,
+                };
+                self.globals.borrow_mut().insert(global.name, global);
+                Ok(Task::None)
+            }
+            display::REGISTRY_GLOBAL_REMOVE_OPCODE => {
+                self.globals.borrow_mut().remove(&message.next_uint()?);
+                Ok(Task::None)
+            }
+            opcode => {
+                Err(SkylaneError::WrongOpcode {
+                        name: INTERFACE,
+                        object_id: message.get_object_id(),
+                        opcode,
+                    })
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Connects to display, registers built-in `wl_display` implementation and waits until server
+/// advertises all globals.
+///
+/// If `path` is `None` default path is used (see `get_default_socket_path`).
+pub fn connect(path: Option<&Path>) -> Result<(Connection, Registry), SkylaneError> {
+    let socket = match path {
+        Some(path) => Socket::connect(path)?,
+        None => Socket::connect_default()?,
+    };
+
+    let mut connection = Connection::new(socket);
+    connection.set_side(Some(Side::Client));
+    connection.add_object(DISPLAY_ID, Box::new(ClientDisplay));
+    connection.set_interface_meta(DISPLAY_ID, &display::META);
+    let registry = Registry::new(&mut connection)?;
+    connection.roundtrip()?;
+    Ok((connection, registry))
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,173 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Definitions related to handling of dispatch errors.
+
+use defs::{Header, SkylaneError};
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Policy deciding what to do when handler fails to dispatch a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DispatchPolicy {
+    /// Stop processing on first error. Remaining received messages are kept pending.
+    #[default]
+    FailFast,
+
+    /// Continue processing remaining messages and collect errors.
+    Continue,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Decision of request filter about received message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Dispatch the message normally.
+    Allow,
+
+    /// Drop the message without dispatching it.
+    Deny,
+
+    /// Drop the message and post `wl_display.error` with given code and description on the object
+    /// the message was sent to. Nothing the peer sends afterwards is dispatched.
+    Error {
+        /// Interface-specific error code.
+        code: u32,
+        /// Human-readable description of the error.
+        message: String,
+    },
+}
+
+/// Filter evaluated for every received message before dispatching it. Takes header of the message,
+/// name of interface of target object (if its `InterfaceMeta` was registered) and opcode.
+///
+/// See `Connection::set_request_filter`.
+pub type RequestFilter = Box<dyn FnMut(&Header, Option<&'static str>, u16) -> FilterDecision>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reason for which the connection ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Peer closed the connection.
+    Closed,
+
+    /// Fatal protocol error was posted to the client (on server side) or received from the server
+    /// (on client side).
+    ProtocolError {
+        /// ID of object on which the error occurred.
+        object_id: ObjectId,
+        /// Interface-specific error code.
+        code: u32,
+        /// Human-readable description of the error.
+        message: String,
+    },
+
+    /// Connection was idle for too long (see `Connection::disconnect_if_idle`).
+    IdleTimeout,
+
+    /// Connection was terminated locally (see `Connection::terminate`).
+    Terminated,
+}
+
+/// State of the connection.
+///
+/// See `Connection::state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connection is being re-established (see `Connection::reconnect`). Objects may be recreated
+    /// by rebinding callback.
+    Connecting,
+
+    /// Connection is usable.
+    Ready,
+
+    /// Fatal protocol error was posted or received. Messages from the peer are not dispatched
+    /// anymore, but queued messages (e.g. the error itself) can still be flushed before the
+    /// connection is closed.
+    Erroring,
+
+    /// Connection ended. Reading, dispatching and sending fail with `SkylaneError::Closed`.
+    Closed,
+}
+
+impl ConnectionState {
+    /// Checks if messages can be exchanged in this state.
+    pub fn is_alive(&self) -> bool {
+        match *self {
+            ConnectionState::Connecting | ConnectionState::Ready => true,
+            ConnectionState::Erroring | ConnectionState::Closed => false,
+        }
+    }
+}
+
+/// Handler called once when the connection ends.
+///
+/// See `Connection::set_disconnect_handler`.
+pub type DisconnectHandler = Box<dyn FnMut(DisconnectReason)>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Information about message which could not be dispatched.
+#[derive(Debug)]
+pub struct DispatchFailure {
+    /// Header of the message.
+    pub header: Header,
+
+    /// Name of the message (e.g. `wl_surface.attach`) if interface of target object is known
+    /// (see `register_interface`).
+    pub name: Option<String>,
+
+    /// Error returned while dispatching.
+    pub error: SkylaneError,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Summary of processing received messages.
+#[derive(Debug, Default)]
+pub struct DispatchReport {
+    /// Number of bytes read from socket.
+    pub bytes_read: usize,
+
+    /// Number of successfully dispatched messages.
+    pub num_dispatched: usize,
+
+    /// Number of messages dropped by request filter (see `Connection::set_request_filter`).
+    pub num_filtered: usize,
+
+    /// Messages which failed to be dispatched.
+    pub failures: Vec<DispatchFailure>,
+
+    /// `true` if server disconnected and connection was re-established (see
+    /// `Connection::set_reconnect_policy`).
+    pub reconnected: bool,
+
+    /// Fatal error posted to the client in strict mode (see `Connection::set_strict`).
+    pub posted_error: Option<SkylaneError>,
+}
+
+impl DispatchReport {
+    /// Checks if all messages were dispatched successfully.
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,177 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Built-in implementations of `wl_display` for server and client side.
+
+use defs::{DisplayError, Side, SkylaneError, Task};
+use bundle::Bundle;
+use message::Message;
+use meta::{InterfaceMeta, MessageMeta};
+use object::{Object, ObjectId};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Name of `wl_display` interface.
+pub const INTERFACE: &str = "wl_display";
+
+/// Opcode of `wl_display.sync` request.
+pub const SYNC_OPCODE: u16 = 0;
+
+/// Opcode of `wl_display.get_registry` request.
+pub const GET_REGISTRY_OPCODE: u16 = 1;
+
+/// Opcode of `wl_display.error` event.
+pub const ERROR_OPCODE: u16 = 0;
+
+/// Opcode of `wl_display.delete_id` event.
+pub const DELETE_ID_OPCODE: u16 = 1;
+
+/// Opcode of `wl_registry.global` event.
+pub const REGISTRY_GLOBAL_OPCODE: u16 = 0;
+
+/// Opcode of `wl_registry.global_remove` event.
+pub const REGISTRY_GLOBAL_REMOVE_OPCODE: u16 = 1;
+
+/// Opcode of `wl_registry.bind` request.
+pub const REGISTRY_BIND_OPCODE: u16 = 0;
+
+/// Opcode of `wl_callback.done` event.
+pub const CALLBACK_DONE_OPCODE: u16 = 0;
+
+/// Metadata of `wl_display` interface.
+pub static META: InterfaceMeta = InterfaceMeta {
+    name: INTERFACE,
+    version: 1,
+    requests: &[MessageMeta { name: "sync", signature: "n" },
+                MessageMeta { name: "get_registry", signature: "n" }],
+    events: &[MessageMeta { name: "error", signature: "ous" },
+              MessageMeta { name: "delete_id", signature: "u" }],
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if object ID created by given `side` of connection is in range allocated by that side.
+/// Returns `wl_display.invalid_object` protocol error otherwise.
+pub fn check_id_range(id: ObjectId, side: Side) -> Result<(), SkylaneError> {
+    if id.is_in_range_of(side) {
+        Ok(())
+    } else {
+        Err(SkylaneError::Protocol {
+                interface: INTERFACE,
+                object_id: id,
+                code: DisplayError::InvalidObject.get_code(),
+                message: format!("ID {} is not in {:?} range", id, side),
+            })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Type of function creating `wl_registry` object with given ID on client request.
+///
+/// The function may send `wl_registry.global` events for advertised globals. Returned object will
+/// be registered by `DisplayObject`.
+pub type RegistryFactory = Box<dyn FnMut(&mut Bundle, ObjectId)
+                                         -> Result<Box<dyn Object>, SkylaneError>>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Server-side implementation of `wl_display`.
+///
+/// Handles `sync` request by sending `wl_callback.done` and `get_registry` request by creating
+/// registry using provided factory. Errors are posted using `Bundle::post_error` and destruction
+/// of client objects is confirmed by `Bundle::remove_object`.
+pub struct DisplayObject {
+    registry_factory: RegistryFactory,
+}
+
+impl DisplayObject {
+    /// Constructs new `DisplayObject`.
+    pub fn new(registry_factory: RegistryFactory) -> Self {
+        DisplayObject { registry_factory }
+    }
+}
+
+impl Object for DisplayObject {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        match message.get_opcode() {
+            SYNC_OPCODE => {
+                let callback_id = message.next_new_id()?;
+                let serial = bundle.next_serial();
+                bundle.fire_callback(callback_id, serial)?;
+                Ok(Task::None)
+            }
+            GET_REGISTRY_OPCODE => {
+                let registry_id = message.next_new_id()?;
+                let registry = (self.registry_factory)(bundle, registry_id)?;
+                bundle.add_remote_object(registry_id, registry)?;
+                Ok(Task::None)
+            }
+            opcode => {
+                Err(SkylaneError::WrongOpcode {
+                        name: INTERFACE,
+                        object_id: message.get_object_id(),
+                        opcode,
+                    })
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side implementation of `wl_display`.
+///
+/// Removes objects confirmed by `delete_id` event and translates `error` event to
+/// `SkylaneError::Remote`.
+///
+/// Client-side `Connection` handles `error` event itself (see `Connection::get_remote_error`), so
+/// registering this object is needed only to handle `delete_id`.
+pub struct ClientDisplay;
+
+impl Object for ClientDisplay {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        match message.get_opcode() {
+            ERROR_OPCODE => {
+                Err(SkylaneError::Remote {
+                        object_id: message.next_object()?,
+                        code: message.next_uint()?,
+                        message: message.next_string()?,
+                    })
+            }
+            DELETE_ID_OPCODE => {
+                bundle.confirm_delete(ObjectId::new(message.next_uint()?));
+                Ok(Task::None)
+            }
+            opcode => {
+                Err(SkylaneError::WrongOpcode {
+                        name: INTERFACE,
+                        object_id: message.get_object_id(),
+                        opcode,
+                    })
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,165 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Byte order of messages on the wire.
+//!
+//! Wayland messages are encoded in native byte order of the machine. Header consists of two 32-bit
+//! words: ID of the object and word with message size in upper and opcode in lower 16 bits. The
+//! connection always uses native byte order, while tools analyzing captures made on other machines
+//! may use `Endianness` explicitly.
+
+use std;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use defs::{Header, SkylaneError};
+use marshal::{Marshaller, HEADER_SIZE};
+use message::MessageIter;
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+// Fails to compile if in-memory size of `Header` differs from size of header on the wire.
+const _: () = assert!(HEADER_SIZE == std::mem::size_of::<Header>());
+
+// -------------------------------------------------------------------------------------------------
+
+/// Byte order of encoded messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// Least significant byte first.
+    Little,
+
+    /// Most significant byte first.
+    Big,
+}
+
+impl Endianness {
+    /// Returns byte order of the machine the code is running on.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+
+    /// Reads 32-bit unsigned value from the beginning of `bytes`.
+    ///
+    /// Panics if `bytes` are shorter than 4 bytes.
+    pub fn read_u32(&self, bytes: &[u8]) -> u32 {
+        match *self {
+            Endianness::Little => LittleEndian::read_u32(bytes),
+            Endianness::Big => BigEndian::read_u32(bytes),
+        }
+    }
+
+    /// Reads 32-bit signed value from the beginning of `bytes`.
+    ///
+    /// Panics if `bytes` are shorter than 4 bytes.
+    pub fn read_i32(&self, bytes: &[u8]) -> i32 {
+        self.read_u32(bytes) as i32
+    }
+
+    /// Writes 32-bit unsigned value at the beginning of `bytes`.
+    ///
+    /// Panics if `bytes` are shorter than 4 bytes.
+    pub fn write_u32(&self, bytes: &mut [u8], value: u32) {
+        match *self {
+            Endianness::Little => LittleEndian::write_u32(bytes, value),
+            Endianness::Big => BigEndian::write_u32(bytes, value),
+        }
+    }
+
+    /// Writes 32-bit signed value at the beginning of `bytes`.
+    ///
+    /// Panics if `bytes` are shorter than 4 bytes.
+    pub fn write_i32(&self, bytes: &mut [u8], value: i32) {
+        self.write_u32(bytes, value as u32)
+    }
+
+    /// Reads message header from the beginning of `bytes`. Returns `None` if `bytes` are shorter
+    /// than header.
+    pub fn read_header(&self, bytes: &[u8]) -> Option<Header> {
+        if bytes.len() >= HEADER_SIZE {
+            let word = self.read_u32(&bytes[4..8]);
+            Some(Header {
+                     object_id: self.read_u32(&bytes[0..4]),
+                     opcode: (word & 0xffff) as u16,
+                     size: (word >> 16) as u16,
+                 })
+        } else {
+            None
+        }
+    }
+
+    /// Writes message header at the beginning of `bytes`.
+    ///
+    /// Panics if `bytes` are shorter than header.
+    pub fn write_header(&self, header: &Header, bytes: &mut [u8]) {
+        self.write_u32(&mut bytes[0..4], header.object_id);
+        self.write_u32(&mut bytes[4..8],
+                       ((header.size as u32) << 16) | (header.opcode as u32));
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if `Marshaller` and `MessageIter` agree with each other and with `Endianness::native` on
+/// layout of message header.
+///
+/// Meant to be called from tests of crates porting the wire format to new platforms.
+pub fn check_native_endianness() -> Result<(), SkylaneError> {
+    let expected = Header {
+        object_id: 0x01020304,
+        opcode: 0x0506,
+        size: (HEADER_SIZE + 4) as u16,
+    };
+
+    let mut marshaller = Marshaller::new(ObjectId::new(expected.object_id), expected.opcode);
+    marshaller.put_uint(0x0708090a);
+    let (bytes, _) = marshaller.finish()?;
+
+    let mut encoded = [0; HEADER_SIZE];
+    Endianness::native().write_header(&expected, &mut encoded);
+    if bytes[..HEADER_SIZE] != encoded {
+        return Err(SkylaneError::Other(format!("Marshalled header {:?} differs from {:?} encoded \
+                                                in native byte order",
+                                               &bytes[..HEADER_SIZE],
+                                               encoded)));
+    }
+
+    match MessageIter::new(&bytes).next() {
+        Some(Ok((header, args))) => {
+            if header != expected {
+                return Err(SkylaneError::Other(format!("Decoded header {:?} differs from \
+                                                        marshalled {:?}",
+                                                       header,
+                                                       expected)));
+            }
+            if Endianness::native().read_u32(args) != 0x0708090a {
+                return Err(SkylaneError::Other(format!("Decoded arguments {:?} differ from \
+                                                        marshalled ones",
+                                                       args)));
+            }
+        }
+        Some(Err(err)) => return Err(err),
+        None => return Err(SkylaneError::Other("Marshalled message not decoded".to_owned())),
+    }
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,300 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Minimal event loop for simple servers.
+
+use std;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::libc;
+
+use defs::SkylaneError;
+use connection::{Connection, ConnectionInternal};
+use dispatch::DispatchReport;
+use multiplex::{ConnectionSet, ConnectionSetInternal};
+use sockets::{DisplaySocket, Socket};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Callbacks invoked by `EventLoop`.
+pub trait LoopHandler {
+    /// Creates connection for newly accepted client. Returning `None` drops the client.
+    fn accept(&mut self, socket: Socket) -> Option<Connection>;
+
+    /// Called after events of connection with given key were processed.
+    fn dispatched(&mut self,
+                  _key: usize,
+                  _connection: &mut Connection,
+                  _result: &Result<DispatchReport, SkylaneError>) {
+    }
+
+    /// Called after connection was removed from the loop because it ended or processing its
+    /// events failed with `error`.
+    fn disconnected(&mut self,
+                    _key: usize,
+                    _connection: Connection,
+                    _error: Option<SkylaneError>) {
+    }
+
+    /// Called every time the timer (see `EventLoop::set_timer`) expires.
+    fn timer(&mut self, _connections: &mut ConnectionSet) {}
+
+    /// Called for every connection when the loop is shut down (see `EventLoop::shutdown`). May
+    /// queue final event or post error to the client.
+    fn shutting_down(&mut self, _key: usize, _connection: &mut Connection) {}
+
+    /// Called on every iteration. Returning `true` makes `EventLoop::run` return.
+    fn should_stop(&self) -> bool {
+        false
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Periodic timer of `EventLoop`.
+struct Timer {
+    interval: Duration,
+    deadline: Instant,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Event loop based on `poll(2)` handling display socket, client connections and a timer.
+///
+/// Waits until any client connects, sends data or the timer expires and invokes appropriate
+/// `LoopHandler` callbacks, so simple servers do not need external event loop. Connections which
+/// ended (see `Connection::set_disconnect_handler`) or failed are removed automatically.
+pub struct EventLoop {
+    display: Option<DisplaySocket>,
+    connections: ConnectionSet,
+    timer: Option<Timer>,
+}
+
+impl EventLoop {
+    /// Constructs new `EventLoop` accepting clients on `display` (if given). The display socket
+    /// should stay in non-blocking mode.
+    pub fn new(display: Option<DisplaySocket>) -> Self {
+        EventLoop {
+            display,
+            connections: ConnectionSet::new(),
+            timer: None,
+        }
+    }
+
+    /// Returns the display socket.
+    pub fn get_display(&self) -> Option<&DisplaySocket> {
+        self.display.as_ref()
+    }
+
+    /// Returns connections handled by the loop.
+    pub fn get_connections(&self) -> &ConnectionSet {
+        &self.connections
+    }
+
+    /// Returns connections handled by the loop.
+    pub fn get_connections_mut(&mut self) -> &mut ConnectionSet {
+        &mut self.connections
+    }
+
+    /// Adds connection to the loop. Returns key identifying the connection.
+    pub fn add_connection(&mut self, connection: Connection) -> usize {
+        self.connections.add(connection)
+    }
+
+    /// Sets interval of periodic timer. `None` disables the timer.
+    pub fn set_timer(&mut self, interval: Option<Duration>) {
+        self.timer = interval.map(|interval| {
+                                      Timer {
+                                          interval,
+                                          deadline: Instant::now() + interval,
+                                      }
+                                  });
+    }
+
+    /// Runs the loop until `LoopHandler::should_stop` returns `true`.
+    pub fn run<H>(&mut self, handler: &mut H) -> Result<(), SkylaneError>
+        where H: LoopHandler
+    {
+        while !handler.should_stop() {
+            self.run_once(handler, None)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for events at most `timeout` (or until timer expires) and handles them.
+    pub fn run_once<H>(&mut self,
+                       handler: &mut H,
+                       timeout: Option<Duration>)
+                       -> Result<(), SkylaneError>
+        where H: LoopHandler
+    {
+        let now = Instant::now();
+        let timeout = match self.timer {
+            Some(ref timer) => {
+                let remaining = if timer.deadline > now {
+                    timer.deadline - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                Some(timeout.map_or(remaining, |timeout| std::cmp::min(timeout, remaining)))
+            }
+            None => timeout,
+        };
+
+        let mut pollfds: Vec<libc::pollfd> = self.display
+            .iter()
+            .map(|display| {
+                     libc::pollfd {
+                         fd: display.get_fd(),
+                         events: libc::POLLIN,
+                         revents: 0,
+                     }
+                 })
+            .collect();
+        let results = self.connections.poll_with(&mut pollfds, timeout)?;
+
+        for (key, result) in results {
+            let is_finished = match self.connections.get_mut(key) {
+                Some(connection) => {
+                    handler.dispatched(key, connection, &result);
+                    connection.is_disconnected() ||
+                    result.as_ref().err().map_or(false, |err| !err.is_would_block())
+                }
+                None => false,
+            };
+            if is_finished {
+                if let Some(connection) = self.connections.remove(key) {
+                    handler.disconnected(key, connection, result.err());
+                }
+            }
+        }
+
+        if pollfds.iter().any(|pollfd| pollfd.revents != 0) {
+            if let Some(ref display) = self.display {
+                for socket in display.accept_all()? {
+                    if let Some(connection) = handler.accept(socket) {
+                        self.connections.add(connection);
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let is_expired = self.timer.as_ref().map_or(false, |timer| timer.deadline <= now);
+        if is_expired {
+            handler.timer(&mut self.connections);
+            if let Some(ref mut timer) = self.timer {
+                timer.deadline = Instant::now() + timer.interval;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shuts the server down gracefully.
+    ///
+    /// Stops accepting new clients, lets `handler` post final event or error to every connection
+    /// (see `LoopHandler::shutting_down`) and flushes outgoing messages waiting at most `timeout`
+    /// for clients to receive them. Then all connections are terminated and reported to
+    /// `LoopHandler::disconnected`. The display socket is dropped last, removing its path only
+    /// after no client is connected anymore.
+    pub fn shutdown<H>(&mut self, handler: &mut H, timeout: Duration) -> Result<(), SkylaneError>
+        where H: LoopHandler
+    {
+        let deadline = Instant::now() + timeout;
+        let display = self.display.take();
+
+        for key in self.connections.get_keys() {
+            if let Some(connection) = self.connections.get_mut(key) {
+                handler.shutting_down(key, connection);
+            }
+        }
+
+        loop {
+            let mut pending = Vec::new();
+            for key in self.connections.get_keys() {
+                let result = match self.connections.get_mut(key) {
+                    Some(connection) => {
+                        if connection.get_bundle().has_queued() {
+                            connection.flush().map(|_| connection.get_bundle().has_queued())
+                        } else {
+                            Ok(false)
+                        }
+                    }
+                    None => Ok(false),
+                };
+                match result {
+                    Ok(true) => pending.push(key),
+                    Ok(false) => {}
+                    Err(err) => {
+                        if let Some(connection) = self.connections.remove(key) {
+                            handler.disconnected(key, connection, Some(err));
+                        }
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            if pending.is_empty() || now >= deadline {
+                break;
+            }
+            let fds: Vec<_> = pending.iter()
+                .filter_map(|key| self.connections.get(*key))
+                .map(|connection| connection.get_socket().get_fd())
+                .collect();
+            wait_writable(&fds, deadline - now)?;
+        }
+
+        for key in self.connections.get_keys() {
+            if let Some(mut connection) = self.connections.remove(key) {
+                // Messages which could not be flushed before the deadline are lost anyway.
+                let _ = connection.terminate();
+                handler.disconnected(key, connection, None);
+            }
+        }
+
+        drop(display);
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Waits until any of `fds` becomes writable or `timeout` passes.
+fn wait_writable(fds: &[RawFd], timeout: Duration) -> Result<(), SkylaneError> {
+    let mut pollfds: Vec<_> = fds.iter()
+        .map(|fd| {
+                 libc::pollfd {
+                     fd: *fd,
+                     events: libc::POLLOUT,
+                     revents: 0,
+                 }
+             })
+        .collect();
+    let millis = (timeout.subsec_nanos() as u64 + 999_999) / 1_000_000;
+    let timeout_ms = (timeout.as_secs() * 1000 + millis) as libc::c_int;
+    let res = unsafe {
+        libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms)
+    };
+    match Errno::result(res) {
+        Ok(_) | Err(Errno::EINTR) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,95 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Ownership of file descriptors.
+
+use std;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use nix;
+use nix::fcntl::{self, FcntlArg};
+
+use defs::SkylaneError;
+
+// -------------------------------------------------------------------------------------------------
+
+/// File descriptor closed when dropped.
+///
+/// Received file descriptors are handed out wrapped in `OwnedFd`, so they do not leak when handler
+/// ignores them. Use `into_raw` to take over the ownership.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OwnedFd {
+    fd: RawFd,
+}
+
+impl OwnedFd {
+    /// Wraps raw file descriptor taking ownership of it.
+    pub fn new(fd: RawFd) -> Self {
+        OwnedFd { fd }
+    }
+
+    /// Returns raw file descriptor. The ownership is kept.
+    pub fn get_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Releases the ownership and returns raw file descriptor. Caller is responsible for closing
+    /// it.
+    pub fn into_raw(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for OwnedFd {
+    fn drop(&mut self) {
+        // Nothing can be done if closing fails.
+        let _ = nix::unistd::close(self.fd);
+    }
+}
+
+impl AsRawFd for OwnedFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for OwnedFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.into_raw()
+    }
+}
+
+impl FromRawFd for OwnedFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        OwnedFd::new(fd)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Duplicates file descriptor `fd` with `FD_CLOEXEC` flag set, so the copy does not leak to child
+/// processes. Useful for forwarding descriptors received from one client to another (e.g. pipes
+/// used for drag-and-drop), as the duplicate can be passed with `Marshaller::put_owned_fd`
+/// independently of the original.
+pub fn dup_cloexec(fd: RawFd) -> Result<OwnedFd, SkylaneError> {
+    let duplicate = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+    Ok(OwnedFd::new(duplicate))
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,144 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Entry points for fuzzing the wire parser.
+//!
+//! Available with `fuzzing` feature. Fuzz targets for `cargo fuzz` are kept in `fuzz` directory.
+
+use std;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::os::unix::io::{IntoRawFd, RawFd};
+
+use defs::{SkylaneError, Task};
+use bundle::Bundle;
+use connection::{Connection, ConnectionInternal};
+use dispatch::{DispatchPolicy, DispatchReport};
+use message::Message;
+use meta::{InterfaceMeta, MessageMeta};
+use object::{Object, ObjectId};
+use sockets::{self, Socket};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Maximal number of synthetic file descriptors passed along with fuzzed data.
+pub const MAX_FDS: usize = sockets::MAX_FDS;
+
+/// Number of mock objects registered before dispatching. They get IDs starting from `2`.
+const NUM_OBJECTS: u32 = 4;
+
+/// Metadata of mock interface with requests covering all argument types.
+static META: InterfaceMeta = InterfaceMeta {
+    name: "skylane_fuzz",
+    version: 1,
+    requests: &[MessageMeta { name: "destroy", signature: "" },
+                MessageMeta { name: "create", signature: "n" },
+                MessageMeta { name: "numbers", signature: "iuf" },
+                MessageMeta { name: "strings", signature: "s?s" },
+                MessageMeta { name: "object", signature: "?o" },
+                MessageMeta { name: "array", signature: "a" },
+                MessageMeta { name: "fd", signature: "h" },
+                MessageMeta { name: "mixed", signature: "usahn" }],
+    events: &[],
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Mock object reading arguments according to signatures from `META`.
+struct FuzzObject;
+
+impl Object for FuzzObject {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        let id = message.get_object_id();
+        let opcode = message.get_opcode();
+        let signature = match META.get_request(opcode) {
+            Some(request) => request.signature,
+            None => {
+                return Err(SkylaneError::WrongOpcode {
+                               name: META.name,
+                               object_id: id,
+                               opcode,
+                           });
+            }
+        };
+
+        for kind in signature.bytes() {
+            match kind {
+                b'i' => drop(message.next_int()?),
+                b'u' => drop(message.next_uint()?),
+                b'f' => drop(message.next_fixed()?),
+                b's' => drop(message.next_string()?),
+                b'o' => drop(message.next_object()?),
+                b'a' => drop(message.next_array()?),
+                b'n' => {
+                    let new_id = message.next_new_id()?;
+                    bundle.add_remote_object(new_id, Box::new(FuzzObject))?;
+                    bundle.set_interface_meta(new_id, &META);
+                }
+                b'h' => drop(message.next_fd()?),
+                _ => {}
+            }
+        }
+
+        if signature.is_empty() {
+            bundle.remove_object(id);
+        }
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Parses and dispatches `bytes` as if they were received by server from client along with
+/// `num_fds` file descriptors (capped at `MAX_FDS`).
+///
+/// Messages are dispatched to built-in `wl_display` and mock objects with requests taking all
+/// argument types. All errors are collected in returned report. The function must never panic or
+/// leak file descriptors regardless of input.
+pub fn dispatch_bytes(bytes: &[u8], num_fds: usize) -> Result<DispatchReport, SkylaneError> {
+    let (_client, server) = Socket::pair()?;
+    server.set_nonblocking(true);
+
+    let factory = Box::new(|_: &mut _, _| Ok(Box::new(FuzzObject) as Box<dyn Object>));
+    let mut connection = Connection::new_server(server, factory);
+    connection.set_dispatch_policy(DispatchPolicy::Continue);
+    for i in 0..NUM_OBJECTS {
+        let id = ObjectId::new(2 + i);
+        connection.add_object(id, Box::new(FuzzObject));
+        connection.set_interface_meta(id, &META);
+    }
+
+    let mut fds = VecDeque::new();
+    for _ in 0..std::cmp::min(num_fds, MAX_FDS) {
+        fds.push_back(open_null()?);
+    }
+
+    connection.feed(bytes, fds)?;
+    let result = connection.dispatch_pending();
+    connection.discard_pending();
+    result
+}
+
+/// Opens `/dev/null` to be used as synthetic file descriptor.
+fn open_null() -> Result<RawFd, SkylaneError> {
+    Ok(File::open(std::path::Path::new("/dev/null"))?.into_raw_fd())
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,146 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Runtime introspection of connection state meant for debugging tools.
+
+use std;
+use std::collections::VecDeque;
+
+use defs::{Direction, Header};
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Default number of messages kept in history. History is disabled by default as keeping it
+/// requires parsing every sent message.
+pub const DEFAULT_HISTORY_SIZE: usize = 0;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Description of registered object.
+///
+/// Interface name is known only if metadata were registered for the object (see
+/// `Bundle::set_interface_meta`).
+#[derive(Clone, Debug)]
+pub struct ObjectInfo {
+    /// ID of the object.
+    pub id: ObjectId,
+
+    /// Name of implemented interface.
+    pub interface: Option<&'static str>,
+
+    /// Bound version of implemented interface (see `Bundle::set_object_version`) if set.
+    pub version: Option<u32>,
+
+    /// Number of references to the handler held by the connection. It is `1` unless the handler
+    /// is being dispatched or referenced elsewhere (e.g. by a weak reference being upgraded).
+    pub refcount: usize,
+}
+
+impl std::fmt::Display for ObjectInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:>10} {}", self.id, self.interface.unwrap_or("<unknown>"))?;
+        if let Some(version) = self.version {
+            write!(f, " v{}", version)?;
+        }
+        write!(f, " (refs: {})", self.refcount)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Description of sent or received message.
+#[derive(Clone, Debug)]
+pub struct MessageInfo {
+    /// Direction of the message.
+    pub direction: Direction,
+
+    /// Header of the message.
+    pub header: Header,
+
+    /// Name of interface of the object the message was addressed to if known.
+    pub interface: Option<&'static str>,
+
+    /// Name of the request or event if known.
+    pub name: Option<&'static str>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Snapshot of connection state returned by `Connection::introspect`.
+#[derive(Clone, Debug)]
+pub struct Introspection {
+    /// Registered objects sorted by ID.
+    pub objects: Vec<ObjectInfo>,
+
+    /// Recently sent and received messages, the oldest first.
+    pub history: Vec<MessageInfo>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Ring buffer of recent messages.
+pub struct History {
+    entries: VecDeque<MessageInfo>,
+    capacity: usize,
+}
+
+impl History {
+    /// Constructs new `History` keeping up to `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Changes number of kept messages dropping the oldest ones if needed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns maximal number of kept messages.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Checks if messages are being kept.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Adds message dropping the oldest one if history is full.
+    pub fn push(&mut self, info: MessageInfo) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(info);
+    }
+
+    /// Returns copy of kept messages.
+    pub fn to_vec(&self) -> Vec<MessageInfo> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
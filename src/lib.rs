@@ -22,13 +22,57 @@
 #![warn(missing_docs)]
 
 extern crate byteorder;
+#[macro_use]
 extern crate nix;
+#[cfg(feature = "io-uring")]
+extern crate io_uring;
 
 mod defs;
 mod object;
 mod bundle;
+mod builder;
+mod callback;
+mod clock;
+mod map;
+mod marshal;
+mod message;
+mod meta;
+mod multiplex;
+mod names;
+mod placeholder;
+mod pool;
+mod proxy;
+mod queue;
+mod reader;
+mod record;
+mod remote;
+mod reconnect;
+mod registry;
+mod serials;
 mod connection;
+mod credentials;
+mod discovery;
+mod dispatch;
+mod display;
+mod endian;
+mod event_loop;
+mod fd;
+mod introspect;
+mod limits;
+mod shm;
 mod sockets;
+mod stats;
+mod trace;
+mod validation;
+
+#[cfg(feature = "io-uring")]
+mod uring;
+
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub mod server;
 pub mod client;
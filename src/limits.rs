@@ -0,0 +1,148 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Flood protection limits.
+
+use std::time::{Duration, Instant};
+
+use defs::SkylaneError;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Limits on traffic from the peer.
+///
+/// Meant to be used on server side to protect from clients spamming requests.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Maximal number of messages which can be processed within `period`.
+    pub max_messages: u32,
+
+    /// Length of period in which messages are counted.
+    pub period: Duration,
+
+    /// Maximal number of bytes waiting in socket to be processed.
+    pub max_pending_bytes: Option<usize>,
+}
+
+impl RateLimit {
+    /// Constructs new `RateLimit` allowing `max_messages` per second without limit on pending
+    /// bytes.
+    pub fn per_second(max_messages: u32) -> Self {
+        RateLimit {
+            max_messages,
+            period: Duration::from_secs(1),
+            max_pending_bytes: None,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// What to do when the peer sends more file descriptors than allowed by `FdLimit`.
+///
+/// Received descriptors are not tied to messages, so once some of them are dropped it is not known
+/// which messages lost theirs. Exceeding the limit is therefore fatal for the connection: all data
+/// read but not dispatched are discarded along with pending descriptors, and so are all data
+/// received later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FdOverflowPolicy {
+    /// Return `SkylaneError::LimitExceeded` from this and every following read. The caller should
+    /// close the connection.
+    Error,
+
+    /// Like `Error` but also terminate the connection (see `Connection::terminate`).
+    Disconnect,
+}
+
+/// Limit on number of received file descriptors not yet consumed by dispatched messages.
+///
+/// Protects from peers sending descriptors without messages using them, which would otherwise
+/// exhaust descriptors of the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FdLimit {
+    /// Maximal number of pending descriptors.
+    pub max_fds: usize,
+
+    /// What to do when the limit is exceeded.
+    pub policy: FdOverflowPolicy,
+}
+
+impl FdLimit {
+    /// Constructs new `FdLimit`.
+    pub fn new(max_fds: usize, policy: FdOverflowPolicy) -> Self {
+        FdLimit {
+            max_fds,
+            policy,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Helper structure counting messages against `RateLimit`.
+pub struct RateLimiter {
+    limit: RateLimit,
+    period_start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    /// Constructs new `RateLimiter`.
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            period_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Checks if given number of bytes waiting in socket is acceptable.
+    pub fn check_pending_bytes(&self, pending_bytes: usize) -> Result<(), SkylaneError> {
+        if let Some(max_pending_bytes) = self.limit.max_pending_bytes {
+            if pending_bytes > max_pending_bytes {
+                return Err(SkylaneError::LimitExceeded {
+                               description: format!("{} bytes pending (limit: {})",
+                                                    pending_bytes,
+                                                    max_pending_bytes),
+                           });
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts one message. Returns error if limit was exceeded.
+    pub fn count_message(&mut self) -> Result<(), SkylaneError> {
+        let now = Instant::now();
+        if now.duration_since(self.period_start) >= self.limit.period {
+            self.period_start = now;
+            self.count = 0;
+        }
+
+        self.count += 1;
+        if self.count > self.limit.max_messages {
+            Err(SkylaneError::LimitExceeded {
+                    description: format!("More than {} messages in {:?}",
+                                         self.limit.max_messages,
+                                         self.limit.period),
+                })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
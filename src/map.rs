@@ -0,0 +1,296 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Storage of protocol objects indexed by their IDs.
+
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use defs::SkylaneError;
+use object::{Object, ObjectId, SERVER_START_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Objects with IDs further than this from the end of dense array are stored in sparse map to
+/// avoid big allocations caused by unusual IDs.
+const MAX_GAP: usize = 1024;
+
+/// Type of reference to registered object.
+pub type ObjectRef = Rc<RefCell<Box<dyn Object>>>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Storage of objects registered in `Bundle`.
+///
+/// `ObjectMap` is used by default. Embedders keeping protocol objects in storage they already own
+/// (e.g. ECS or arena) may provide own implementation with `Connection::set_object_store`.
+/// References returned by the store must be the same `ObjectRef`s which were inserted.
+pub trait ObjectStore {
+    /// Adds object. Overrides object previously registered with the same ID.
+    fn insert(&mut self, id: ObjectId, object: ObjectRef);
+
+    /// Removes object and returns it if it was present.
+    fn remove(&mut self, id: ObjectId) -> Option<ObjectRef>;
+
+    /// Returns object with given ID.
+    fn get(&self, id: ObjectId) -> Option<&ObjectRef>;
+
+    /// Returns IDs of all registered objects in ascending order.
+    fn get_ids(&self) -> Vec<ObjectId>;
+
+    /// Returns number of registered objects.
+    fn len(&self) -> usize {
+        self.get_ids().len()
+    }
+
+    /// Checks if there are no registered objects.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the biggest ID of registered objects between `first` and `last` (inclusive).
+    fn max_id_in(&self, first: ObjectId, last: ObjectId) -> Option<ObjectId> {
+        self.get_ids().into_iter().filter(|id| first <= *id && *id <= last).max()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Map of objects.
+///
+/// Client and server IDs are allocated densely from the beginning of their ranges so objects are
+/// kept in two arrays indexed by ID giving lookup without hashing. IDs not fitting this scheme
+/// fall back to hash map.
+pub struct ObjectMap {
+    client: Vec<Option<ObjectRef>>,
+    server: Vec<Option<ObjectRef>>,
+    sparse: HashMap<ObjectId, ObjectRef>,
+    len: usize,
+}
+
+impl ObjectMap {
+    /// Constructs new empty `ObjectMap`.
+    pub fn new() -> Self {
+        ObjectMap {
+            client: Vec::new(),
+            server: Vec::new(),
+            sparse: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl Default for ObjectMap {
+    fn default() -> Self {
+        ObjectMap::new()
+    }
+}
+
+impl ObjectStore for ObjectMap {
+    fn insert(&mut self, id: ObjectId, object: ObjectRef) {
+        self.remove(id);
+        self.len += 1;
+        let object = {
+            let (slots, index) = self.get_slots_mut(id);
+            if index < slots.len() + MAX_GAP {
+                if index >= slots.len() {
+                    slots.resize(index + 1, None);
+                }
+                slots[index] = Some(object);
+                return;
+            }
+            object
+        };
+        self.sparse.insert(id, object);
+    }
+
+    fn remove(&mut self, id: ObjectId) -> Option<ObjectRef> {
+        let removed = {
+            let (slots, index) = self.get_slots_mut(id);
+            if index < slots.len() {
+                let removed = slots[index].take();
+                while let Some(&None) = slots.last() {
+                    slots.pop();
+                }
+                removed
+            } else {
+                None
+            }
+        };
+        let removed = removed.or_else(|| self.sparse.remove(&id));
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get_ids(&self) -> Vec<ObjectId> {
+        let client = self.client
+            .iter()
+            .enumerate()
+            .filter(|&(_, slot)| slot.is_some())
+            .map(|(index, _)| ObjectId::new(index as u32));
+        let server = self.server
+            .iter()
+            .enumerate()
+            .filter(|&(_, slot)| slot.is_some())
+            .map(|(index, _)| ObjectId::new(SERVER_START_ID.get_value() + index as u32));
+        let sparse = self.sparse.keys().cloned();
+        let mut ids: Vec<ObjectId> = client.chain(server).chain(sparse).collect();
+        ids.sort();
+        ids
+    }
+
+    fn get(&self, id: ObjectId) -> Option<&ObjectRef> {
+        let (slots, index) = self.get_slots(id);
+        if let Some(Some(object)) = slots.get(index) {
+            Some(object)
+        } else {
+            self.sparse.get(&id)
+        }
+    }
+
+    fn max_id_in(&self, first: ObjectId, last: ObjectId) -> Option<ObjectId> {
+        let client = Self::last_id(&self.client, 0);
+        let server = Self::last_id(&self.server, SERVER_START_ID.get_value());
+        let sparse = self.sparse.keys().cloned();
+        client.into_iter()
+            .chain(server)
+            .chain(sparse)
+            .filter(|id| first <= *id && *id <= last)
+            .max()
+    }
+}
+
+/// Private methods.
+impl ObjectMap {
+    /// Returns array of slots for given ID and index in this array.
+    fn get_slots(&self, id: ObjectId) -> (&Vec<Option<ObjectRef>>, usize) {
+        if id >= SERVER_START_ID {
+            (&self.server, (id.get_value() - SERVER_START_ID.get_value()) as usize)
+        } else {
+            (&self.client, id.get_value() as usize)
+        }
+    }
+
+    /// Returns mutable array of slots for given ID and index in this array.
+    fn get_slots_mut(&mut self, id: ObjectId) -> (&mut Vec<Option<ObjectRef>>, usize) {
+        if id >= SERVER_START_ID {
+            (&mut self.server, (id.get_value() - SERVER_START_ID.get_value()) as usize)
+        } else {
+            (&mut self.client, id.get_value() as usize)
+        }
+    }
+
+    /// Returns ID of the last object in array of slots. Arrays are kept trimmed so the last slot
+    /// is always occupied.
+    fn last_id(slots: &[Option<ObjectRef>], base: u32) -> Option<ObjectId> {
+        if !slots.is_empty() {
+            Some(ObjectId::new(base + slots.len() as u32 - 1))
+        } else {
+            None
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Weak reference to registered object.
+///
+/// Meant to be stored by handlers referring to other objects (e.g. sub-surface referring to its
+/// parent) instead of raw `ObjectId`s. Reference becomes invalid when the object is removed and
+/// stays invalid even if its ID is reused by another object.
+#[derive(Clone)]
+pub struct WeakObjectRef {
+    id: ObjectId,
+    object: Weak<RefCell<Box<dyn Object>>>,
+    objects: Weak<RefCell<Box<dyn ObjectStore>>>,
+}
+
+impl WeakObjectRef {
+    /// Constructs new `WeakObjectRef` to object registered in `objects` under `id`.
+    pub fn new(id: ObjectId,
+               object: &ObjectRef,
+               objects: &Rc<RefCell<Box<dyn ObjectStore>>>)
+               -> Self {
+        WeakObjectRef {
+            id,
+            object: Rc::downgrade(object),
+            objects: Rc::downgrade(objects),
+        }
+    }
+
+    /// Returns ID the object was registered with.
+    pub fn get_id(&self) -> ObjectId {
+        self.id
+    }
+
+    /// Checks if the object is still registered.
+    pub fn is_alive(&self) -> bool {
+        self.upgrade().is_some()
+    }
+
+    /// Returns ID of the object if it is still registered.
+    pub fn get_id_if_alive(&self) -> Option<ObjectId> {
+        self.upgrade().map(|_| self.id)
+    }
+
+    /// Calls `f` with the object if it is still registered. Returns `WrongObject` error if it was
+    /// removed and `Reentrancy` error if its handler is currently running.
+    pub fn with<F, R>(&self, f: F) -> Result<R, SkylaneError>
+        where F: FnOnce(&mut dyn Object) -> R
+    {
+        let object = self.upgrade().ok_or(SkylaneError::WrongObject { object_id: self.id })?;
+        let mut object = object.try_borrow_mut()
+            .map_err(|_| {
+                         SkylaneError::Reentrancy {
+                             object_id: Some(self.id),
+                             depth: 0,
+                         }
+                     })?;
+        Ok(f(&mut **object))
+    }
+}
+
+/// Private methods.
+impl WeakObjectRef {
+    /// Returns the object if it is still registered under its ID.
+    fn upgrade(&self) -> Option<ObjectRef> {
+        let object = self.object.upgrade()?;
+        let objects = self.objects.upgrade()?;
+
+        // Removed object may still be kept alive e.g. while its handler is running.
+        let is_registered = objects.borrow()
+            .get(self.id)
+            .map_or(false, |registered| Rc::ptr_eq(registered, &object));
+        if is_registered { Some(object) } else { None }
+    }
+}
+
+impl std::fmt::Debug for WeakObjectRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "WeakObjectRef({:?}, alive: {})", self.id, self.is_alive())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,273 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Helpers for marshalling messages.
+
+use std;
+use std::os::unix::io::RawFd;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use defs::SkylaneError;
+use endian::Endianness;
+use fd::OwnedFd;
+use object::{ObjectId, NULL_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Size of message header in bytes.
+pub const HEADER_SIZE: usize = 8;
+
+/// Maximal size of message including header, as the size is stored in 16 bits of the header.
+pub const MAX_MESSAGE_SIZE: usize = 0xffff;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Messages up to this size are composed without heap allocation.
+pub const INLINE_CAPACITY: usize = 64;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Helper structure for composing messages.
+///
+/// Header is written on construction. Its size field is filled in when the message is finished.
+///
+/// Most messages are small, so they are composed in fixed buffer inside the structure. Only when
+/// the message does not fit there the heap buffer is used.
+pub struct Marshaller {
+    inline: [u8; INLINE_CAPACITY],
+    inline_len: usize,
+    heap: Vec<u8>,
+    spilled: bool,
+    fds: Vec<RawFd>,
+    owned: Vec<OwnedFd>,
+    signature: Option<Vec<u8>>,
+}
+
+impl Marshaller {
+    /// Constructs new `Marshaller` for message with given `opcode` addressed to object `object_id`.
+    pub fn new(object_id: ObjectId, opcode: u16) -> Self {
+        Self::with_buffer(object_id, opcode, Vec::new())
+    }
+
+    /// Constructs new `Marshaller` using given buffer if message does not fit in inline buffer.
+    /// Previous content of the buffer is discarded.
+    pub fn with_buffer(object_id: ObjectId, opcode: u16, mut buffer: Vec<u8>) -> Self {
+        buffer.clear();
+        let mut marshaller = Marshaller {
+            inline: [0; INLINE_CAPACITY],
+            inline_len: 0,
+            heap: buffer,
+            spilled: false,
+            fds: Vec::new(),
+            owned: Vec::new(),
+            signature: None,
+        };
+        marshaller.put_u32(object_id.get_value());
+        marshaller.put_u32(opcode as u32);
+        marshaller
+    }
+
+    /// Starts recording types of appended arguments. Used for validation.
+    pub fn record_signature(&mut self) {
+        self.signature = Some(Vec::new());
+    }
+
+    /// Returns types of appended arguments if recording was started.
+    pub fn get_signature(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
+
+    /// Appends unsigned integer argument.
+    pub fn put_uint(&mut self, value: u32) {
+        self.record(b'u');
+        self.put_u32(value);
+    }
+
+    /// Appends signed integer argument.
+    pub fn put_int(&mut self, value: i32) {
+        self.record(b'i');
+        self.put_u32(value as u32);
+    }
+
+    /// Appends fixed-point (24.8) argument.
+    pub fn put_fixed(&mut self, value: f64) {
+        self.record(b'f');
+        self.put_u32((value * 256.0) as i32 as u32);
+    }
+
+    /// Appends object ID argument.
+    pub fn put_object(&mut self, object_id: ObjectId) {
+        self.record(b'o');
+        self.put_u32(object_id.get_value());
+    }
+
+    /// Appends nullable object ID argument. `None` is sent as `NULL_ID`.
+    pub fn put_nullable_object(&mut self, object_id: Option<ObjectId>) {
+        self.put_object(object_id.unwrap_or(NULL_ID));
+    }
+
+    /// Appends new object ID argument.
+    pub fn put_new_id(&mut self, object_id: ObjectId) {
+        self.record(b'n');
+        self.put_u32(object_id.get_value());
+    }
+
+    /// Appends string argument. String is terminated with `NUL` and padded to 32-bit boundary.
+    pub fn put_string(&mut self, value: &str) {
+        self.record(b's');
+        self.put_u32(value.len() as u32 + 1);
+        self.extend(value.as_bytes());
+        self.extend(&[0]);
+        self.pad();
+    }
+
+    /// Appends array argument. Array is padded to 32-bit boundary.
+    pub fn put_array(&mut self, value: &[u8]) {
+        self.record(b'a');
+        self.put_u32(value.len() as u32);
+        self.extend(value);
+        self.pad();
+    }
+
+    /// Appends array argument consisting of 32-bit unsigned integers (e.g. pressed keys in
+    /// `wl_keyboard.enter`). Size prefix is given in bytes.
+    pub fn put_array_u32(&mut self, value: &[u32]) {
+        self.record(b'a');
+        self.put_u32((value.len() * 4) as u32);
+        for item in value {
+            self.put_u32(*item);
+        }
+    }
+
+    /// Appends file descriptor. File descriptors are not part of message body and will be sent as
+    /// control data.
+    pub fn put_fd(&mut self, fd: RawFd) {
+        self.record(b'h');
+        self.fds.push(fd);
+    }
+
+    /// Appends file descriptor like `put_fd` taking ownership of it. Ownership passes to `Bundle`
+    /// when the message is queued with `Bundle::send` or `Bundle::queue_message`; the descriptor
+    /// is then closed after it was passed to the peer (or the message was dropped), so caller
+    /// must not close it.
+    ///
+    /// Bytes returned by `finalize` do not carry ownership: if they are queued with
+    /// `Bundle::queue_event` the descriptor is closed when the marshaller is dropped, possibly
+    /// before it was passed.
+    pub fn put_owned_fd(&mut self, fd: OwnedFd) {
+        self.put_fd(fd.get_fd());
+        self.owned.push(fd);
+    }
+
+    /// Takes ownership of file descriptors appended with `put_owned_fd`.
+    pub fn take_owned_fds(&mut self) -> Vec<OwnedFd> {
+        std::mem::take(&mut self.owned)
+    }
+
+    /// Fills in message size and returns message bytes and file descriptors without copying them.
+    ///
+    /// Returns error if the message is longer than `MAX_MESSAGE_SIZE`.
+    pub fn finalize(&mut self) -> Result<(&[u8], &[RawFd]), SkylaneError> {
+        let size = self.len();
+        if size > MAX_MESSAGE_SIZE {
+            return Err(SkylaneError::LimitExceeded {
+                description: format!("Message size ({}) exceeds maximum of {} bytes",
+                                     size,
+                                     MAX_MESSAGE_SIZE),
+            });
+        }
+        let bytes = if self.spilled {
+            &mut self.heap[..]
+        } else {
+            &mut self.inline[..self.inline_len]
+        };
+        let native = Endianness::native();
+        let opcode = native.read_u32(&bytes[4..HEADER_SIZE]) & 0xffff;
+        native.write_u32(&mut bytes[4..HEADER_SIZE], ((size as u32) << 16) | opcode);
+        Ok((bytes, &self.fds))
+    }
+
+    /// Fills in message size and returns message bytes and file descriptors. Ownership of
+    /// descriptors appended with `put_owned_fd` passes to the caller.
+    ///
+    /// Returns error if the message is longer than `MAX_MESSAGE_SIZE`.
+    pub fn finish(mut self) -> Result<(Vec<u8>, Vec<RawFd>), SkylaneError> {
+        self.finalize()?;
+        for fd in self.take_owned_fds() {
+            fd.into_raw();
+        }
+        if !self.spilled {
+            self.heap.extend_from_slice(&self.inline[..self.inline_len]);
+        }
+        Ok((self.heap, self.fds))
+    }
+
+    /// Returns heap buffer so it can be reused.
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.heap
+    }
+}
+
+/// Private methods.
+impl Marshaller {
+    /// Returns current size of the message.
+    fn len(&self) -> usize {
+        if self.spilled {
+            self.heap.len()
+        } else {
+            self.inline_len
+        }
+    }
+
+    /// Appends bytes. Moves the message to heap buffer if it does not fit in inline buffer.
+    fn extend(&mut self, data: &[u8]) {
+        if !self.spilled {
+            let end = self.inline_len + data.len();
+            if end <= INLINE_CAPACITY {
+                self.inline[self.inline_len..end].copy_from_slice(data);
+                self.inline_len = end;
+                return;
+            }
+            self.heap.extend_from_slice(&self.inline[..self.inline_len]);
+            self.spilled = true;
+        }
+        self.heap.extend_from_slice(data);
+    }
+
+    /// Records type of appended argument if recording was started.
+    fn record(&mut self, kind: u8) {
+        if let Some(ref mut signature) = self.signature {
+            signature.push(kind);
+        }
+    }
+
+    /// Appends 32-bit value.
+    fn put_u32(&mut self, value: u32) {
+        let mut buf = [0; 4];
+        NativeEndian::write_u32(&mut buf, value);
+        self.extend(&buf);
+    }
+
+    /// Pads message body with zeros to 32-bit boundary.
+    fn pad(&mut self) {
+        let padding = (4 - self.len() % 4) % 4;
+        self.extend(&[0, 0, 0][..padding]);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
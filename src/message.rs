@@ -0,0 +1,324 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Definition of `Message` providing typed access to arguments of received messages.
+
+use std;
+use std::io::Cursor;
+
+use byteorder::{ByteOrder, NativeEndian, ReadBytesExt};
+
+use defs::{DisplayError, Header, Side, SkylaneError};
+use display;
+use endian::Endianness;
+use fd::OwnedFd;
+use marshal::HEADER_SIZE;
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handling of string arguments which are not valid UTF-8.
+///
+/// Regardless of the policy raw bytes of string arguments can be read with
+/// `Message::next_string_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// `Message::next_string` returns `SkylaneError::Other`.
+    #[default]
+    Error,
+
+    /// Invalid sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    Lossy,
+
+    /// `Message::next_string` returns `wl_display.invalid_method` protocol error for the object
+    /// the message was addressed to, so on server side it is posted to the client.
+    ProtocolError,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Received message.
+///
+/// Bundles message header with its arguments and queue of received file descriptors. Arguments
+/// should be read in order they are defined in protocol.
+pub struct Message<'a, 'b: 'a> {
+    header: Header,
+    args: Cursor<&'b [u8]>,
+    fds: &'a mut Cursor<&'b [u8]>,
+    side: Option<Side>,
+    utf8_policy: Utf8Policy,
+}
+
+impl<'a, 'b: 'a> Message<'a, 'b> {
+    /// Constructs new `Message`.
+    ///
+    /// - `args` contains raw message without header.
+    /// - `fds` is queue of file descriptors received along with the message. It may be shared
+    ///   between many messages.
+    pub fn new(header: Header, args: &'b [u8], fds: &'a mut Cursor<&'b [u8]>) -> Self {
+        Message {
+            header,
+            args: Cursor::new(args),
+            fds,
+            side: None,
+            utf8_policy: Utf8Policy::default(),
+        }
+    }
+
+    /// Returns message header.
+    pub fn get_header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns ID of the object the message is addressed to.
+    pub fn get_object_id(&self) -> ObjectId {
+        ObjectId::new(self.header.object_id)
+    }
+
+    /// Returns opcode of called method.
+    pub fn get_opcode(&self) -> u16 {
+        self.header.opcode
+    }
+
+    /// Returns header and raw buffers with arguments and file descriptors.
+    ///
+    /// This is meant for migration of code using `Object::dispatch`.
+    pub fn as_raw_parts(&mut self) -> (&Header, &mut Cursor<&'b [u8]>, &mut Cursor<&'b [u8]>) {
+        (&self.header, &mut self.args, self.fds)
+    }
+
+    /// Reads next unsigned integer argument.
+    pub fn next_uint(&mut self) -> Result<u32, SkylaneError> {
+        Ok(self.args.read_u32::<NativeEndian>()?)
+    }
+
+    /// Reads next signed integer argument.
+    pub fn next_int(&mut self) -> Result<i32, SkylaneError> {
+        Ok(self.args.read_i32::<NativeEndian>()?)
+    }
+
+    /// Reads next fixed-point (24.8) argument.
+    pub fn next_fixed(&mut self) -> Result<f64, SkylaneError> {
+        Ok(self.next_int()? as f64 / 256.0)
+    }
+
+    /// Reads next object ID argument.
+    ///
+    /// For arguments declared as nullable use `next_nullable_object`, so null is not mistaken for
+    /// ID of an object.
+    pub fn next_object(&mut self) -> Result<ObjectId, SkylaneError> {
+        Ok(ObjectId::new(self.next_uint()?))
+    }
+
+    /// Reads next nullable object ID argument. Returns `None` if it was `NULL_ID`.
+    pub fn next_nullable_object(&mut self) -> Result<Option<ObjectId>, SkylaneError> {
+        let id = self.next_object()?;
+        Ok(if id.is_null() { None } else { Some(id) })
+    }
+
+    /// Reads next new object ID argument.
+    ///
+    /// If side of the connection is known, checks if the ID is in range allocated by peer.
+    pub fn next_new_id(&mut self) -> Result<ObjectId, SkylaneError> {
+        let id = self.next_object()?;
+        if let Some(side) = self.side {
+            display::check_id_range(id, side.peer())?;
+        }
+        Ok(id)
+    }
+
+    /// Reads next string argument. Invalid UTF-8 is handled according to `Utf8Policy` set for the
+    /// connection (see `Connection::set_utf8_policy`).
+    pub fn next_string(&mut self) -> Result<String, SkylaneError> {
+        let bytes = self.next_string_bytes()?;
+        match std::str::from_utf8(bytes) {
+            Ok(string) => Ok(string.to_owned()),
+            Err(err) => {
+                match self.utf8_policy {
+                    Utf8Policy::Error => {
+                        Err(SkylaneError::Other(format!("Invalid string ({:?}): {:?}",
+                                                        self.header,
+                                                        err)))
+                    }
+                    Utf8Policy::Lossy => Ok(String::from_utf8_lossy(bytes).into_owned()),
+                    Utf8Policy::ProtocolError => {
+                        Err(SkylaneError::Protocol {
+                                interface: display::INTERFACE,
+                                object_id: self.get_object_id(),
+                                code: DisplayError::InvalidMethod.get_code(),
+                                message: format!("Invalid UTF-8 in string (opcode: {})",
+                                                 self.header.opcode),
+                            })
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads next string argument as raw bytes without terminating `NUL` and without checking
+    /// if they are valid UTF-8.
+    pub fn next_string_bytes(&mut self) -> Result<&'b [u8], SkylaneError> {
+        let bytes = self.next_array_slice()?;
+        match bytes.split_last() {
+            Some((&0, string)) => Ok(string),
+            _ => {
+                Err(SkylaneError::Other(format!("String not terminated with NUL ({:?})",
+                                                self.header)))
+            }
+        }
+    }
+
+    /// Reads next array argument.
+    pub fn next_array(&mut self) -> Result<Vec<u8>, SkylaneError> {
+        Ok(self.next_array_slice()?.to_vec())
+    }
+
+    /// Reads next array argument without copying it. Returned slice borrows from the receive
+    /// buffer and has no alignment guarantees, so it should not be cast to slices of wider types;
+    /// use `next_array_u32` instead.
+    pub fn next_array_slice(&mut self) -> Result<&'b [u8], SkylaneError> {
+        let size = self.next_uint()? as usize;
+        let position = self.args.position() as usize;
+        let args: &'b [u8] = self.args.get_ref();
+        let padded_size = (size + 3) & !3;
+        if padded_size > args.len().saturating_sub(position) {
+            return Err(SkylaneError::Other(format!("Array exceeds message ({:?})", self.header)));
+        }
+        self.args.set_position((position + padded_size) as u64);
+        Ok(&args[position..position + size])
+    }
+
+    /// Reads next array argument consisting of 32-bit unsigned integers (e.g. pressed keys in
+    /// `wl_keyboard.enter`). Returns error if size of the array is not multiple of four.
+    pub fn next_array_u32(&mut self) -> Result<Vec<u32>, SkylaneError> {
+        let bytes = self.next_array_slice()?;
+        if bytes.len() % 4 != 0 {
+            return Err(SkylaneError::Other(format!("Array size ({}) is not multiple of 4 ({:?})",
+                                                   bytes.len(),
+                                                   self.header)));
+        }
+        Ok(bytes.chunks(4).map(NativeEndian::read_u32).collect())
+    }
+
+    /// Takes next file descriptor from the queue.
+    ///
+    /// The descriptor is closed when returned `OwnedFd` is dropped unless ownership is taken with
+    /// `OwnedFd::into_raw`.
+    pub fn next_fd(&mut self) -> Result<OwnedFd, SkylaneError> {
+        Ok(OwnedFd::new(self.fds.read_i32::<NativeEndian>()?))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Methods of `Message` available in this crate but not exported.
+pub trait MessageInternal {
+    /// Sets side of connection which received the message.
+    fn set_side(&mut self, side: Option<Side>);
+
+    /// Sets handling of invalid UTF-8 in string arguments.
+    fn set_utf8_policy(&mut self, policy: Utf8Policy);
+}
+
+impl<'a, 'b: 'a> MessageInternal for Message<'a, 'b> {
+    fn set_side(&mut self, side: Option<Side>) {
+        self.side = side;
+    }
+
+    fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.utf8_policy = policy;
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Iterator over messages stored in buffer. Yields headers along with arguments of complete
+/// messages.
+///
+/// Iteration stops on incomplete message at the end of buffer (see `is_truncated`) or after
+/// yielding error for message with size smaller than header or not multiple of four.
+pub struct MessageIter<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    failed: bool,
+}
+
+impl<'a> MessageIter<'a> {
+    /// Constructs new `MessageIter` over `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        MessageIter {
+            bytes,
+            position: 0,
+            failed: false,
+        }
+    }
+
+    /// Returns number of bytes taken by messages yielded so far.
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns bytes following messages yielded so far.
+    pub fn get_remaining(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    /// Checks if bytes following messages yielded so far do not form complete message.
+    pub fn is_truncated(&self) -> bool {
+        match read_header(self.get_remaining()) {
+            Some(header) => {
+                let size = header.size as usize;
+                size >= HEADER_SIZE && size % 4 == 0 && size > self.get_remaining().len()
+            }
+            None => self.position < self.bytes.len(),
+        }
+    }
+}
+
+impl<'a> Iterator for MessageIter<'a> {
+    type Item = Result<(Header, &'a [u8]), SkylaneError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let header = read_header(self.get_remaining())?;
+
+        let size = header.size as usize;
+        if size < HEADER_SIZE || size % 4 != 0 {
+            self.failed = true;
+            return Some(Err(SkylaneError::Other(format!("Malformed message: {:?}", header))));
+        }
+
+        let end = self.position + size;
+        if end > self.bytes.len() {
+            return None;
+        }
+
+        let args = &self.bytes[(self.position + HEADER_SIZE)..end];
+        self.position = end;
+        Some(Ok((header, args)))
+    }
+}
+
+/// Reads message header from the beginning of `bytes` if they are long enough.
+fn read_header(bytes: &[u8]) -> Option<Header> {
+    Endianness::native().read_header(bytes)
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,97 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Metadata describing protocol interfaces.
+//!
+//! Metadata let the core of the crate inspect messages without calling into handlers: validate
+//! them, count attached file descriptors or print them in traces.
+
+use defs::Side;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Description of single request or event.
+#[derive(Clone, Copy, Debug)]
+pub struct MessageMeta {
+    /// Name of the message.
+    pub name: &'static str,
+
+    /// Signature of arguments in `libwayland` notation (see `Validator`).
+    pub signature: &'static str,
+}
+
+impl MessageMeta {
+    /// Returns number of file descriptors carried by the message.
+    pub fn get_fd_count(&self) -> usize {
+        self.signature.bytes().filter(|c| *c == b'h').count()
+    }
+
+    /// Returns version of interface since which the message is available. It is encoded as number
+    /// at the beginning of signature; messages without it are available since version 1.
+    pub fn get_since(&self) -> u32 {
+        let digits = self.signature.bytes().take_while(|c| b'0' <= *c && *c <= b'9').count();
+        self.signature[..digits].parse().unwrap_or(1)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Description of interface. Requests and events are indexed by opcode.
+///
+/// Meant to be defined statically by generated code and registered along with objects using
+/// `Bundle::set_interface_meta`.
+#[derive(Clone, Copy, Debug)]
+pub struct InterfaceMeta {
+    /// Name of the interface.
+    pub name: &'static str,
+
+    /// Version of the interface.
+    pub version: u32,
+
+    /// Requests sent by client.
+    pub requests: &'static [MessageMeta],
+
+    /// Events sent by server.
+    pub events: &'static [MessageMeta],
+}
+
+impl InterfaceMeta {
+    /// Returns request with given opcode.
+    pub fn get_request(&self, opcode: u16) -> Option<&'static MessageMeta> {
+        self.requests.get(opcode as usize)
+    }
+
+    /// Returns event with given opcode.
+    pub fn get_event(&self, opcode: u16) -> Option<&'static MessageMeta> {
+        self.events.get(opcode as usize)
+    }
+
+    /// Returns messages received by given side of connection.
+    pub fn get_incoming(&self, side: Side) -> &'static [MessageMeta] {
+        match side {
+            Side::Client => self.events,
+            Side::Server => self.requests,
+        }
+    }
+
+    /// Returns messages sent by given side of connection.
+    pub fn get_outgoing(&self, side: Side) -> &'static [MessageMeta] {
+        self.get_incoming(side.peer())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
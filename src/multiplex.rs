@@ -0,0 +1,205 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Helper for handling many connections in one thread.
+
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::libc;
+
+use defs::SkylaneError;
+use connection::{Connection, ConnectionInternal};
+use dispatch::DispatchReport;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Keys of processed connections with results of processing.
+type PollResults = Vec<(usize, Result<DispatchReport, SkylaneError>)>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Set of connections polled together.
+///
+/// Useful e.g. for clients connected to many compositors at once. Connections do not share any
+/// state, so they can be used independently; `ConnectionSet` only waits for any of them to become
+/// ready and dispatches its messages.
+pub struct ConnectionSet {
+    connections: Vec<Option<Connection>>,
+}
+
+impl ConnectionSet {
+    /// Constructs new empty `ConnectionSet`.
+    pub fn new() -> Self {
+        ConnectionSet { connections: Vec::new() }
+    }
+
+    /// Adds connection to the set. Returns key identifying the connection in the set.
+    pub fn add(&mut self, connection: Connection) -> usize {
+        if let Some(key) = self.connections.iter().position(|slot| slot.is_none()) {
+            self.connections[key] = Some(connection);
+            key
+        } else {
+            self.connections.push(Some(connection));
+            self.connections.len() - 1
+        }
+    }
+
+    /// Removes connection from the set and returns it.
+    pub fn remove(&mut self, key: usize) -> Option<Connection> {
+        self.connections.get_mut(key).and_then(|slot| slot.take())
+    }
+
+    /// Returns connection with given key.
+    pub fn get(&self, key: usize) -> Option<&Connection> {
+        self.connections.get(key).and_then(|slot| slot.as_ref())
+    }
+
+    /// Returns connection with given key.
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut Connection> {
+        self.connections.get_mut(key).and_then(|slot| slot.as_mut())
+    }
+
+    /// Returns number of connections in the set.
+    pub fn len(&self) -> usize {
+        self.connections.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Checks if there are no connections in the set.
+    pub fn is_empty(&self) -> bool {
+        self.connections.iter().all(|slot| slot.is_none())
+    }
+
+    /// Returns keys of all connections in the set.
+    pub fn get_keys(&self) -> Vec<usize> {
+        self.connections
+            .iter()
+            .enumerate()
+            .filter(|&(_, slot)| slot.is_some())
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Disconnects and removes connections which are idle (see `Connection::set_idle_timeout`).
+    /// Returns keys of removed connections with the connections.
+    pub fn remove_idle(&mut self) -> Vec<(usize, Connection)> {
+        let mut removed = Vec::new();
+        for (key, slot) in self.connections.iter_mut().enumerate() {
+            let is_idle = slot.as_ref().map_or(false, |connection| connection.is_idle());
+            if is_idle {
+                if let Some(mut connection) = slot.take() {
+                    // The connection is dropped anyway, failure to shut it down does not matter.
+                    let _ = connection.disconnect_if_idle();
+                    removed.push((key, connection));
+                }
+            }
+        }
+        removed
+    }
+
+    /// Waits until at least one connection is ready (or `timeout` passes) and processes events of
+    /// all ready connections. Returns keys of processed connections with results of processing.
+    ///
+    /// Connections with already read but not dispatched messages are processed without waiting.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<PollResults, SkylaneError> {
+        self.poll_with(&mut [], timeout)
+    }
+}
+
+impl Default for ConnectionSet {
+    fn default() -> Self {
+        ConnectionSet::new()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Methods of `ConnectionSet` available in this crate but not exported.
+pub trait ConnectionSetInternal {
+    /// Works like `ConnectionSet::poll` but also waits for `extra` descriptors. Their `revents`
+    /// are filled after polling. Returns without processing anything if polling was interrupted.
+    fn poll_with(&mut self,
+                 extra: &mut [libc::pollfd],
+                 timeout: Option<Duration>)
+                 -> Result<PollResults, SkylaneError>;
+}
+
+impl ConnectionSetInternal for ConnectionSet {
+    fn poll_with(&mut self,
+                 extra: &mut [libc::pollfd],
+                 timeout: Option<Duration>)
+                 -> Result<PollResults, SkylaneError> {
+        let mut keys = Vec::with_capacity(self.connections.len());
+        let mut pollfds = Vec::with_capacity(self.connections.len() + extra.len());
+        pollfds.extend_from_slice(extra);
+        let mut has_pending = false;
+        for (key, slot) in self.connections.iter().enumerate() {
+            if let Some(ref connection) = *slot {
+                has_pending |= !connection.is_paused() && connection.has_pending_messages();
+                for fd in Some(connection.get_socket().get_fd())
+                    .into_iter()
+                    .chain(connection.get_remote_fd()) {
+                    keys.push(key);
+                    pollfds.push(libc::pollfd {
+                                     fd,
+                                     events: libc::POLLIN,
+                                     revents: 0,
+                                 });
+                }
+            }
+        }
+
+        let timeout_ms = match (has_pending, timeout) {
+            (true, _) => 0,
+            (false, Some(duration)) => {
+                // Round up, so waiting for timer deadline does not end before it.
+                let millis = (duration.subsec_nanos() as u64 + 999_999) / 1_000_000;
+                (duration.as_secs() * 1000 + millis) as libc::c_int
+            }
+            (false, None) => -1,
+        };
+
+        let res = unsafe {
+            libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms)
+        };
+        match Errno::result(res) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        }
+        let num_extra = extra.len();
+        for (pollfd, polled) in extra.iter_mut().zip(pollfds.drain(..num_extra)) {
+            pollfd.revents = polled.revents;
+        }
+
+        let mut results = Vec::new();
+        for (key, slot) in self.connections.iter_mut().enumerate() {
+            if let Some(ref mut connection) = *slot {
+                let ready = keys.iter()
+                    .zip(pollfds.iter())
+                    .any(|(k, pollfd)| *k == key && pollfd.revents != 0);
+                if ready {
+                    results.push((key, connection.process_events_with_report()));
+                } else if !connection.is_paused() && connection.has_pending_messages() {
+                    results.push((key, connection.dispatch_pending()));
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
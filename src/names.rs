@@ -0,0 +1,189 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Process-wide registry of interface and message names used in diagnostics.
+//!
+//! Errors like `WrongOpcode` carry only interface name and opcode. When metadata of the interface
+//! was registered (by generated code or manually) logs and dispatch reports can name the message,
+//! e.g. `wl_surface.attach` instead of `opcode 1`. Metadata set with `Bundle::set_interface_meta`
+//! is used only by diagnostics of its own connection and takes precedence over this registry.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+
+use defs::Side;
+use meta::{InterfaceMeta, MessageMeta};
+use trace::escape_json;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Registered interfaces by name.
+static INTERFACES: Mutex<BTreeMap<&'static str, &'static InterfaceMeta>> =
+    Mutex::new(BTreeMap::new());
+
+/// Locks the registry.
+fn lock_registry() -> MutexGuard<'static, BTreeMap<&'static str, &'static InterfaceMeta>> {
+    INTERFACES.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Registers metadata of interface so its message names can be resolved. Registering interface
+/// with the same name again replaces previous metadata.
+pub fn register_interface(meta: &'static InterfaceMeta) {
+    lock_registry().insert(meta.name, meta);
+}
+
+/// Returns metadata of interface with given name if registered.
+pub fn get_interface(name: &str) -> Option<&'static InterfaceMeta> {
+    lock_registry().get(name).cloned()
+}
+
+/// Returns name of message with given opcode received by given `side` of connection on object
+/// implementing `interface`.
+pub fn get_message_name(interface: &str, opcode: u16, side: Side) -> Option<&'static str> {
+    get_interface(interface)
+        .and_then(|meta| meta.get_incoming(side).get(opcode as usize))
+        .map(|message| message.name)
+}
+
+/// Returns human-readable name of message with given opcode received by given `side`, e.g.
+/// `wl_surface.attach`. Falls back to `wl_surface#1` if the message is not known.
+pub fn describe_message(interface: &str, opcode: u16, side: Side) -> String {
+    match get_interface(interface) {
+        Some(meta) => describe_with_meta(meta, opcode, side),
+        None => format!("{}#{}", interface, opcode),
+    }
+}
+
+/// Works like `describe_message` but resolves the name using given metadata instead of the
+/// registry.
+pub fn describe_with_meta(meta: &InterfaceMeta, opcode: u16, side: Side) -> String {
+    match meta.get_incoming(side).get(opcode as usize) {
+        Some(message) => format!("{}.{}", meta.name, message.name),
+        None => format!("{}#{}", meta.name, opcode),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Format of report generated by `describe_protocol`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Indented plain text meant for humans.
+    Text,
+
+    /// JSON document meant for tools.
+    Json,
+}
+
+/// Returns metadata of all registered interfaces sorted by name.
+pub fn get_interfaces() -> Vec<&'static InterfaceMeta> {
+    lock_registry().values().cloned().collect()
+}
+
+/// Renders report describing all registered interfaces: their versions and opcodes, names,
+/// signatures and versions of their requests and events.
+///
+/// In text format each interface is described as
+///
+/// ```text
+/// wl_surface v4
+///   requests:
+///     0 destroy()
+///     1 attach(?oii)
+///     8 set_buffer_scale(i) since v3
+///   events:
+///     0 enter(o)
+/// ```
+///
+/// JSON document has form `{"interfaces": [{"name": ..., "version": ..., "requests": [{"opcode":
+/// ..., "name": ..., "signature": ..., "since": ...}, ...], "events": [...]}, ...]}`. Signatures
+/// are given without version prefix.
+pub fn describe_protocol(format: ReportFormat) -> String {
+    let interfaces = get_interfaces();
+    let mut report = String::new();
+    match format {
+        ReportFormat::Text => {
+            for meta in interfaces {
+                write_interface_text(&mut report, meta);
+            }
+        }
+        ReportFormat::Json => {
+            report.push_str("{\"interfaces\":[");
+            for (i, meta) in interfaces.iter().enumerate() {
+                if i > 0 {
+                    report.push(',');
+                }
+                write_interface_json(&mut report, meta);
+            }
+            report.push_str("]}");
+        }
+    }
+    report
+}
+
+/// Writes description of interface in text format.
+fn write_interface_text(report: &mut String, meta: &InterfaceMeta) {
+    let _ = writeln!(report, "{} v{}", meta.name, meta.version);
+    for &(title, messages) in &[("requests", meta.requests), ("events", meta.events)] {
+        if messages.is_empty() {
+            continue;
+        }
+        let _ = writeln!(report, "  {}:", title);
+        for (opcode, message) in messages.iter().enumerate() {
+            let _ = write!(report, "    {} {}({})", opcode, message.name, get_arguments(message));
+            if message.get_since() > 1 {
+                let _ = write!(report, " since v{}", message.get_since());
+            }
+            report.push('\n');
+        }
+    }
+}
+
+/// Writes description of interface in JSON format.
+fn write_interface_json(report: &mut String, meta: &InterfaceMeta) {
+    let _ = write!(report,
+                   "{{\"name\":\"{}\",\"version\":{}",
+                   escape_json(meta.name),
+                   meta.version);
+    for &(title, messages) in &[("requests", meta.requests), ("events", meta.events)] {
+        let _ = write!(report, ",\"{}\":[", title);
+        for (opcode, message) in messages.iter().enumerate() {
+            if opcode > 0 {
+                report.push(',');
+            }
+            let _ = write!(report,
+                           "{{\"opcode\":{},\"name\":\"{}\",\"signature\":\"{}\",\
+                            \"since\":{}}}",
+                           opcode,
+                           escape_json(message.name),
+                           escape_json(get_arguments(message)),
+                           message.get_since());
+        }
+        report.push(']');
+    }
+    report.push('}');
+}
+
+/// Returns signature of message without version prefix.
+fn get_arguments(message: &MessageMeta) -> &'static str {
+    message.signature.trim_start_matches(|c: char| c.is_ascii_digit())
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -19,18 +19,33 @@
 
 use std;
 
-use defs::{Header, SkylaneError, Task};
+use defs::{Header, Side, SkylaneError, Task};
 use bundle::Bundle;
+use message::Message;
 
 // -------------------------------------------------------------------------------------------------
 
 /// Structure representing ID of protocol object.
+///
+/// IDs are opaque: no arithmetic is provided. IDs for new objects should be obtained from
+/// `Bundle::get_next_available_client_object_id` or
+/// `Bundle::get_next_available_server_object_id`. IDs received from the peer are decoded by
+/// `Message`; IDs known upfront are provided as constants (see `DISPLAY_ID`). Ordering follows
+/// numerical values, so client IDs come before server IDs.
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ObjectId(u32);
 
 impl ObjectId {
     /// Constructs new `ObjectId`.
-    pub fn new(value: u32) -> Self {
+    pub(crate) fn new(value: u32) -> Self {
+        ObjectId(value)
+    }
+
+    /// Constructs `ObjectId` from raw value without checking whether the object exists or whether
+    /// this side of connection is allowed to allocate it.
+    ///
+    /// Meant for IDs obtained outside of `skylane` (e.g. from hand-decoded messages) and for tests.
+    pub fn from_raw_unchecked(value: u32) -> Self {
         ObjectId(value)
     }
 
@@ -39,14 +54,27 @@ impl ObjectId {
         self.0
     }
 
-    /// Returns new `ObjectId` incremented by `1`.
-    pub fn incremented(&self) -> ObjectId {
-        ObjectId(self.0 + 1)
+    /// Checks if this is `NULL_ID`, which on the wire denotes absence of object.
+    pub fn is_null(&self) -> bool {
+        *self == NULL_ID
     }
 
-    /// Checks if object ID is valid.
-    pub fn is_null(&self) -> bool {
-        self.0 == 0
+    /// Checks if ID belongs to range of IDs allocated by client.
+    pub fn is_client_range(&self) -> bool {
+        !self.is_null() && *self < SERVER_START_ID
+    }
+
+    /// Checks if ID belongs to range of IDs allocated by server.
+    pub fn is_server_range(&self) -> bool {
+        *self >= SERVER_START_ID
+    }
+
+    /// Checks if ID belongs to range of IDs allocated by given side of connection.
+    pub fn is_in_range_of(&self, side: Side) -> bool {
+        match side {
+            Side::Client => self.is_client_range(),
+            Side::Server => self.is_server_range(),
+        }
     }
 }
 
@@ -62,30 +90,133 @@ impl std::fmt::Debug for ObjectId {
     }
 }
 
-/// Default ID of main global object.
+impl From<ObjectId> for u32 {
+    fn from(id: ObjectId) -> u32 {
+        id.get_value()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Object ID tagged with type representing interface of the object.
+///
+/// Helps to avoid passing ID of object of one interface where ID of other interface is expected.
+/// `I` is used only as marker. Raw `ObjectId` is still used on the wire.
+pub struct TypedObjectId<I> {
+    id: ObjectId,
+    _interface: std::marker::PhantomData<fn() -> I>,
+}
+
+impl<I> TypedObjectId<I> {
+    /// Constructs new `TypedObjectId` from raw ID.
+    pub fn new(id: ObjectId) -> Self {
+        TypedObjectId {
+            id,
+            _interface: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns raw ID.
+    pub fn get_id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+impl<I> From<TypedObjectId<I>> for ObjectId {
+    fn from(id: TypedObjectId<I>) -> ObjectId {
+        id.id
+    }
+}
+
+impl<I> Clone for TypedObjectId<I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I> Copy for TypedObjectId<I> {}
+
+impl<I> PartialEq for TypedObjectId<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<I> Eq for TypedObjectId<I> {}
+
+impl<I> std::hash::Hash for TypedObjectId<I> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<I> std::fmt::Display for TypedObjectId<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+impl<I> std::fmt::Debug for TypedObjectId<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.id)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// ID used on the wire in place of nullable object or new ID argument to denote absence of object.
+/// Objects must not be registered with this ID.
+pub const NULL_ID: ObjectId = ObjectId(0);
+
+/// Default ID of main global object. This is also the first ID in client range.
 pub const DISPLAY_ID: ObjectId = ObjectId(1);
 
 /// In Wayland object ID can be generated by client or by server. Client is allowed to generate ID
 /// only below 0xff000000, server only above.
 pub const SERVER_START_ID: ObjectId = ObjectId(0xff000000);
 
+/// Last ID in range of IDs allocated by client.
+pub const CLIENT_END_ID: ObjectId = ObjectId(0xfeffffff);
+
+/// Last ID in range of IDs allocated by server.
+pub const SERVER_END_ID: ObjectId = ObjectId(0xffffffff);
+
 // -------------------------------------------------------------------------------------------------
 
 /// This trait has to be implemented by all objects to be registered as message handlers in
 /// `Connection`.
+///
+/// Implementations should provide `dispatch_message`. `dispatch` is kept for compatibility with
+/// older code - by default `dispatch_message` forwards to it.
 pub trait Object {
+    /// Informs implementation about incoming message.
+    ///
+    /// - `bundle` provides access to socket and registered objects.
+    /// - `message` provides access to header, arguments and file descriptors.
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        let (header, bytes_buf, fds_buf) = message.as_raw_parts();
+        self.dispatch(bundle, header, bytes_buf, fds_buf)
+    }
+
     /// Informs implementation about incoming message.
     ///
     /// - `bundle` provides access to socket and registered objects.
     /// - `header` defines what method was called for what objects.
     /// - `bytes_buf` contains raw message without header.
     /// - `fds_buf` contains received file descriptors.
+    ///
+    /// Deprecated in favour of `dispatch_message`.
     fn dispatch(&mut self,
-                bundle: &mut Bundle,
+                _bundle: &mut Bundle,
                 header: &Header,
-                bytes_buf: &mut std::io::Cursor<&[u8]>,
-                fds_buf: &mut std::io::Cursor<&[u8]>)
-                -> Result<Task, SkylaneError>;
+                _bytes_buf: &mut std::io::Cursor<&[u8]>,
+                _fds_buf: &mut std::io::Cursor<&[u8]>)
+                -> Result<Task, SkylaneError> {
+        Err(SkylaneError::Other(format!("Dispatching not implemented ({:?})", header)))
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
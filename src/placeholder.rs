@@ -0,0 +1,93 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Placeholder objects queuing received messages until the real handler is attached.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use defs::{Header, SkylaneError, Task};
+use bundle::{Bundle, BundleInternal};
+use fd::OwnedFd;
+use message::Message;
+use object::Object;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Message received by placeholder object.
+pub struct PendingMessage {
+    /// Header of the message.
+    pub header: Header,
+
+    /// Raw arguments of the message.
+    pub args: Vec<u8>,
+
+    /// File descriptors received along with the message.
+    pub fds: Vec<OwnedFd>,
+}
+
+/// Queue of messages received by placeholder object shared with `Bundle`.
+pub type PendingQueue = Rc<RefCell<VecDeque<PendingMessage>>>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler registered in place of object whose real handler is not constructed yet. Stores all
+/// received messages so they can be replayed when the real handler is attached.
+///
+/// Number of file descriptors carried by each message is known only if metadata were registered
+/// for the object (see `Bundle::set_interface_meta`).
+pub struct Placeholder {
+    queue: PendingQueue,
+}
+
+impl Placeholder {
+    /// Constructs new `Placeholder` storing messages in `queue`.
+    pub fn new(queue: PendingQueue) -> Self {
+        Placeholder { queue }
+    }
+}
+
+impl Object for Placeholder {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        let header = *message.get_header();
+        let num_fds = bundle.get_incoming_fd_count(message.get_object_id(), header.opcode)
+            .unwrap_or(0);
+
+        let mut fds = Vec::with_capacity(num_fds);
+        for _ in 0..num_fds {
+            fds.push(message.next_fd()?);
+        }
+
+        let args = {
+            let (_, args, _) = message.as_raw_parts();
+            let position = args.position() as usize;
+            args.get_ref()[position..].to_vec()
+        };
+
+        self.queue.borrow_mut().push_back(PendingMessage {
+                                              header,
+                                              args,
+                                              fds,
+                                          });
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,58 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Pool of reusable buffers for outgoing messages.
+
+// -------------------------------------------------------------------------------------------------
+
+/// Maximal number of buffers kept in pool.
+const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Buffers bigger than this are not returned to the pool to avoid holding big allocations.
+const MAX_POOLED_CAPACITY: usize = 4096;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Pool of byte buffers.
+///
+/// Marshalling a message requires a buffer. Instead of allocating new one for every message
+/// buffers are taken from the pool and returned to it after the message was sent.
+pub struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Constructs new empty `BufferPool`.
+    pub fn new() -> Self {
+        BufferPool { buffers: Vec::new() }
+    }
+
+    /// Returns empty buffer. Reuses one of released buffers if available.
+    pub fn acquire(&mut self) -> Vec<u8> {
+        self.buffers.pop().unwrap_or_default()
+    }
+
+    /// Returns buffer to the pool.
+    pub fn release(&mut self, mut buffer: Vec<u8>) {
+        if self.buffers.len() < MAX_POOLED_BUFFERS && buffer.capacity() <= MAX_POOLED_CAPACITY {
+            buffer.clear();
+            self.buffers.push(buffer);
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
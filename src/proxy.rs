@@ -0,0 +1,165 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Client-side handles of protocol objects.
+
+use defs::SkylaneError;
+use bundle::{Bundle, BundleInternal};
+use marshal::Marshaller;
+use object::{Object, ObjectId, TypedObjectId};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Client-side handle of protocol object used to send requests to it.
+///
+/// `Proxy` is meant as a base for client code generated by `skylane_scanner`. For every interface
+/// generated code contains:
+///
+///  - struct wrapping `Proxy<I>` with typed method for every request (composing arguments with
+///    `send_request`),
+///  - trait with method for every event,
+///  - `Object` implementation decoding events with `Message` accessors and calling the trait.
+///
+/// The object is registered with `Connection::create_proxy` (or `add_next_client_object`) and
+/// application never has to decode raw messages by hand. Objects created by requests are
+/// registered with `send_constructor` and destroyed with `destroy`.
+pub struct Proxy<I> {
+    id: TypedObjectId<I>,
+    bundle: Bundle,
+}
+
+impl<I> Proxy<I> {
+    /// Constructs new `Proxy` for already registered object with given ID.
+    ///
+    /// Useful in handlers receiving ID of object created by server (`new_id` event argument).
+    pub fn new(bundle: &Bundle, id: ObjectId) -> Self {
+        Proxy {
+            id: TypedObjectId::new(id),
+            bundle: bundle.duplicate(),
+        }
+    }
+
+    /// Returns ID of the object.
+    pub fn get_id(&self) -> ObjectId {
+        self.id.get_id()
+    }
+
+    /// Returns typed ID of the object.
+    pub fn get_typed_id(&self) -> TypedObjectId<I> {
+        self.id
+    }
+
+    /// Returns version of interface bound for the object if known (see
+    /// `Bundle::set_object_version`).
+    pub fn get_version(&self) -> Option<u32> {
+        self.bundle.get_object_version(self.get_id())
+    }
+
+    /// Sets version of interface bound for the object.
+    ///
+    /// See `Bundle::set_object_version`.
+    pub fn set_version(&mut self, version: u32) {
+        let id = self.get_id();
+        self.bundle.set_object_version(id, version);
+    }
+
+    /// Checks if the object is still registered, i.e. it was not destroyed or removed.
+    pub fn is_alive(&self) -> bool {
+        self.bundle.get_weak_ref(self.get_id()).is_some()
+    }
+
+    /// Replaces handler of events of the object.
+    ///
+    /// See `Bundle::replace_object`.
+    pub fn set_handler(&mut self, object: Box<dyn Object>) -> Result<(), SkylaneError> {
+        let id = self.get_id();
+        self.bundle.replace_object(id, object)
+    }
+
+    /// Composes request with given opcode using `compose` and sends it.
+    pub fn send_request<F>(&self, opcode: u16, compose: F) -> Result<(), SkylaneError>
+        where F: FnOnce(&mut Marshaller)
+    {
+        self.bundle.send_marshalled(self.get_id(), opcode, compose)
+    }
+
+    /// Composes request with given opcode using `compose` and queues it until next flush.
+    ///
+    /// See `Bundle::send`.
+    pub fn queue_request<F>(&self, opcode: u16, compose: F)
+        where F: FnOnce(&mut Marshaller)
+    {
+        self.bundle.send(self.get_id(), opcode, compose);
+    }
+
+    /// Registers `object` with next available client ID and returns `Proxy` for it. Meant for
+    /// requests creating new objects: the returned ID should be passed as `new_id` argument.
+    pub fn create_child<J>(&mut self, object: Box<dyn Object>) -> Result<Proxy<J>, SkylaneError> {
+        let id = self.bundle.add_next_client_object(object)?;
+        Ok(Proxy::new(&self.bundle, id))
+    }
+
+    /// Registers `object` with next available client ID and sends request with given opcode
+    /// creating it. `compose` receives ID of the new object to be put as `new_id` argument.
+    ///
+    /// If the request could not be sent the new object is unregistered.
+    pub fn send_constructor<J, F>(&mut self,
+                                  opcode: u16,
+                                  object: Box<dyn Object>,
+                                  compose: F)
+                                  -> Result<Proxy<J>, SkylaneError>
+        where F: FnOnce(&mut Marshaller, ObjectId)
+    {
+        let child = self.create_child(object)?;
+        let child_id = child.get_id();
+        let result = self.send_request(opcode, |marshaller| compose(marshaller, child_id));
+        if let Err(err) = result {
+            // Server never learned about the object, so there is no removal to be confirmed.
+            self.bundle.confirm_delete(child_id);
+            return Err(err);
+        }
+        Ok(child)
+    }
+
+    /// Sends destructor request with given opcode and removes the object. The object stays a
+    /// zombie until server confirms the removal with `wl_display.delete_id`.
+    ///
+    /// The object is removed even if the request could not be sent.
+    pub fn destroy(mut self, opcode: u16) -> Result<(), SkylaneError> {
+        let result = self.send_request(opcode, |_| {});
+        self.remove();
+        result
+    }
+
+    /// Removes the object without sending any request, e.g. after server destroyed it with an
+    /// event (like `wl_callback.done`).
+    ///
+    /// See `Bundle::remove_object`.
+    pub fn remove(&mut self) {
+        let id = self.get_id();
+        self.bundle.remove_object(id);
+    }
+}
+
+/// `Bundle` does not implement `Clone`, so `Proxy` must implement it manually.
+impl<I> Clone for Proxy<I> {
+    fn clone(&self) -> Self {
+        Proxy::new(&self.bundle, self.get_id())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,295 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Queue of outgoing messages deferred until flush.
+
+use std;
+use std::os::unix::io::RawFd;
+
+use endian::Endianness;
+use fd::OwnedFd;
+use marshal::HEADER_SIZE;
+use sockets::MAX_FDS;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Priority of queued message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Bulk traffic.
+    #[default]
+    Normal,
+
+    /// Latency-critical messages (e.g. input events) written before all queued normal messages.
+    High,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Sequence of messages with attached file descriptors. Every descriptor is tagged with offset of
+/// the message it was queued with, so it is passed along with that message.
+struct Lane {
+    bytes: Vec<u8>,
+    fds: Vec<(usize, RawFd)>,
+}
+
+impl Lane {
+    /// Constructs new empty `Lane`.
+    fn new() -> Self {
+        Lane {
+            bytes: Vec::new(),
+            fds: Vec::new(),
+        }
+    }
+
+    /// Appends messages `bytes` with file descriptors `fds` attached to the first of them.
+    fn push(&mut self, bytes: &[u8], fds: &[RawFd]) {
+        let offset = self.bytes.len();
+        self.bytes.extend_from_slice(bytes);
+        self.fds.extend(fds.iter().map(|fd| (offset, *fd)));
+    }
+
+    /// Appends data from `lane` starting at `start` along with descriptors not passed yet.
+    fn push_lane(&mut self, lane: &Lane, start: usize) {
+        let offset = self.bytes.len();
+        self.bytes.extend_from_slice(&lane.bytes[start..]);
+        self.fds.extend(lane.fds
+                            .iter()
+                            .map(|&(position, fd)| (offset + position.saturating_sub(start), fd)));
+    }
+
+    /// Returns end of the first message ending at or after `position`.
+    fn get_boundary(&self, position: usize) -> usize {
+        let mut end = 0;
+        while end < position && end + HEADER_SIZE <= self.bytes.len() {
+            let size = Endianness::native()
+                .read_header(&self.bytes[end..])
+                .map_or(0, |header| header.size as usize);
+            if size < HEADER_SIZE {
+                // Malformed message; can not find boundaries anymore.
+                return self.bytes.len();
+            }
+            end += size;
+        }
+        if end < position {
+            self.bytes.len()
+        } else {
+            std::cmp::min(end, self.bytes.len())
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Part of queued data which can be written in one `sendmsg` call: for every lane number of bytes
+/// and number of file descriptors.
+struct Batch {
+    lanes: [(usize, usize); 3],
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Queue of outgoing messages.
+///
+/// Messages are kept in two lanes by priority. On flush high-priority messages are written before
+/// normal ones, but never in the middle of a message: the rest of a partially written message is
+/// always written first.
+///
+/// File descriptors are passed in the same `sendmsg` call as the beginning of the message they
+/// were queued with. If there are too many descriptors to be passed at once, data is written in
+/// batches ending before the first message whose descriptors did not fit.
+///
+/// Queue may own some of the descriptors (see `adopt`). They are closed once passed to the peer.
+pub struct OutgoingQueue {
+    partial: Lane,
+    high: Lane,
+    normal: Lane,
+    owned: Vec<OwnedFd>,
+}
+
+impl OutgoingQueue {
+    /// Constructs new empty `OutgoingQueue`.
+    pub fn new() -> Self {
+        OutgoingQueue {
+            partial: Lane::new(),
+            high: Lane::new(),
+            normal: Lane::new(),
+            owned: Vec::new(),
+        }
+    }
+
+    /// Appends message with normal priority.
+    pub fn push(&mut self, bytes: &[u8], fds: &[RawFd]) {
+        self.push_with_priority(bytes, fds, Priority::Normal);
+    }
+
+    /// Appends message to lane of given priority.
+    pub fn push_with_priority(&mut self, bytes: &[u8], fds: &[RawFd], priority: Priority) {
+        self.get_lane_mut(priority).push(bytes, fds);
+    }
+
+    /// Queues data not written by direct write. `bytes` are messages of which first `written`
+    /// bytes were written along with `fds`. The rest of partially written message will be written
+    /// first on next flush.
+    pub fn push_unwritten(&mut self, bytes: &[u8], fds: &[RawFd], written: usize) {
+        let mut lane = Lane::new();
+        lane.push(bytes, if written == 0 { fds } else { &[] });
+        self.consume_lane(lane, written, Priority::Normal);
+    }
+
+    /// Takes ownership of descriptors `fds` queued earlier, so they are closed after they were
+    /// passed. Descriptors not queued anymore are closed immediately.
+    pub fn adopt(&mut self, fds: Vec<OwnedFd>) {
+        self.owned.extend(fds);
+        self.release_passed();
+    }
+
+    /// Checks if there are no queued messages.
+    pub fn is_empty(&self) -> bool {
+        self.partial.bytes.is_empty() && self.high.bytes.is_empty() &&
+        self.normal.bytes.is_empty()
+    }
+
+    /// Returns queued data which should be written in one `sendmsg` call, in order they should be
+    /// written, and file descriptors to be passed with them. If not all queued data is returned
+    /// the rest should be obtained after the returned data was written and consumed.
+    pub fn get_data(&self) -> (Vec<&[u8]>, Vec<RawFd>) {
+        let batch = self.get_batch();
+        let mut slices = Vec::new();
+        let mut fds = Vec::new();
+        for (lane, &(num_bytes, num_fds)) in self.get_lanes().iter().zip(batch.lanes.iter()) {
+            if num_bytes > 0 {
+                slices.push(&lane.bytes[..num_bytes]);
+            }
+            fds.extend(lane.fds[..num_fds].iter().map(|&(_, fd)| fd));
+        }
+        (slices, fds)
+    }
+
+    /// Removes data written to socket after they were obtained with `get_data`.
+    ///
+    /// File descriptors are passed along with the first written byte, so all returned by
+    /// `get_data` are removed if anything was written.
+    pub fn consume(&mut self, written: usize) {
+        if written == 0 {
+            return;
+        }
+
+        let batch = self.get_batch();
+        let mut partial = std::mem::replace(&mut self.partial, Lane::new());
+        let mut high = std::mem::replace(&mut self.high, Lane::new());
+        let mut normal = std::mem::replace(&mut self.normal, Lane::new());
+        for (lane, &(_, num_fds)) in [&mut partial, &mut high, &mut normal]
+            .iter_mut()
+            .zip(batch.lanes.iter()) {
+            lane.fds.drain(..num_fds);
+        }
+
+        let mut remaining = written;
+        if remaining < partial.bytes.len() {
+            self.partial.push_lane(&partial, remaining);
+            remaining = 0;
+        } else {
+            remaining -= partial.bytes.len();
+        }
+
+        for (lane, priority) in [(high, Priority::High), (normal, Priority::Normal)] {
+            let len = lane.bytes.len();
+            self.consume_lane(lane, remaining, priority);
+            remaining -= std::cmp::min(remaining, len);
+        }
+        self.release_passed();
+    }
+
+    /// Takes all queued data along with all file descriptors to be passed with it. Descriptors
+    /// owned by the queue are returned separately: they must be kept open until the data was
+    /// written and dropped afterwards. The rest stay owned by whoever queued them.
+    pub fn drain(&mut self) -> (Vec<u8>, Vec<RawFd>, Vec<OwnedFd>) {
+        let owned = std::mem::take(&mut self.owned);
+        let (bytes, fds) = {
+            let lanes = self.get_lanes();
+            let bytes = lanes.iter().map(|lane| &lane.bytes[..]).collect::<Vec<_>>().concat();
+            let fds = lanes.iter().flat_map(|lane| lane.fds.iter().map(|&(_, fd)| fd)).collect();
+            (bytes, fds)
+        };
+        *self = OutgoingQueue::new();
+        (bytes, fds, owned)
+    }
+}
+
+/// Private methods.
+impl OutgoingQueue {
+    /// Returns lanes in order they should be written.
+    fn get_lanes(&self) -> [&Lane; 3] {
+        [&self.partial, &self.high, &self.normal]
+    }
+
+    /// Returns lane of given priority.
+    fn get_lane_mut(&mut self, priority: Priority) -> &mut Lane {
+        match priority {
+            Priority::Normal => &mut self.normal,
+            Priority::High => &mut self.high,
+        }
+    }
+
+    /// Decides which data should be written in next `sendmsg` call. Descriptors of the first
+    /// message are always included, even if there are more of them than can be passed at once.
+    fn get_batch(&self) -> Batch {
+        let mut batch = Batch { lanes: [(0, 0); 3] };
+        let mut total_fds = 0;
+        for (i, lane) in self.get_lanes().iter().enumerate() {
+            let mut num_fds = 0;
+            while num_fds < lane.fds.len() {
+                let offset = lane.fds[num_fds].0;
+                let count = lane.fds[num_fds..].iter().take_while(|&&(o, _)| o == offset).count();
+                if total_fds > 0 && total_fds + count > MAX_FDS {
+                    // Write data up to the message whose descriptors do not fit.
+                    batch.lanes[i] = (offset, num_fds);
+                    return batch;
+                }
+                num_fds += count;
+                total_fds += count;
+            }
+            batch.lanes[i] = (lane.bytes.len(), num_fds);
+        }
+        batch
+    }
+
+    /// Closes owned descriptors not attached to any queued message.
+    fn release_passed(&mut self) {
+        let (partial, high, normal) = (&self.partial, &self.high, &self.normal);
+        self.owned.retain(|owned| {
+            [partial, high, normal]
+                .iter()
+                .any(|lane| lane.fds.iter().any(|&(_, fd)| fd == owned.get_fd()))
+        });
+    }
+
+    /// Puts back data from `lane` of which first `written` bytes were written. The rest of
+    /// partially written message is moved to the front of the queue.
+    fn consume_lane(&mut self, lane: Lane, written: usize, priority: Priority) {
+        let boundary = if written > 0 { lane.get_boundary(written) } else { 0 };
+        if written < boundary {
+            self.partial.bytes.extend_from_slice(&lane.bytes[written..boundary]);
+        }
+        if boundary < lane.bytes.len() || !lane.fds.is_empty() {
+            self.get_lane_mut(priority).push_lane(&lane, boundary);
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,365 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Coordination of reading from socket by many threads.
+//!
+//! Follows `libwayland` protocol: every thread wanting to read calls `Reader::prepare_read`,
+//! polls the socket and then calls `ReadIntent::read_events` or `ReadIntent::cancel`. Only the
+//! last of the threads actually reads the data, others wait for it. This way no data is lost nor
+//! dispatched twice.
+
+use std;
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use byteorder::{NativeEndian, ReadBytesExt};
+use nix;
+
+use credentials::Credentials;
+use defs::SkylaneError;
+use endian::Endianness;
+use limits::FdLimit;
+use sockets::{Socket, SocketInternal, MAX_FDS};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Default maximal number of bytes read from socket at once.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Data read from socket but not yet dispatched.
+struct Incoming {
+    bytes: Vec<u8>,
+    fds: VecDeque<RawFd>,
+    num_readers: u32,
+    read_serial: u64,
+    buffer_size: usize,
+    credentials: Option<Credentials>,
+    fd_limit: Option<FdLimit>,
+    is_overflowed: bool,
+}
+
+impl Incoming {
+    /// Checks if there is at least one complete message.
+    fn has_complete_message(&self) -> bool {
+        match Endianness::native().read_header(&self.bytes) {
+            Some(header) => self.bytes.len() >= header.size as usize,
+            None => false,
+        }
+    }
+
+    /// Appends received file descriptors.
+    ///
+    /// If there are more pending descriptors than allowed by the limit, all pending data are
+    /// discarded and descriptors are closed and counted in statistics of `socket`. Data received
+    /// afterwards are discarded the same way.
+    fn push_fds<I>(&mut self, fds: I, socket: &Socket) -> Result<(), SkylaneError>
+        where I: IntoIterator<Item = RawFd>
+    {
+        self.fds.extend(fds);
+        let max_fds = self.fd_limit.map_or(usize::MAX, |limit| limit.max_fds);
+        if !self.is_overflowed && self.fds.len() <= max_fds {
+            return Ok(());
+        }
+
+        self.is_overflowed = true;
+        let num_pending = self.fds.len();
+        self.discard();
+        socket.update_stats(|stats| stats.fds_dropped += num_pending as u64);
+        Err(SkylaneError::LimitExceeded {
+                description: format!("File descriptor limit ({}) exceeded; {} dropped",
+                                     max_fds,
+                                     num_pending),
+            })
+    }
+
+    /// Drops all pending data and closes pending file descriptors.
+    fn discard(&mut self) {
+        self.bytes.clear();
+        for fd in self.fds.drain(..) {
+            // Nothing can be done if closing fails.
+            let _ = nix::unistd::close(fd);
+        }
+    }
+}
+
+/// State shared by all `Reader`s of one connection.
+struct ReadState {
+    incoming: Mutex<Incoming>,
+    condvar: Condvar,
+
+    /// Held while reading from socket, so data read by concurrent readers is appended in order
+    /// without keeping `incoming` locked during blocking reads.
+    reading: Mutex<()>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handle allowing to read from connection socket. Can be sent to other threads.
+///
+/// Data read using `Reader` is dispatched by `Connection::dispatch_pending`.
+#[derive(Clone)]
+pub struct Reader {
+    socket: Socket,
+    state: Arc<ReadState>,
+}
+
+impl Reader {
+    /// Announces intention to read from socket.
+    ///
+    /// Returns `None` if there are messages already read but not yet dispatched. They should be
+    /// dispatched before reading again.
+    pub fn prepare_read(&self) -> Option<ReadIntent> {
+        let mut incoming = self.lock();
+        if incoming.has_complete_message() {
+            None
+        } else {
+            incoming.num_readers += 1;
+            Some(ReadIntent {
+                     reader: self.clone(),
+                     finished: false,
+                 })
+        }
+    }
+}
+
+/// Private methods.
+impl Reader {
+    /// Reads from socket and appends the data to incoming data. Shared state is not locked while
+    /// waiting for data, so other threads can dispatch messages read earlier.
+    fn read_into(&self) -> Result<usize, SkylaneError> {
+        let _reading = self.state.reading.lock().unwrap_or_else(|err| err.into_inner());
+        let mut bytes = vec![0; self.lock().buffer_size];
+        let mut fds: [u8; 4 * MAX_FDS] = [0; 4 * MAX_FDS];
+
+        let mut credentials = None;
+        let result = if self.socket.is_passing_credentials() {
+            self.socket
+                .receive_message_with_credentials(&mut bytes, &mut fds)
+                .map(|(bytes_size, fds_size, received)| {
+                         credentials = Some(received);
+                         (bytes_size, fds_size)
+                     })
+        } else {
+            self.socket.receive_message(&mut bytes, &mut fds)
+        };
+
+        let mut incoming = self.lock();
+        let (bytes_size, fds_size) = match result {
+            Ok(sizes) => sizes,
+            Err(err) => {
+                if let SkylaneError::LimitExceeded { .. } = err {
+                    // Descriptors were lost; it is not known which messages they belonged to.
+                    incoming.is_overflowed = true;
+                    incoming.discard();
+                }
+                return Err(err);
+            }
+        };
+        if let Some(credentials) = credentials {
+            incoming.credentials = credentials;
+        }
+        incoming.bytes.extend_from_slice(&bytes[..bytes_size]);
+
+        let mut fds_buf = Cursor::new(&fds[..]);
+        let mut received = Vec::with_capacity(fds_size);
+        for _ in 0..fds_size {
+            received.push(fds_buf.read_i32::<NativeEndian>()?);
+        }
+        incoming.push_fds(received, &self.socket)?;
+        Ok(bytes_size)
+    }
+
+    /// Locks shared state.
+    fn lock(&self) -> MutexGuard<'_, Incoming> {
+        self.state.incoming.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Intention to read from socket returned by `Reader::prepare_read`.
+///
+/// Must be finished with `read_events` or `cancel`. Dropping it is equivalent to `cancel`.
+pub struct ReadIntent {
+    reader: Reader,
+    finished: bool,
+}
+
+impl ReadIntent {
+    /// Reads data from socket if this is the last thread which prepared read. Otherwise waits
+    /// until the last thread reads or cancels. Returns number of bytes read by this thread.
+    pub fn read_events(mut self) -> Result<usize, SkylaneError> {
+        self.finished = true;
+        let mut incoming = self.reader.lock();
+        incoming.num_readers -= 1;
+        if incoming.num_readers == 0 {
+            drop(incoming);
+            let result = self.reader.read_into();
+            let mut incoming = self.reader.lock();
+            incoming.read_serial += 1;
+            self.reader.state.condvar.notify_all();
+            result
+        } else {
+            let serial = incoming.read_serial;
+            while incoming.read_serial == serial {
+                incoming = self.reader
+                    .state
+                    .condvar
+                    .wait(incoming)
+                    .unwrap_or_else(|err| err.into_inner());
+            }
+            Ok(0)
+        }
+    }
+
+    /// Cancels the intention to read.
+    pub fn cancel(mut self) {
+        self.do_cancel();
+    }
+}
+
+/// Private methods.
+impl ReadIntent {
+    /// Cancels the intention to read. If this was the last reader wakes up waiting threads.
+    fn do_cancel(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            let mut incoming = self.reader.lock();
+            incoming.num_readers -= 1;
+            if incoming.num_readers == 0 {
+                incoming.read_serial += 1;
+                self.reader.state.condvar.notify_all();
+            }
+        }
+    }
+}
+
+impl Drop for ReadIntent {
+    fn drop(&mut self) {
+        self.do_cancel();
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Methods of `Reader` available in this crate but not exported.
+pub trait ReaderInternal {
+    /// Constructs new `Reader`.
+    fn new(socket: Socket) -> Self;
+
+    /// Reads from socket without coordinating with other readers. Returns number of bytes read.
+    fn read(&self) -> Result<usize, SkylaneError>;
+
+    /// Takes all data read so far.
+    fn take_incoming(&self) -> (Vec<u8>, VecDeque<RawFd>);
+
+    /// Puts back data which was not dispatched. It is placed before data read in the meantime.
+    fn return_incoming(&self, bytes: &[u8], fds: VecDeque<RawFd>);
+
+    /// Appends data as if it was read from socket. File descriptors are subject to the limit set
+    /// with `set_fd_limit`.
+    fn feed(&self, bytes: &[u8], fds: VecDeque<RawFd>) -> Result<(), SkylaneError>;
+
+    /// Sets maximal number of bytes read from socket at once.
+    fn set_buffer_size(&self, size: usize);
+
+    /// Sets limit on number of received file descriptors not yet dispatched.
+    fn set_fd_limit(&self, limit: Option<FdLimit>);
+
+    /// Returns limit on number of received file descriptors not yet dispatched.
+    fn get_fd_limit(&self) -> Option<FdLimit>;
+
+    /// Checks if there is at least one complete message read but not dispatched.
+    fn has_pending_messages(&self) -> bool;
+
+    /// Returns credentials of the peer received along with the last read data.
+    fn get_credentials(&self) -> Option<Credentials>;
+}
+
+impl ReaderInternal for Reader {
+    fn new(socket: Socket) -> Self {
+        Reader {
+            socket,
+            state: Arc::new(ReadState {
+                                incoming: Mutex::new(Incoming {
+                                                         bytes: Vec::new(),
+                                                         fds: VecDeque::new(),
+                                                         num_readers: 0,
+                                                         read_serial: 0,
+                                                         buffer_size: DEFAULT_BUFFER_SIZE,
+                                                         credentials: None,
+                                                         fd_limit: None,
+                                                         is_overflowed: false,
+                                                     }),
+                                condvar: Condvar::new(),
+                                reading: Mutex::new(()),
+                            }),
+        }
+    }
+
+    fn read(&self) -> Result<usize, SkylaneError> {
+        self.read_into()
+    }
+
+    fn take_incoming(&self) -> (Vec<u8>, VecDeque<RawFd>) {
+        let mut incoming = self.lock();
+        (std::mem::take(&mut incoming.bytes), std::mem::take(&mut incoming.fds))
+    }
+
+    fn return_incoming(&self, bytes: &[u8], mut fds: VecDeque<RawFd>) {
+        let mut incoming = self.lock();
+        incoming.bytes.splice(0..0, bytes.iter().cloned());
+        fds.extend(incoming.fds.drain(..));
+        incoming.fds = fds;
+        if incoming.is_overflowed {
+            // Limit was exceeded while the data was being dispatched.
+            incoming.discard();
+        }
+    }
+
+    fn feed(&self, bytes: &[u8], fds: VecDeque<RawFd>) -> Result<(), SkylaneError> {
+        let mut incoming = self.lock();
+        incoming.bytes.extend_from_slice(bytes);
+        incoming.push_fds(fds, &self.socket)
+    }
+
+    fn set_buffer_size(&self, size: usize) {
+        self.lock().buffer_size = size;
+    }
+
+    fn set_fd_limit(&self, limit: Option<FdLimit>) {
+        self.lock().fd_limit = limit;
+    }
+
+    fn get_fd_limit(&self) -> Option<FdLimit> {
+        self.lock().fd_limit
+    }
+
+    fn has_pending_messages(&self) -> bool {
+        self.lock().has_complete_message()
+    }
+
+    fn get_credentials(&self) -> Option<Credentials> {
+        self.lock().credentials
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
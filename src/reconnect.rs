@@ -0,0 +1,71 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Definitions related to automatic reconnection on client side.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use defs::SkylaneError;
+use connection::Connection;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Callback invoked after connection was re-established.
+///
+/// Passed `Connection` has no objects registered. The callback should register `wl_display`
+/// implementation (e.g. `ClientDisplay`), create registry, bind globals and recreate all objects
+/// the application needs.
+pub type RebindCallback = Box<dyn FnMut(&mut Connection) -> Result<(), SkylaneError>>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Policy of re-establishing connection when server disconnects (e.g. when compositor restarts).
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Path to display socket. If `None` default path is used (see `get_default_socket_path`).
+    pub path: Option<PathBuf>,
+
+    /// Maximal number of connection attempts.
+    pub max_attempts: u32,
+
+    /// Time to wait between attempts.
+    pub interval: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Constructs new `ReconnectPolicy` making 10 attempts in 100ms intervals.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        ReconnectPolicy {
+            path,
+            max_attempts: 10,
+            interval: Duration::from_millis(100),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reconnection settings kept by `Connection`.
+pub struct Reconnect {
+    /// Reconnection policy.
+    pub policy: ReconnectPolicy,
+
+    /// Callback recreating state after reconnecting. `None` while the callback is running.
+    pub rebind: Option<RebindCallback>,
+}
+
+// -------------------------------------------------------------------------------------------------
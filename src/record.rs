@@ -0,0 +1,184 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Recording and replaying of wire sessions.
+//!
+//! Recording is a sequence of entries, each storing direction, time since start of recording,
+//! number of passed file descriptors and raw bytes. File descriptors can not be recorded, so only
+//! their number is kept. All numbers are stored in little endian.
+
+use std;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use defs::{Direction, SkylaneError};
+use connection::{Connection, ConnectionInternal};
+use dispatch::DispatchReport;
+
+// -------------------------------------------------------------------------------------------------
+
+const INCOMING: u8 = 0;
+const OUTGOING: u8 = 1;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Single recorded chunk of data.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Direction of the data.
+    pub direction: Direction,
+
+    /// Time since start of recording.
+    pub timestamp: Duration,
+
+    /// Number of file descriptors passed with the data.
+    pub num_fds: u32,
+
+    /// Raw data as read from or written to socket.
+    pub bytes: Vec<u8>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Records data passing through socket to a file.
+///
+/// Set with `Socket::set_recorder`. Clones write to the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    output: Arc<Mutex<BufWriter<File>>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates new recording file.
+    pub fn create(path: &Path) -> Result<Self, SkylaneError> {
+        Ok(Recorder {
+               output: Arc::new(Mutex::new(BufWriter::new(File::create(path)?))),
+               start: Instant::now(),
+           })
+    }
+
+    /// Appends entry to recording.
+    pub fn record(&self,
+                  direction: Direction,
+                  bytes: &[u8],
+                  num_fds: usize)
+                  -> Result<(), SkylaneError> {
+        let elapsed = self.start.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_nanos() as u64 / 1000;
+        let mut output = self.output.lock().unwrap_or_else(|err| err.into_inner());
+        output.write_u8(match direction {
+                            Direction::Incoming => INCOMING,
+                            Direction::Outgoing => OUTGOING,
+                        })?;
+        output.write_u64::<LittleEndian>(micros)?;
+        output.write_u32::<LittleEndian>(num_fds as u32)?;
+        output.write_u32::<LittleEndian>(bytes.len() as u32)?;
+        output.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Writes buffered entries to the file.
+    pub fn flush(&self) -> Result<(), SkylaneError> {
+        let mut output = self.output.lock().unwrap_or_else(|err| err.into_inner());
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reads recording and feeds recorded incoming data into `Connection`.
+pub struct Replayer {
+    input: BufReader<File>,
+}
+
+impl Replayer {
+    /// Opens recording file.
+    pub fn open(path: &Path) -> Result<Self, SkylaneError> {
+        Ok(Replayer { input: BufReader::new(File::open(path)?) })
+    }
+
+    /// Reads next entry. Returns `None` at the end of recording.
+    pub fn next_entry(&mut self) -> Result<Option<Entry>, SkylaneError> {
+        let direction = match self.input.read_u8() {
+            Ok(INCOMING) => Direction::Incoming,
+            Ok(OUTGOING) => Direction::Outgoing,
+            Ok(other) => {
+                return Err(SkylaneError::Other(format!("Invalid direction in recording: {}",
+                                                       other)));
+            }
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let micros = self.input.read_u64::<LittleEndian>()?;
+        let num_fds = self.input.read_u32::<LittleEndian>()?;
+        let size = self.input.read_u32::<LittleEndian>()? as usize;
+        let mut bytes = vec![0; size];
+        self.input.read_exact(&mut bytes)?;
+        let timestamp = Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000);
+        Ok(Some(Entry {
+                    direction,
+                    timestamp,
+                    num_fds,
+                    bytes,
+                }))
+    }
+
+    /// Feeds all remaining incoming entries into `connection` and dispatches them in recorded
+    /// order. Outgoing entries are skipped. Instead of recorded file descriptors handlers receive
+    /// descriptors of `/dev/null`.
+    ///
+    /// Messages sent by handlers are written to socket of `connection`, so it should be e.g. one
+    /// end of `Socket::pair`.
+    pub fn replay(&mut self, connection: &mut Connection) -> Result<DispatchReport, SkylaneError> {
+        let mut report = DispatchReport::default();
+        while let Some(entry) = self.next_entry()? {
+            if entry.direction != Direction::Incoming {
+                continue;
+            }
+
+            let mut fds = VecDeque::with_capacity(entry.num_fds as usize);
+            for _ in 0..entry.num_fds {
+                fds.push_back(open_placeholder()?);
+            }
+
+            connection.feed(&entry.bytes, fds)?;
+            let partial = connection.dispatch_pending()?;
+            report.bytes_read += entry.bytes.len();
+            report.num_dispatched += partial.num_dispatched;
+            report.failures.extend(partial.failures);
+        }
+        Ok(report)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Opens file descriptor used in place of recorded one.
+fn open_placeholder() -> Result<RawFd, SkylaneError> {
+    Ok(File::open(std::path::Path::new("/dev/null"))?.into_raw_fd())
+}
+
+// -------------------------------------------------------------------------------------------------
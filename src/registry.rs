@@ -0,0 +1,487 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Server-side implementation of `wl_registry` advertising globals to clients.
+
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use credentials::Credentials;
+use defs::{DisplayError, SkylaneError, Task};
+use bundle::{Bundle, BundleInternal, WeakBundle};
+use display::{self, RegistryFactory};
+use message::Message;
+use meta::{InterfaceMeta, MessageMeta};
+use object::{Object, ObjectId};
+use sockets::Socket;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Name of `wl_registry` interface.
+pub const INTERFACE: &str = "wl_registry";
+
+/// Metadata of `wl_registry` interface.
+pub static META: InterfaceMeta = InterfaceMeta {
+    name: INTERFACE,
+    version: 1,
+    requests: &[MessageMeta { name: "bind", signature: "usun" }],
+    events: &[MessageMeta { name: "global", signature: "usu" },
+              MessageMeta { name: "global_remove", signature: "u" }],
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Information about client used to decide which globals are visible to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// Credentials of the client process if they could be obtained.
+    pub credentials: Option<Credentials>,
+
+    /// Name of display socket the client connected through (see `DisplaySocket::get_name`).
+    pub origin: Option<String>,
+}
+
+impl ClientInfo {
+    /// Constructs new `ClientInfo` for client connected on `socket` through display socket named
+    /// `origin`. Credentials are taken from the socket (see `Socket::get_peer_credentials`).
+    pub fn new(socket: &Socket, origin: Option<&str>) -> Self {
+        ClientInfo {
+            credentials: socket.get_peer_credentials().ok(),
+            origin: origin.map(|origin| origin.to_owned()),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Allocator of names of registry globals.
+///
+/// Names are allocated sequentially starting from `1` and never reused within lifetime of the
+/// allocator, so `bind` request racing with removal of a global can not bind another global
+/// added later under the same name. Values are looked up by name in constant time.
+pub struct GlobalNames<T> {
+    values: HashMap<u32, T>,
+    next: Option<u32>,
+}
+
+impl<T> GlobalNames<T> {
+    /// Constructs new empty `GlobalNames`.
+    pub fn new() -> Self {
+        GlobalNames {
+            values: HashMap::new(),
+            next: Some(1),
+        }
+    }
+
+    /// Allocates new name for `value`. Returns error if all names were already used.
+    pub fn insert(&mut self, value: T) -> Result<u32, SkylaneError> {
+        let name = match self.next {
+            Some(name) => name,
+            None => return Err(SkylaneError::Other("Global names exhausted".to_owned())),
+        };
+        self.next = name.checked_add(1);
+        self.values.insert(name, value);
+        Ok(name)
+    }
+
+    /// Returns value with given name.
+    pub fn get(&self, name: u32) -> Option<&T> {
+        self.values.get(&name)
+    }
+
+    /// Removes value with given name and returns it. The name will not be allocated again.
+    pub fn remove(&mut self, name: u32) -> Option<T> {
+        self.values.remove(&name)
+    }
+
+    /// Returns number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Checks if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns all names with their values sorted by name, i.e. in order of allocation.
+    pub fn get_entries(&self) -> Vec<(u32, &T)> {
+        let mut entries: Vec<(u32, &T)> =
+            self.values.iter().map(|(name, value)| (*name, value)).collect();
+        entries.sort_by_key(|&(name, _)| name);
+        entries
+    }
+}
+
+impl<T> Default for GlobalNames<T> {
+    fn default() -> Self {
+        GlobalNames::new()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Type of function creating object for global bound by client. Takes ID of the new object and
+/// version requested by client, already checked against the advertised version.
+pub type GlobalFactory = Box<dyn FnMut(&mut Bundle, ObjectId, u32)
+                                   -> Result<Box<dyn Object>, SkylaneError>>;
+
+/// Type of predicate deciding if global is visible to given client.
+///
+/// Globals not visible to a client are never advertised to it and can not be bound by it.
+pub type Visibility = Box<dyn Fn(&ClientInfo) -> bool>;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Global registered in `GlobalRegistry`.
+struct GlobalEntry {
+    interface: &'static str,
+    version: u32,
+    visibility: Option<Rc<Visibility>>,
+    factory: Rc<RefCell<GlobalFactory>>,
+}
+
+impl GlobalEntry {
+    /// Checks if the global is visible to given client.
+    fn is_visible_to(&self, client: &ClientInfo) -> bool {
+        self.visibility.as_ref().map_or(true, |visibility| visibility(client))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// `wl_registry` object bound by a client.
+struct RegistryBinding {
+    id: ObjectId,
+    client: Rc<ClientInfo>,
+    bundle: WeakBundle,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Shared state of `GlobalRegistry`.
+struct RegistryState {
+    globals: GlobalNames<GlobalEntry>,
+    bindings: Vec<RegistryBinding>,
+    clamp_versions: bool,
+}
+
+/// Set of globals advertised to clients.
+///
+/// One `GlobalRegistry` is meant to be shared by all connections of the server. `get_factory`
+/// returns `RegistryFactory` for `Connection::new_server` creating `wl_registry` objects which
+/// advertise globals visible to the client and handle `bind` requests.
+///
+/// Globals may be added and removed at any time (e.g. on output hotplug). Clients which already
+/// bound `wl_registry` are notified with `wl_registry.global` and `wl_registry.global_remove`
+/// events. Failures to notify a client are ignored; they will surface when its connection is
+/// processed.
+#[derive(Clone)]
+pub struct GlobalRegistry {
+    state: Rc<RefCell<RegistryState>>,
+}
+
+impl GlobalRegistry {
+    /// Constructs new empty `GlobalRegistry`.
+    pub fn new() -> Self {
+        GlobalRegistry {
+            state: Rc::new(RefCell::new(RegistryState {
+                                            globals: GlobalNames::new(),
+                                            bindings: Vec::new(),
+                                            clamp_versions: false,
+                                        })),
+        }
+    }
+
+    /// Adds global visible to all clients and announces it to clients which bound registry.
+    /// Returns its name. Names are never reused (see `GlobalNames`).
+    ///
+    /// Returns error if all `u32` names were already used.
+    pub fn add_global(&self,
+                      interface: &'static str,
+                      version: u32,
+                      factory: GlobalFactory)
+                      -> Result<u32, SkylaneError> {
+        self.insert(interface, version, None, factory)
+    }
+
+    /// Adds global visible only to clients for which `visibility` returns `true` and announces it
+    /// to them. Returns its name or error if all names were already used.
+    ///
+    /// Useful for privileged interfaces (e.g. screen capture) which should not be advertised to
+    /// unprivileged clients at all.
+    pub fn add_global_with_visibility(&self,
+                                      interface: &'static str,
+                                      version: u32,
+                                      visibility: Visibility,
+                                      factory: GlobalFactory)
+                                      -> Result<u32, SkylaneError> {
+        self.insert(interface, version, Some(Rc::new(visibility)), factory)
+    }
+
+    /// Removes global with given name and sends `wl_registry.global_remove` to clients it was
+    /// visible to. Returns `false` if there was no such global.
+    ///
+    /// Objects created earlier by binding the global are not affected.
+    pub fn remove_global(&self, name: u32) -> bool {
+        let global = match self.state.borrow_mut().globals.remove(name) {
+            Some(global) => global,
+            None => return false,
+        };
+
+        self.broadcast(|bundle, registry_id, client| {
+            if global.is_visible_to(client) {
+                let _ = bundle.send_marshalled(registry_id,
+                                               display::REGISTRY_GLOBAL_REMOVE_OPCODE,
+                                               |marshaller| marshaller.put_uint(name));
+            }
+        });
+        true
+    }
+
+    /// Checks if global with given name exists and is visible to given client.
+    pub fn is_visible(&self, name: u32, client: &ClientInfo) -> bool {
+        self.state
+            .borrow()
+            .globals
+            .get(name)
+            .map_or(false, |global| global.is_visible_to(client))
+    }
+
+    /// Sets how `bind` requests with version higher than advertised are handled. By default
+    /// `wl_display.invalid_object` error is posted. If `clamp` is `true` the version is lowered to
+    /// the advertised one instead, which may help with misbehaving clients.
+    ///
+    /// Version 0 is always an error.
+    pub fn set_clamp_versions(&self, clamp: bool) {
+        self.state.borrow_mut().clamp_versions = clamp;
+    }
+
+    /// Returns factory of `wl_registry` objects for connection of given client.
+    pub fn get_factory(&self, client: ClientInfo) -> RegistryFactory {
+        let registry = self.clone();
+        let client = Rc::new(client);
+        Box::new(move |bundle: &mut Bundle, id: ObjectId| {
+            bundle.set_interface_meta(id, &META);
+            registry.advertise(bundle, id, &client)?;
+            {
+                let mut state = registry.state.borrow_mut();
+                // Forget bindings of connections which were dropped in the meantime.
+                state.bindings.retain(|binding| binding.bundle.upgrade().is_some());
+                state.bindings.push(RegistryBinding {
+                                        id,
+                                        client: client.clone(),
+                                        bundle: bundle.downgrade(),
+                                    });
+            }
+            Ok(Box::new(RegistryObject {
+                            registry: registry.clone(),
+                            client: client.clone(),
+                        }) as Box<dyn Object>)
+        })
+    }
+}
+
+impl Default for GlobalRegistry {
+    fn default() -> Self {
+        GlobalRegistry::new()
+    }
+}
+
+/// Private methods.
+impl GlobalRegistry {
+    /// Adds new global.
+    fn insert(&self,
+              interface: &'static str,
+              version: u32,
+              visibility: Option<Rc<Visibility>>,
+              factory: GlobalFactory)
+              -> Result<u32, SkylaneError> {
+        let name = {
+            let mut state = self.state.borrow_mut();
+            let global = GlobalEntry {
+                interface,
+                version,
+                visibility,
+                factory: Rc::new(RefCell::new(factory)),
+            };
+            state.globals.insert(global)?
+        };
+
+        self.broadcast(|bundle, registry_id, client| {
+            let state = self.state.borrow();
+            if let Some(global) = state.globals.get(name) {
+                if global.is_visible_to(client) {
+                    let opcode = display::REGISTRY_GLOBAL_OPCODE;
+                    let _ = bundle.send_marshalled(registry_id, opcode, |marshaller| {
+                        marshaller.put_uint(name);
+                        marshaller.put_string(interface);
+                        marshaller.put_uint(version);
+                    });
+                }
+            }
+        });
+        Ok(name)
+    }
+
+    /// Calls `f` for every bound `wl_registry` object whose connection still exists. Bindings of
+    /// connections which were dropped are forgotten.
+    fn broadcast<F>(&self, mut f: F)
+        where F: FnMut(&Bundle, ObjectId, &ClientInfo)
+    {
+        let mut bindings = std::mem::take(&mut self.state.borrow_mut().bindings);
+        bindings.retain(|binding| match binding.bundle.upgrade() {
+                            Some(ref bundle) if bundle.get_weak_ref(binding.id).is_some() => {
+                                f(bundle, binding.id, &binding.client);
+                                true
+                            }
+                            _ => false,
+                        });
+        self.state.borrow_mut().bindings = bindings;
+    }
+
+    /// Sends `wl_registry.global` events for all globals visible to the client.
+    fn advertise(&self,
+                 bundle: &Bundle,
+                 registry_id: ObjectId,
+                 client: &ClientInfo)
+                 -> Result<(), SkylaneError> {
+        let globals: Vec<(u32, &'static str, u32)> = self.state
+            .borrow()
+            .globals
+            .get_entries()
+            .into_iter()
+            .filter(|&(_, global)| global.is_visible_to(client))
+            .map(|(name, global)| (name, global.interface, global.version))
+            .collect();
+
+        for (name, interface, version) in globals {
+            bundle.send_marshalled(registry_id, display::REGISTRY_GLOBAL_OPCODE, |marshaller| {
+                    marshaller.put_uint(name);
+                    marshaller.put_string(interface);
+                    marshaller.put_uint(version);
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Creates object for global bound by client.
+    ///
+    /// Version passed to the factory is checked against the advertised one and stored as version
+    /// of the new object (see `Bundle::get_object_version`), so handlers can rely on it.
+    #[allow(clippy::too_many_arguments)]
+    fn bind(&self,
+            bundle: &mut Bundle,
+            registry_id: ObjectId,
+            client: &ClientInfo,
+            name: u32,
+            interface: &str,
+            version: u32,
+            id: ObjectId)
+            -> Result<Task, SkylaneError> {
+        let (factory, version) = {
+            let state = self.state.borrow();
+            match state.globals.get(name) {
+                Some(global) if global.is_visible_to(client) && global.interface == interface => {
+                    let version = check_version(global,
+                                                registry_id,
+                                                name,
+                                                version,
+                                                state.clamp_versions)?;
+                    (global.factory.clone(), version)
+                }
+                _ => {
+                    return Err(SkylaneError::Protocol {
+                                   interface: display::INTERFACE,
+                                   object_id: registry_id,
+                                   code: DisplayError::InvalidObject.get_code(),
+                                   message: format!("invalid global {} ({})", interface, name),
+                               });
+                }
+            }
+        };
+
+        // Factory is called without borrowing the state so it can add globals.
+        let object = (*factory.borrow_mut())(bundle, id, version)?;
+        bundle.add_remote_object(id, object)?;
+        bundle.set_object_version(id, version);
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks version requested by client in `bind` request against version advertised for the
+/// global. Returns version to be used for the new object.
+fn check_version(global: &GlobalEntry,
+                 registry_id: ObjectId,
+                 name: u32,
+                 requested: u32,
+                 clamp: bool)
+                 -> Result<u32, SkylaneError> {
+    if requested == 0 || (requested > global.version && !clamp) {
+        Err(SkylaneError::Protocol {
+                interface: display::INTERFACE,
+                object_id: registry_id,
+                code: DisplayError::InvalidObject.get_code(),
+                message: format!("invalid version for global {} ({}): have {}, wanted {}",
+                                 global.interface,
+                                 name,
+                                 global.version,
+                                 requested),
+            })
+    } else {
+        Ok(std::cmp::min(requested, global.version))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Server-side `wl_registry` object of one client.
+struct RegistryObject {
+    registry: GlobalRegistry,
+    client: Rc<ClientInfo>,
+}
+
+impl Object for RegistryObject {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        match message.get_opcode() {
+            display::REGISTRY_BIND_OPCODE => {
+                let name = message.next_uint()?;
+                let interface = message.next_string()?;
+                let version = message.next_uint()?;
+                let id = message.next_new_id()?;
+                let registry_id = message.get_object_id();
+                self.registry.bind(bundle, registry_id, &self.client, name, &interface, version, id)
+            }
+            opcode => {
+                Err(SkylaneError::WrongOpcode {
+                        name: INTERFACE,
+                        object_id: message.get_object_id(),
+                        opcode,
+                    })
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
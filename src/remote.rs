@@ -0,0 +1,193 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Thread-safe handle for posting messages and operations to connection from other threads.
+
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+
+use nix;
+use nix::errno::Errno;
+use nix::libc;
+
+use defs::SkylaneError;
+use bundle::{Bundle, BundleInternal};
+use marshal::Marshaller;
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Function run on the connection's thread.
+type RemoteFn = Box<dyn FnMut(&mut Bundle) -> Result<(), SkylaneError> + Send>;
+
+/// Operation posted by `RemoteController`.
+enum Command {
+    Send(Marshaller),
+    RemoveObject(ObjectId),
+    PostError(ObjectId, u32, String),
+    Run(RemoteFn),
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// File descriptor closed on drop.
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handle to the connection which can be sent to other threads.
+///
+/// `Controller` shares data with `Connection` without synchronisation and can not leave its
+/// thread. `RemoteController` only enqueues operations. They are executed by the thread owning
+/// `Connection` in `Connection::drain_remote` (called also by `process_events`). To wake up that
+/// thread `Connection::get_remote_fd` should be polled along with the socket.
+#[derive(Clone)]
+pub struct RemoteController {
+    sender: Sender<Command>,
+    wake: Arc<Fd>,
+}
+
+impl RemoteController {
+    /// Enqueues message composed using `compose`.
+    pub fn send<F>(&self, object_id: ObjectId, opcode: u16, compose: F) -> Result<(), SkylaneError>
+        where F: FnOnce(&mut Marshaller)
+    {
+        let mut marshaller = Marshaller::new(object_id, opcode);
+        marshaller.record_signature();
+        compose(&mut marshaller);
+        self.push(Command::Send(marshaller))
+    }
+
+    /// Enqueues removal of object.
+    ///
+    /// See `Bundle::remove_object`.
+    pub fn remove_object(&self, id: ObjectId) -> Result<(), SkylaneError> {
+        self.push(Command::RemoveObject(id))
+    }
+
+    /// Enqueues posting of protocol error.
+    ///
+    /// See `Bundle::post_error`.
+    pub fn post_error(&self,
+                      object_id: ObjectId,
+                      code: u32,
+                      message: String)
+                      -> Result<(), SkylaneError> {
+        self.push(Command::PostError(object_id, code, message))
+    }
+
+    /// Enqueues arbitrary operation on `Bundle`.
+    pub fn run<F>(&self, f: F) -> Result<(), SkylaneError>
+        where F: FnOnce(&mut Bundle) -> Result<(), SkylaneError> + Send + 'static
+    {
+        let mut f = Some(f);
+        self.push(Command::Run(Box::new(move |bundle| match f.take() {
+                                            Some(f) => f(bundle),
+                                            None => Ok(()),
+                                        })))
+    }
+}
+
+/// Private methods.
+impl RemoteController {
+    /// Enqueues command and wakes up thread owning the connection.
+    fn push(&self, command: Command) -> Result<(), SkylaneError> {
+        if self.sender.send(command).is_err() {
+            return Err(SkylaneError::Other("Connection was dropped".to_owned()));
+        }
+
+        // If the pipe is full the thread will wake up anyway.
+        let byte = [0u8; 1];
+        let _ = unsafe { libc::write(self.wake.0, byte.as_ptr() as *const libc::c_void, 1) };
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Queue of operations posted by `RemoteController`s.
+pub struct RemoteQueue {
+    sender: Sender<Command>,
+    receiver: Receiver<Command>,
+    wake_read: Fd,
+    wake_write: Arc<Fd>,
+}
+
+impl RemoteQueue {
+    /// Constructs new `RemoteQueue`.
+    pub fn new() -> Result<Self, SkylaneError> {
+        let mut fds: [libc::c_int; 2] = [0; 2];
+        let flags = libc::O_CLOEXEC | libc::O_NONBLOCK;
+        Errno::result(unsafe { libc::pipe2(fds.as_mut_ptr(), flags) })?;
+
+        let (sender, receiver) = channel();
+        Ok(RemoteQueue {
+               sender,
+               receiver,
+               wake_read: Fd(fds[0]),
+               wake_write: Arc::new(Fd(fds[1])),
+           })
+    }
+
+    /// Returns new `RemoteController` posting to this queue.
+    pub fn get_controller(&self) -> RemoteController {
+        RemoteController {
+            sender: self.sender.clone(),
+            wake: self.wake_write.clone(),
+        }
+    }
+
+    /// Returns file descriptor which becomes readable when operations are posted.
+    pub fn get_fd(&self) -> RawFd {
+        self.wake_read.0
+    }
+
+    /// Executes all posted operations. Returns number of executed operations.
+    pub fn drain(&self, bundle: &mut Bundle) -> Result<usize, SkylaneError> {
+        let mut buf = [0u8; 64];
+        while unsafe {
+                  libc::read(self.wake_read.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+              } > 0 {}
+
+        let mut count = 0;
+        loop {
+            let command = match self.receiver.try_recv() {
+                Ok(command) => command,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
+
+            count += 1;
+            match command {
+                Command::Send(marshaller) => bundle.send_composed(marshaller)?,
+                Command::RemoveObject(id) => bundle.remove_object(id),
+                Command::PostError(object_id, code, message) => {
+                    bundle.post_error(object_id, code, &message)?
+                }
+                Command::Run(mut f) => f(bundle)?,
+            }
+        }
+        Ok(count)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
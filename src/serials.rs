@@ -0,0 +1,102 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tracking of recently issued serials.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Information about event for which serial was issued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SerialInfo {
+    /// The serial.
+    pub serial: u32,
+
+    /// ID of object the event was sent to.
+    pub object_id: ObjectId,
+
+    /// Opcode of the event.
+    pub opcode: u16,
+
+    /// Time when the serial was issued.
+    pub timestamp: Instant,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Bounded history of recently issued serials.
+///
+/// Entries are dropped when history is full or when they become older than maximal age.
+pub struct SerialHistory {
+    entries: VecDeque<SerialInfo>,
+    capacity: usize,
+    max_age: Option<Duration>,
+}
+
+impl SerialHistory {
+    /// Constructs new `SerialHistory` keeping up to `capacity` serials not older than `max_age`.
+    pub fn new(capacity: usize, max_age: Option<Duration>) -> Self {
+        SerialHistory {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            max_age,
+        }
+    }
+
+    /// Returns maximal number of kept serials.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns maximal age of kept serials.
+    pub fn get_max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Adds serial dropping the oldest one if history is full.
+    pub fn push(&mut self, info: SerialInfo) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.expire(info.timestamp);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(info);
+    }
+
+    /// Returns information about given serial if it was issued recently.
+    pub fn find(&mut self, serial: u32) -> Option<SerialInfo> {
+        self.expire(Instant::now());
+        self.entries.iter().rev().find(|info| info.serial == serial).cloned()
+    }
+
+    /// Drops entries older than maximal age.
+    fn expire(&mut self, now: Instant) {
+        if let Some(max_age) = self.max_age {
+            while self.entries.front().map_or(false, |info| now - info.timestamp > max_age) {
+                self.entries.pop_front();
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -17,10 +17,42 @@
 
 //! Server part of `skylane` crate.
 
-pub use defs::{Header, Logger, SkylaneError, Task};
-pub use object::{Object, ObjectId};
+pub use credentials::Credentials;
+pub use defs::{Direction, DisplayError, Header, LogFn, LogLevel, LogRecord, Logger, Side,
+               SkylaneError, Task, MAX_CONTEXT_BYTES};
+pub use object::{Object, ObjectId, TypedObjectId};
+pub use fd::{OwnedFd, dup_cloexec};
+pub use endian::{check_native_endianness, Endianness};
+pub use message::{Message, MessageIter, Utf8Policy};
+pub use marshal::{Marshaller, MAX_MESSAGE_SIZE};
+pub use meta::{InterfaceMeta, MessageMeta};
+pub use names::{describe_message, describe_protocol, get_interface, get_interfaces,
+                get_message_name, register_interface, ReportFormat};
 pub use bundle::Bundle;
+pub use map::{ObjectMap, ObjectRef, ObjectStore, WeakObjectRef};
+pub use callback::ServerCallback;
+pub use builder::ConnectionBuilder;
+pub use clock::{Clock, ManualClock, MonotonicClock};
 pub use connection::{Connection, Controller};
-pub use sockets::{DisplaySocket, Socket};
+pub use multiplex::ConnectionSet;
+pub use event_loop::{EventLoop, LoopHandler};
+pub use queue::Priority;
+pub use dispatch::{ConnectionState, DisconnectHandler, DisconnectReason, DispatchFailure,
+                   DispatchPolicy, DispatchReport, FilterDecision, RequestFilter};
+pub use introspect::{Introspection, MessageInfo, ObjectInfo};
+pub use display::{DisplayObject, RegistryFactory};
+pub use limits::{FdLimit, FdOverflowPolicy, RateLimit};
+pub use sockets::{DisplaySocket, DisplaySocketOptions, Shutdown, Socket};
+pub use shm::{create_sealed_fd, validate_pool_fd, Sealing, ShmPool};
+pub use record::{Entry, Recorder, Replayer};
+pub use registry::{ClientInfo, GlobalFactory, GlobalNames, GlobalRegistry, Visibility};
+pub use remote::RemoteController;
+pub use serials::SerialInfo;
+pub use stats::{MetricsSink, Stats};
+pub use trace::{Arg, JsonTraceSink, TraceRecord, TraceSink};
+pub use validation::{ValidationMode, VersionCheck};
 
-pub use object::DISPLAY_ID;
+#[cfg(feature = "io-uring")]
+pub use uring::UringTransport;
+
+pub use object::{CLIENT_END_ID, DISPLAY_ID, NULL_ID, SERVER_END_ID, SERVER_START_ID};
@@ -0,0 +1,338 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Helpers for shared memory pools used to pass buffer contents between client and server.
+
+use std::env;
+use std::ffi::CString;
+use std::os::unix::io::{BorrowedFd, RawFd};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix;
+use nix::errno::Errno;
+use nix::libc;
+
+use defs::SkylaneError;
+use sockets::Socket;
+
+// -------------------------------------------------------------------------------------------------
+
+// Flags for `memfd_create` and `fcntl` not available in all versions of `libc`.
+const MFD_CLOEXEC: libc::c_uint = 0x0001;
+const MFD_ALLOW_SEALING: libc::c_uint = 0x0002;
+const F_ADD_SEALS: libc::c_int = 1033;
+const F_GET_SEALS: libc::c_int = 1034;
+const F_SEAL_SEAL: libc::c_int = 0x0001;
+const F_SEAL_SHRINK: libc::c_int = 0x0002;
+const F_SEAL_GROW: libc::c_int = 0x0004;
+const F_SEAL_WRITE: libc::c_int = 0x0008;
+
+/// Counter used to generate unique names for `shm_open`.
+static SHM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// -------------------------------------------------------------------------------------------------
+
+/// Creates file containing given `data` and returns its descriptor, e.g. for passing keymap in
+/// `wl_keyboard.keymap` event. Size of the file is equal to length of `data`.
+///
+/// If possible `memfd` sealed against any modifications is used. Otherwise temporary file is
+/// created in `$XDG_RUNTIME_DIR` and unlinked immediately. The descriptor should be sent with
+/// `Marshaller::put_fd` or `Socket::write_with_control_data` and then closed by the caller.
+pub fn create_sealed_fd(name: &str, data: &[u8]) -> Result<RawFd, SkylaneError> {
+    let (fd, sealable) = match create_memfd(name) {
+        Ok(fd) => (fd, true),
+        Err(_) => (create_tmpfile(name)?, false),
+    };
+
+    if let Err(err) = write_all(fd, data) {
+        let _ = nix::unistd::close(fd);
+        return Err(err.into());
+    }
+
+    if sealable {
+        let seals = F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_SEAL;
+        if let Err(err) = Errno::result(unsafe { libc::fcntl(fd, F_ADD_SEALS, seals) }) {
+            let _ = nix::unistd::close(fd);
+            return Err(err.into());
+        }
+    }
+    Ok(fd)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if received file descriptor can be safely mapped as shared memory pool of given `size`.
+///
+/// File must be a regular file (e.g. `memfd`) at least `size` bytes long. If it is not sealed
+/// against shrinking the peer may still truncate it after mapping and cause `SIGBUS` on access,
+/// so the caller should either reject it or be prepared to handle the signal.
+pub fn validate_pool_fd(fd: RawFd, size: usize) -> Result<Sealing, SkylaneError> {
+    let stat = nix::sys::stat::fstat(fd)?;
+    if (stat.st_mode & libc::S_IFMT) != libc::S_IFREG {
+        return Err(SkylaneError::Other(format!("File descriptor {} is not a regular file", fd)));
+    }
+
+    if (stat.st_size as u64) < (size as u64) {
+        return Err(SkylaneError::Other(format!("File of descriptor {} has {} bytes, {} expected",
+                                               fd,
+                                               stat.st_size,
+                                               size)));
+    }
+
+    let seals = unsafe { libc::fcntl(fd, F_GET_SEALS) };
+    if seals >= 0 && (seals & F_SEAL_SHRINK) != 0 {
+        Ok(Sealing::Sealed)
+    } else {
+        Ok(Sealing::Unsealed)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Informs if shared memory is protected from shrinking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sealing {
+    /// Memory is `memfd` sealed against shrinking.
+    Sealed,
+
+    /// Memory may be shrunk by peer.
+    Unsealed,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Shared memory pool mapped into process memory.
+///
+/// On client side pool is created with `new`. Memory is backed by `memfd` sealed against
+/// shrinking, so the peer can not cause `SIGBUS` by truncating it. If `memfd` is not available
+/// anonymous `shm_open` object is used.
+///
+/// File descriptor of the pool should be sent to the peer (e.g. in `wl_shm.create_pool` request)
+/// with `Marshaller::put_fd`, `Socket::write_with_control_data` or `write_with_fd`.
+///
+/// On server side pool is created from received descriptor with `import`.
+///
+/// Memory is unmapped and descriptor closed when the pool is dropped.
+pub struct ShmPool {
+    fd: RawFd,
+    size: usize,
+    memory: *mut libc::c_void,
+    sealing: Sealing,
+    owned: bool,
+}
+
+impl ShmPool {
+    /// Creates new pool of given `size` in bytes. `name` is used only for debugging purposes.
+    pub fn new(name: &str, size: usize) -> Result<Self, SkylaneError> {
+        if size == 0 {
+            return Err(SkylaneError::Other("Shared memory pool can not be empty".to_owned()));
+        }
+
+        let fd = match create_memfd(name) {
+            Ok(fd) => fd,
+            Err(_) => create_shm(name)?,
+        };
+
+        let mut pool = ShmPool {
+            fd,
+            size: 0,
+            memory: ptr::null_mut(),
+            sealing: Sealing::Unsealed,
+            owned: true,
+        };
+        pool.resize(size)?;
+
+        // Sealing fails for `shm_open` objects; they can not be protected this way.
+        if unsafe { libc::fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK) } == 0 {
+            pool.sealing = Sealing::Sealed;
+        }
+        Ok(pool)
+    }
+
+    /// Maps pool of given `size` from received file descriptor. The pool takes ownership of the
+    /// descriptor. Descriptor is validated with `validate_pool_fd` and if `require_sealed` is
+    /// `true` unsealed memory is rejected.
+    pub fn import(fd: RawFd, size: usize, require_sealed: bool) -> Result<Self, SkylaneError> {
+        let mut pool = ShmPool {
+            fd,
+            size: 0,
+            memory: ptr::null_mut(),
+            sealing: Sealing::Unsealed,
+            owned: false,
+        };
+
+        pool.sealing = validate_pool_fd(fd, size)?;
+        if require_sealed && pool.sealing != Sealing::Sealed {
+            return Err(SkylaneError::Other(format!("File descriptor {} is not sealed", fd)));
+        }
+
+        pool.remap(size)?;
+        Ok(pool)
+    }
+
+    /// Checks if memory is protected from shrinking.
+    pub fn get_sealing(&self) -> Sealing {
+        self.sealing
+    }
+
+    /// Returns file descriptor of the pool.
+    pub fn get_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Returns size of the pool in bytes.
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns memory of the pool.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.memory as *const u8, self.size) }
+    }
+
+    /// Returns mutable memory of the pool.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.memory as *mut u8, self.size) }
+    }
+
+    /// Enlarges the pool to given `size` and maps it again. Like in `wl_shm_pool` pools can only
+    /// grow.
+    ///
+    /// For pools created with `new` the file is enlarged and peer should be informed about new
+    /// size (e.g. with `wl_shm_pool.resize` request). Imported pools are validated again, as the
+    /// file should have been enlarged by peer.
+    pub fn resize(&mut self, size: usize) -> Result<(), SkylaneError> {
+        if size < self.size {
+            return Err(SkylaneError::Other(format!("Shared memory pool can not shrink \
+                                                    ({} to {})",
+                                                   self.size,
+                                                   size)));
+        }
+
+        if self.owned {
+            Errno::result(unsafe { libc::ftruncate(self.fd, size as libc::off_t) })?;
+        } else {
+            self.sealing = validate_pool_fd(self.fd, size)?;
+        }
+        self.remap(size)
+    }
+
+    /// Writes message `bytes` passing file descriptor of the pool along with them. Returns number
+    /// of bytes written (see `Socket::write_with_control_data`).
+    pub fn write_with_fd(&self, socket: &Socket, bytes: &[u8]) -> Result<usize, SkylaneError> {
+        socket.write_with_control_data(bytes, &[self.fd])
+    }
+}
+
+/// Private methods.
+impl ShmPool {
+    /// Maps memory again with new size.
+    fn remap(&mut self, size: usize) -> Result<(), SkylaneError> {
+        let memory = map(self.fd, size)?;
+        self.unmap();
+        self.memory = memory;
+        self.size = size;
+        Ok(())
+    }
+
+    /// Unmaps memory if mapped.
+    fn unmap(&mut self) {
+        if !self.memory.is_null() {
+            unsafe { libc::munmap(self.memory, self.size) };
+            self.memory = ptr::null_mut();
+        }
+    }
+}
+
+impl Drop for ShmPool {
+    fn drop(&mut self) {
+        self.unmap();
+        let _ = nix::unistd::close(self.fd);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Creates `memfd` allowing sealing.
+fn create_memfd(name: &str) -> nix::Result<RawFd> {
+    let name = CString::new(name).map_err(|_| Errno::EINVAL)?;
+    let res = unsafe {
+        libc::syscall(libc::SYS_memfd_create, name.as_ptr(), MFD_CLOEXEC | MFD_ALLOW_SEALING)
+    };
+    Errno::result(res).map(|fd| fd as RawFd)
+}
+
+/// Creates anonymous shared memory object with `shm_open`.
+fn create_shm(name: &str) -> nix::Result<RawFd> {
+    let counter = SHM_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path = format!("/{}-{}-{}", name.replace('/', "_"), nix::unistd::getpid(), counter);
+    let path = CString::new(path).map_err(|_| Errno::EINVAL)?;
+    let fd = Errno::result(unsafe {
+        libc::shm_open(path.as_ptr(),
+                       libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+                       0o600)
+    })?;
+    unsafe { libc::shm_unlink(path.as_ptr()) };
+    Ok(fd)
+}
+
+/// Creates unlinked temporary file in `$XDG_RUNTIME_DIR`.
+fn create_tmpfile(name: &str) -> Result<RawFd, SkylaneError> {
+    let template = format!("{}/{}-XXXXXX", env::var("XDG_RUNTIME_DIR")?, name.replace('/', "_"));
+    let template = CString::new(template).map_err(|_| Errno::EINVAL)?;
+    let mut path = template.into_bytes_with_nul();
+    let fd = Errno::result(unsafe { libc::mkstemp(path.as_mut_ptr() as *mut libc::c_char) })?;
+    unsafe {
+        libc::unlink(path.as_ptr() as *const libc::c_char);
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    Ok(fd)
+}
+
+/// Writes whole `data` to file.
+fn write_all(fd: RawFd, mut data: &[u8]) -> nix::Result<()> {
+    // The descriptor is owned by the caller and stays open for the duration of the call.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data) {
+            Ok(written) => data = &data[written..],
+            Err(Errno::EINTR) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Maps file of given size for reading and writing.
+fn map(fd: RawFd, size: usize) -> nix::Result<*mut libc::c_void> {
+    let memory = unsafe {
+        libc::mmap(ptr::null_mut(),
+                   size,
+                   libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_SHARED,
+                   fd,
+                   0)
+    };
+    if memory == libc::MAP_FAILED {
+        Err(Errno::last())
+    } else {
+        Ok(memory)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
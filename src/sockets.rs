@@ -18,17 +18,28 @@
 //! This module provides functionality for connecting, reading and writing sockets.
 
 use std;
-use std::error::Error;
-use std::io::Cursor;
-use std::os::unix::io::RawFd;
+use std::io::{Cursor, IoSlice, IoSliceMut};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use byteorder::{NativeEndian, WriteBytesExt};
 
 use nix;
+use nix::errno::Errno;
+use nix::libc;
+use nix::NixPath;
 use nix::sys::socket;
-use nix::sys::uio;
 
-use defs::{Logger, SkylaneError};
+use credentials::{self, Credentials};
+use defs::{Direction, LogFn, LogLevel, LogRecord, Logger, SkylaneError};
+use endian::Endianness;
+use marshal::HEADER_SIZE;
+use record::Recorder;
+use stats::Stats;
 
 // -------------------------------------------------------------------------------------------------
 
@@ -39,9 +50,7 @@ macro_rules! try_sock {
         match $expr {
             Ok(result) => result,
             Err(err) => {
-                return Err(SkylaneError::Other(
-                    format!("{} {:?}: {:?}", $action, $path, err.description())
-                ));
+                return Err(SkylaneError::Other(format!("{} {:?}: {}", $action, $path, err)));
             }
         }
     }
@@ -49,6 +58,18 @@ macro_rules! try_sock {
 
 // -------------------------------------------------------------------------------------------------
 
+/// Maximal number of file descriptors passed along with one `sendmsg` call (the same as in
+/// `libwayland`). Buffers for received descriptors must have place for this many of them.
+pub const MAX_FDS: usize = 28;
+
+/// Maximal number of file descriptors passed by the kernel along with one message
+/// (`SCM_MAX_FD`). Space for received control messages is sized for this many, so descriptors
+/// exceeding `MAX_FDS` still reach us and can be closed and reported. If the kernel truncated
+/// them, `nix` would not hand out even the ones which fit.
+pub const SCM_MAX_FD: usize = 253;
+
+// -------------------------------------------------------------------------------------------------
+
 /// Returns default server socket path.
 ///
 /// Path is created from system variables: `$XDG_RUNTIME_DIR/$WAYLAND_DISPLAY` or
@@ -65,35 +86,165 @@ pub fn get_default_socket_path() -> Result<std::path::PathBuf, SkylaneError> {
 
 // -------------------------------------------------------------------------------------------------
 
+/// Checks if `path` denotes address in abstract namespace, i.e. starts with `NUL` byte.
+fn is_abstract(path: &std::path::Path) -> bool {
+    path.as_os_str().as_bytes().first() == Some(&0)
+}
+
+/// Creates Unix socket address from `path`. If `path` starts with `NUL` byte address in abstract
+/// namespace is created from the rest of the path.
+fn make_unix_addr(path: &std::path::Path) -> nix::Result<socket::UnixAddr> {
+    if is_abstract(path) {
+        socket::UnixAddr::new_abstract(&path.as_os_str().as_bytes()[1..])
+    } else {
+        socket::UnixAddr::new(path)
+    }
+}
+
+
+/// Changes group owning file under `path`.
+fn set_group(path: &std::path::Path, group: u32) -> nix::Result<()> {
+    let res = path.with_nix_path(|cstr| unsafe {
+            libc::chown(cstr.as_ptr(), libc::uid_t::MAX, group as libc::gid_t)
+        })?;
+    Errno::result(res).map(drop)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Sets timeout socket option (`SO_RCVTIMEO` or `SO_SNDTIMEO`). `None` disables timeout.
+///
+/// Timeouts shorter than one microsecond are rounded up, as zero would disable the timeout.
+fn set_timeout(fd: RawFd, option: libc::c_int, timeout: Option<Duration>) -> nix::Result<()> {
+    let timeval = match timeout {
+        Some(duration) => {
+            let mut usecs = duration.subsec_micros();
+            if duration.as_secs() == 0 && usecs == 0 {
+                usecs = 1;
+            }
+            libc::timeval {
+                tv_sec: duration.as_secs() as libc::time_t,
+                tv_usec: usecs as libc::suseconds_t,
+            }
+        }
+        None => libc::timeval { tv_sec: 0, tv_usec: 0 },
+    };
+
+    let res = unsafe {
+        libc::setsockopt(fd,
+                         libc::SOL_SOCKET,
+                         option,
+                         &timeval as *const libc::timeval as *const libc::c_void,
+                         std::mem::size_of::<libc::timeval>() as libc::socklen_t)
+    };
+    Errno::result(res).map(drop)
+}
+
+/// Sets or clears `O_NONBLOCK` flag of file descriptor.
+fn set_fd_nonblocking(fd: RawFd, nonblocking: bool) -> nix::Result<()> {
+    let flags = Errno::result(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    let flags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    Errno::result(unsafe { libc::fcntl(fd, libc::F_SETFL, flags) }).map(drop)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Direction of connection to shut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Further reads are disallowed. Requests already sent by peer are discarded.
+    Read,
+
+    /// Further writes are disallowed. Peer receives end of stream after already written data.
+    Write,
+
+    /// Both reads and writes are disallowed.
+    Both,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// State shared by all clones of `Socket`.
+struct SocketInner {
+    fd: RawFd,
+    stats: Mutex<Stats>,
+    unfinished: AtomicUsize,
+    send_credentials: AtomicBool,
+    pass_credentials: AtomicBool,
+    nonblocking: AtomicBool,
+    logger: Mutex<Option<Arc<LogFn>>>,
+}
+
+impl Drop for SocketInner {
+    fn drop(&mut self) {
+        // Nothing can be done if closing fails.
+        let _ = nix::unistd::close(self.fd);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
 /// Structure representing connection between server and client.
+///
+/// Clones of `Socket` share the underlying file descriptor which is closed when the last clone is
+/// dropped. Use `shutdown` to disconnect the peer while clones are still alive.
 #[derive(Clone)]
 pub struct Socket {
-    fd: RawFd,
-    next_serial: std::cell::Cell<u32>,
-    logger: Logger,
+    inner: Arc<SocketInner>,
+    recorder: Option<Recorder>,
 }
 
 // -------------------------------------------------------------------------------------------------
 
 impl Socket {
     /// Connects to display socket.
+    ///
+    /// If `path` starts with `NUL` byte the rest of it is treated as name in abstract namespace.
     pub fn connect(path: &std::path::Path) -> Result<Self, SkylaneError> {
+        Self::connect_with_timeout(path, None)
+    }
+
+    /// Connects to display socket. If `timeout` is given and connection can not be established
+    /// within it (e.g. server does not accept connections) error is returned.
+    pub fn connect_with_timeout(path: &std::path::Path,
+                                timeout: Option<Duration>)
+                                -> Result<Self, SkylaneError> {
+        // The descriptor is closed on failure until it is passed to `Socket`.
         let sockfd = try_sock!("Creating",
                                path,
                                socket::socket(socket::AddressFamily::Unix,
                                               socket::SockType::Stream,
-                                              socket::SOCK_CLOEXEC,
-                                              0));
+                                              socket::SockFlag::SOCK_CLOEXEC,
+                                              None));
+
+        let fd = sockfd.as_raw_fd();
+        let unix_addr = try_sock!("Linking", path, make_unix_addr(path));
+        if timeout.is_some() {
+            // For Unix sockets `connect` waits for place in listen queue with send timeout.
+            try_sock!("Setting timeout", path, set_timeout(fd, libc::SO_SNDTIMEO, timeout));
+            try_sock!("Connecting", path, socket::connect(fd, &unix_addr));
+            try_sock!("Resetting timeout", path, set_timeout(fd, libc::SO_SNDTIMEO, None));
+        } else {
+            try_sock!("Connecting", path, socket::connect(fd, &unix_addr));
+        }
 
-        let unix_addr = try_sock!("Linking", path, socket::UnixAddr::new(path));
-        let sock_addr = socket::SockAddr::Unix(unix_addr);
-        try_sock!("Connecting", path, socket::connect(sockfd, &sock_addr));
+        Ok(Socket::new(sockfd.into_raw_fd()))
+    }
 
-        Ok(Socket {
-               fd: sockfd,
-                next_serial: std::cell::Cell::new(0),
-                logger: None,
-           })
+    /// Creates pair of connected sockets.
+    ///
+    /// Useful for testing or when the client is spawned by the server with already connected
+    /// socket.
+    pub fn pair() -> Result<(Self, Self), SkylaneError> {
+        let (fd1, fd2) = socket::socketpair(socket::AddressFamily::Unix,
+                                            socket::SockType::Stream,
+                                            None,
+                                            socket::SockFlag::SOCK_CLOEXEC)?;
+        Ok((Socket::new(fd1.into_raw_fd()), Socket::new(fd2.into_raw_fd())))
     }
 
     /// Connects to display socket on default path.
@@ -106,24 +257,171 @@ impl Socket {
 
     /// Returns raw file descriptor.
     pub fn get_fd(&self) -> RawFd {
-        self.fd
+        self.inner.fd
+    }
+
+    /// Sets logger. Logger is shared by all clones of the `Socket`.
+    pub fn set_logger(&self, logger: Logger) {
+        *self.lock_logger() = logger.map(Arc::from);
     }
 
-    /// Increments and return next serial.
-    pub fn get_next_serial(&self) -> u32 {
-        let serial = self.next_serial.get();
-        self.next_serial.set(serial + 1);
-        serial
+    /// Shuts down the connection in given direction (see `shutdown(2)`). Affects all clones. The
+    /// descriptor itself is closed when the last clone is dropped.
+    ///
+    /// Server wanting to disconnect a client gracefully may shut down reading to ignore further
+    /// requests, flush remaining events and then shut down writing.
+    pub fn shutdown(&self, how: Shutdown) -> Result<(), SkylaneError> {
+        let how = match how {
+            Shutdown::Read => socket::Shutdown::Read,
+            Shutdown::Write => socket::Shutdown::Write,
+            Shutdown::Both => socket::Shutdown::Both,
+        };
+        socket::shutdown(self.inner.fd, how)?;
+        Ok(())
+    }
+
+    /// Checks if logger is set.
+    pub fn has_logger(&self) -> bool {
+        self.lock_logger().is_some()
+    }
+
+    /// Returns traffic statistics. Statistics are shared by all clones of the `Socket`.
+    pub fn stats(&self) -> Stats {
+        *self.lock_stats()
+    }
+
+    /// Sets recorder. All data read from or written to this socket (and its clones created
+    /// afterwards) will be recorded. `None` stops recording.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
     }
 
-    /// Sets logger.
-    pub fn set_logger(&mut self, logger: Logger) {
-        self.logger = logger;
+    /// Sets reading mode. In non-blocking mode (default) `receive_message` returns immediately with
+    /// error if there is no data to read. In blocking mode it waits for data.
+    ///
+    /// The mode is shared by all clones of this `Socket`. Switching to blocking mode clears
+    /// `O_NONBLOCK` flag of the descriptor (e.g. set by `DisplaySocket::accept`); non-blocking
+    /// reads and all writes do not rely on this flag.
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        if !nonblocking {
+            // If this fails reads will report `EAGAIN` like in non-blocking mode.
+            let _ = set_fd_nonblocking(self.inner.fd, false);
+        }
+        self.inner.nonblocking.store(nonblocking, Ordering::SeqCst);
     }
 
-    /// Returns logger.
-    pub fn get_logger(&self) -> Logger {
-        self.logger
+    /// Checks if reading is non-blocking.
+    pub fn is_nonblocking(&self) -> bool {
+        self.inner.nonblocking.load(Ordering::SeqCst)
+    }
+
+    /// Enables or disables attaching credentials of current process (`SCM_CREDENTIALS`) to every
+    /// outgoing message. The option is shared by all clones of this `Socket`.
+    ///
+    /// Meant for privileged protocol extensions requiring authentication of the sender at the time
+    /// of sending rather than of connecting. Receiving side has to enable `set_pass_credentials`
+    /// and gets credentials per read, not per message (see `Connection::get_last_credentials`).
+    pub fn set_send_credentials(&self, enabled: bool) {
+        self.inner.send_credentials.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Enables or disables receiving credentials of the peer along with messages (`SO_PASSCRED`).
+    /// When enabled `Reader` uses `receive_message_with_credentials` and credentials of the last
+    /// read data are available from `Connection::get_last_credentials`. Credentials are not
+    /// tracked per message.
+    pub fn set_pass_credentials(&self, enabled: bool) -> Result<(), SkylaneError> {
+        let value: libc::c_int = if enabled { 1 } else { 0 };
+        let res = unsafe {
+            libc::setsockopt(self.inner.fd,
+                             libc::SOL_SOCKET,
+                             libc::SO_PASSCRED,
+                             &value as *const libc::c_int as *const libc::c_void,
+                             std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        Errno::result(res)?;
+        self.inner.pass_credentials.store(enabled, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Checks if receiving credentials is enabled.
+    pub fn is_passing_credentials(&self) -> bool {
+        self.inner.pass_credentials.load(Ordering::SeqCst)
+    }
+
+    /// Returns credentials of the peer process obtained when the connection was established
+    /// (`SO_PEERCRED`).
+    pub fn get_peer_credentials(&self) -> Result<Credentials, SkylaneError> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(self.inner.fd,
+                             libc::SOL_SOCKET,
+                             libc::SO_PEERCRED,
+                             &mut cred as *mut libc::ucred as *mut libc::c_void,
+                             &mut len)
+        };
+        Errno::result(res)?;
+        Ok(Credentials {
+               pid: cred.pid,
+               uid: cred.uid,
+               gid: cred.gid,
+           })
+    }
+
+    /// Sets timeout for blocking reads. If no data arrives within `timeout` `receive_message`
+    /// returns error. `None` means reads may block indefinitely.
+    ///
+    /// Timeout is applied to the underlying socket, so it affects all clones of this `Socket`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), SkylaneError> {
+        set_timeout(self.inner.fd, libc::SO_RCVTIMEO, timeout)?;
+        Ok(())
+    }
+
+    /// Waits until there is data to be read. Returns `false` if `timeout` elapsed before data
+    /// arrived. `None` means waiting indefinitely.
+    ///
+    /// Waiting interrupted by a signal is resumed with the remaining time.
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> Result<bool, SkylaneError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut pollfd = libc::pollfd {
+            fd: self.inner.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        loop {
+            let timeout_ms = match (timeout, deadline) {
+                (Some(_), Some(deadline)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let ms = remaining.as_secs()
+                        .saturating_mul(1000)
+                        .saturating_add(remaining.subsec_millis() as u64);
+                    std::cmp::min(ms, libc::c_int::MAX as u64) as libc::c_int
+                }
+                // Deadline not representable; wait as long as `poll` allows.
+                (Some(_), None) => libc::c_int::MAX,
+                (None, _) => -1,
+            };
+
+            let res = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+            match Errno::result(res) {
+                Ok(res) => return Ok(res > 0),
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Returns number of bytes waiting in socket to be read.
+    pub fn get_pending_bytes(&self) -> Result<usize, SkylaneError> {
+        let mut pending: libc::c_int = 0;
+        let res = unsafe { libc::ioctl(self.inner.fd, libc::FIONREAD, &mut pending) };
+        Errno::result(res)?;
+        Ok(pending as usize)
     }
 
     /// Reads from sockets.
@@ -132,45 +430,114 @@ impl Socket {
     /// used for file descriptors.
     ///
     /// Returns number of bytes written to `bytes` and number of file descriptors written to `fds`.
+    ///
+    /// If received descriptors do not fit in `fds` (which should have place for `MAX_FDS` of
+    /// them), all of them are closed and `SkylaneError::LimitExceeded` is returned. It is not
+    /// known which messages the lost descriptors belonged to, so the connection should be closed.
     pub fn receive_message(&self,
                            bytes: &mut [u8],
                            fds: &mut [u8])
                            -> Result<(usize, usize), SkylaneError> {
-        let mut cmsg: socket::CmsgSpace<[RawFd; 1]> = socket::CmsgSpace::new();
-        let mut iov: [uio::IoVec<&mut [u8]>; 1] = [uio::IoVec::from_mut_slice(&mut bytes[..]); 1];
-
-        let msg = socket::recvmsg(self.fd, &mut iov[..], Some(&mut cmsg), socket::MSG_DONTWAIT)?;
-
-        let mut num_fds = 0;
-        let mut buf = Cursor::new(fds);
-        for cmsg in msg.cmsgs() {
-            match cmsg {
-                socket::ControlMessage::ScmRights(newfds) => {
-                    buf.write_i32::<NativeEndian>(newfds[0])?;
-                    num_fds += 1;
+        let mut cmsg = cmsg_space!([RawFd; SCM_MAX_FD]);
+        let mut flags = socket::MsgFlags::MSG_CMSG_CLOEXEC;
+        if self.is_nonblocking() {
+            flags |= socket::MsgFlags::MSG_DONTWAIT;
+        }
+
+        let result = {
+            let mut iov = [IoSliceMut::new(bytes)];
+            socket::recvmsg::<()>(self.inner.fd, &mut iov, Some(&mut cmsg), flags).and_then(|msg| {
+                let mut received = Vec::new();
+                for cmsg in msg.cmsgs()? {
+                    if let socket::ControlMessageOwned::ScmRights(fds) = cmsg {
+                        received.extend_from_slice(&fds);
+                    }
+                }
+                Ok((msg.bytes, received))
+            })
+        };
+
+        let (num_bytes, received) = match result {
+            Ok(received) => received,
+            Err(err) => {
+                let err = SkylaneError::from(err);
+                if !err.is_would_block() {
+                    self.log(|| LogRecord {
+                                 direction: Some(Direction::Incoming),
+                                 ..LogRecord::new(LogLevel::Error, format!("Receiving: {:?}", err))
+                             });
+                }
+                return Err(err);
+            }
+        };
+
+        let num_fds = self.store_fds(&received, fds)?;
+        self.count_received(&bytes[..num_bytes], num_fds);
+        Ok((num_bytes, num_fds))
+    }
+
+    /// Reads from socket like `receive_message` and additionally returns credentials of the peer
+    /// if they were attached to received data (see `set_pass_credentials`).
+    pub fn receive_message_with_credentials(&self,
+                                            bytes: &mut [u8],
+                                            fds: &mut [u8])
+                                            -> Result<(usize, usize, Option<Credentials>),
+                                                      SkylaneError> {
+        match credentials::receive(self.inner.fd, bytes, self.is_nonblocking()) {
+            Ok((num_bytes, received, credentials)) => {
+                let num_fds = self.store_fds(&received, fds)?;
+                self.count_received(&bytes[..num_bytes], num_fds);
+                Ok((num_bytes, num_fds, credentials))
+            }
+            Err(err) => {
+                let err = SkylaneError::from(err);
+                if !err.is_would_block() {
+                    self.log(|| LogRecord {
+                                 direction: Some(Direction::Incoming),
+                                 ..LogRecord::new(LogLevel::Error, format!("Receiving: {:?}", err))
+                             });
                 }
-                _ => {}
+                Err(err)
             }
         }
-        Ok((msg.bytes, num_fds))
     }
 
     /// Writes given data to socket.
-    pub fn write(&self, bytes: &[u8]) -> Result<(), SkylaneError> {
-        let iov: [uio::IoVec<&[u8]>; 1] = [uio::IoVec::from_slice(&bytes[..]); 1];
-        let cmsgs: [socket::ControlMessage; 0] = unsafe { std::mem::uninitialized() };
-
-        socket::sendmsg(self.fd, &iov[..], &cmsgs[..], socket::MSG_DONTWAIT, None)?;
-        Ok(())
+    ///
+    /// Returns number of bytes written which may be less than length of `bytes` if socket buffer
+    /// is full. Caller is responsible for writing the rest later.
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, SkylaneError> {
+        self.write_vectored(&[bytes], &[])
     }
 
-    /// Writes given data to socket.
-    pub fn write_with_control_data(&self, bytes: &[u8], fds: &[RawFd]) -> Result<(), SkylaneError> {
-        let iov: [uio::IoVec<&[u8]>; 1] = [uio::IoVec::from_slice(&bytes[..]); 1];
-        let cmsgs = [socket::ControlMessage::ScmRights(fds)];
+    /// Writes given data to socket passing file descriptors `fds` along with them.
+    ///
+    /// Returns number of bytes written. File descriptors are passed along with the first written
+    /// byte, so they must not be passed again when writing the rest.
+    pub fn write_with_control_data(&self,
+                                   bytes: &[u8],
+                                   fds: &[RawFd])
+                                   -> Result<usize, SkylaneError> {
+        self.write_vectored(&[bytes], fds)
+    }
 
-        socket::sendmsg(self.fd, &iov[..], &cmsgs[..], socket::MSG_DONTWAIT, None)?;
-        Ok(())
+    /// Writes data gathered from multiple `slices` (e.g. header and body of message) in one call
+    /// passing file descriptors `fds` (if any) along with them.
+    ///
+    /// Returns number of bytes written. See `write_with_control_data`.
+    pub fn write_vectored(&self, slices: &[&[u8]], fds: &[RawFd]) -> Result<usize, SkylaneError> {
+        let iov: Vec<IoSlice> = slices.iter().map(|slice| IoSlice::new(slice)).collect();
+
+        let written = if self.inner.send_credentials.load(Ordering::SeqCst) {
+            self.send_with_credentials(slices, fds)?
+        } else if !fds.is_empty() {
+            self.send(&iov, &[socket::ControlMessage::ScmRights(fds)])?
+        } else {
+            self.send(&iov, &[])?
+        };
+
+        self.count_sent(slices, written, fds.len());
+        Ok(written)
     }
 }
 
@@ -183,15 +550,247 @@ impl Socket {
     /// This method is used by `DisplaySocket` when connection was accepted.
     fn new(fd: RawFd) -> Self {
         Socket {
-            fd: fd,
-            next_serial: std::cell::Cell::new(0),
-            logger: None,
+            inner: Arc::new(SocketInner {
+                                fd,
+                                stats: Mutex::new(Stats::default()),
+                                unfinished: AtomicUsize::new(0),
+                                send_credentials: AtomicBool::new(false),
+                                pass_credentials: AtomicBool::new(false),
+                                nonblocking: AtomicBool::new(true),
+                                logger: Mutex::new(None),
+                            }),
+            recorder: None,
+        }
+    }
+
+    /// Locks statistics.
+    fn lock_stats(&self) -> MutexGuard<'_, Stats> {
+        // Statistics are only counters so they are valid even if other thread panicked.
+        self.inner.stats.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Locks logger.
+    fn lock_logger(&self) -> MutexGuard<'_, Option<Arc<LogFn>>> {
+        self.inner.logger.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Sends data and control messages. Logs failures. Returns number of bytes sent.
+    fn send(&self,
+            iov: &[IoSlice],
+            cmsgs: &[socket::ControlMessage])
+            -> Result<usize, SkylaneError> {
+        let flags = socket::MsgFlags::MSG_DONTWAIT;
+        match socket::sendmsg::<()>(self.inner.fd, iov, cmsgs, flags, None) {
+            Ok(written) => Ok(written),
+            Err(err) => {
+                let err = SkylaneError::from(err);
+                self.log(|| LogRecord {
+                             direction: Some(Direction::Outgoing),
+                             ..LogRecord::new(LogLevel::Error, format!("Sending: {:?}", err))
+                         });
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends data and file descriptors along with credentials of current process. Logs failures.
+    fn send_with_credentials(&self,
+                             slices: &[&[u8]],
+                             fds: &[RawFd])
+                             -> Result<usize, SkylaneError> {
+        match credentials::send(self.inner.fd, slices, fds, &Credentials::current()) {
+            Ok(written) => Ok(written),
+            Err(err) => {
+                let err = SkylaneError::from(err);
+                self.log(|| LogRecord {
+                             direction: Some(Direction::Outgoing),
+                             ..LogRecord::new(LogLevel::Error, format!("Sending: {:?}", err))
+                         });
+                Err(err)
+            }
+        }
+    }
+
+    /// Writes received file descriptors `received` to `buf` as native-endian 32-bit integers.
+    /// Returns number of written descriptors.
+    ///
+    /// If descriptors do not fit in `buf`, closes all of them and returns error.
+    fn store_fds(&self, received: &[RawFd], buf: &mut [u8]) -> Result<usize, SkylaneError> {
+        let capacity = buf.len() / std::mem::size_of::<RawFd>();
+        if received.len() <= capacity {
+            let mut cursor = Cursor::new(buf);
+            for fd in received {
+                cursor.write_i32::<NativeEndian>(*fd)?;
+            }
+            return Ok(received.len());
+        }
+
+        for fd in received {
+            // Nothing can be done if closing fails.
+            let _ = nix::unistd::close(*fd);
+        }
+        self.lock_stats().fds_dropped += received.len() as u64;
+        let err = SkylaneError::LimitExceeded {
+            description: format!("Received file descriptors do not fit in buffer for {}; {} \
+                                  dropped",
+                                 capacity,
+                                 received.len()),
+        };
+        self.log(|| LogRecord {
+                     direction: Some(Direction::Incoming),
+                     ..LogRecord::new(LogLevel::Error, format!("Receiving: {:?}", err))
+                 });
+        Err(err)
+    }
+
+    /// Updates statistics after receiving `bytes` and `num_fds` file descriptors.
+    fn count_received(&self, bytes: &[u8], num_fds: usize) {
+        self.record(Direction::Incoming, bytes, num_fds);
+
+        let mut stats = self.lock_stats();
+        stats.bytes_received += bytes.len() as u64;
+        stats.fds_received += num_fds as u64;
+    }
+
+    /// Records data if recording is enabled.
+    fn record(&self, direction: Direction, bytes: &[u8], num_fds: usize) {
+        if let Some(ref recorder) = self.recorder {
+            if let Err(err) = recorder.record(direction, bytes, num_fds) {
+                self.log(|| LogRecord::new(LogLevel::Warning, format!("Recording: {:?}", err)));
+            }
+        }
+    }
+
+    /// Updates statistics and traces messages after writing first `written` bytes of data
+    /// gathered from `slices` and `num_fds` file descriptors.
+    ///
+    /// Messages are counted when their first byte is written. If data were written only partially
+    /// the rest of the last counted message is expected at the beginning of the next write and is
+    /// skipped.
+    fn count_sent(&self, slices: &[&[u8]], written: usize, num_fds: usize) {
+        let bytes = if slices.len() == 1 {
+            std::borrow::Cow::Borrowed(slices[0])
+        } else {
+            std::borrow::Cow::Owned(slices.concat())
+        };
+
+        self.record(Direction::Outgoing, &bytes[..written], num_fds);
+
+        let mut num_messages = 0;
+        let mut position = std::cmp::min(self.inner.unfinished.load(Ordering::SeqCst), written);
+        self.inner.unfinished.fetch_sub(position, Ordering::SeqCst);
+        while position + HEADER_SIZE <= bytes.len() && position < written {
+            let header = match Endianness::native().read_header(&bytes[position..]) {
+                Some(header) => header,
+                None => break,
+            };
+            if (header.size as usize) < HEADER_SIZE {
+                break;
+            }
+            self.log(|| {
+                LogRecord::for_message(LogLevel::Trace,
+                                       Direction::Outgoing,
+                                       &header,
+                                       format!("Sent {} bytes", header.size))
+            });
+            position += header.size as usize;
+            num_messages += 1;
+        }
+        if position > written {
+            self.inner.unfinished.store(position - written, Ordering::SeqCst);
+        }
+
+        let mut stats = self.lock_stats();
+        stats.messages_sent += num_messages;
+        stats.bytes_sent += written as u64;
+        stats.fds_sent += num_fds as u64;
+        stats.flushes += 1;
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Methods of `Socket` available in this crate but not exported.
+pub trait SocketInternal {
+    /// Updates statistics using given function.
+    fn update_stats<F>(&self, f: F) where F: FnOnce(&mut Stats);
+
+    /// Passes record created by `f` to logger. `f` is not called if logger is not set.
+    fn log<F>(&self, f: F) where F: FnOnce() -> LogRecord;
+
+    /// Makes this socket use the same logger as `other`.
+    fn inherit_logger(&self, other: &Socket);
+
+    /// Creates reference to the `Socket` which does not keep it open.
+    fn downgrade(&self) -> WeakSocket;
+}
+
+impl SocketInternal for Socket {
+    fn update_stats<F>(&self, f: F)
+        where F: FnOnce(&mut Stats)
+    {
+        f(&mut self.lock_stats());
+    }
+
+    fn log<F>(&self, f: F)
+        where F: FnOnce() -> LogRecord
+    {
+        // Logger is called without holding the lock so it may log on the socket itself.
+        let logger = self.lock_logger().clone();
+        if let Some(logger) = logger {
+            logger(&f());
+        }
+    }
+
+    fn inherit_logger(&self, other: &Socket) {
+        let logger = other.lock_logger().clone();
+        *self.lock_logger() = logger;
+    }
+
+    fn downgrade(&self) -> WeakSocket {
+        WeakSocket {
+            inner: Arc::downgrade(&self.inner),
+            recorder: self.recorder.clone(),
         }
     }
 }
 
 // -------------------------------------------------------------------------------------------------
 
+/// Reference to `Socket` not keeping its file descriptor open.
+///
+/// See `SocketInternal::downgrade`.
+pub struct WeakSocket {
+    inner: Weak<SocketInner>,
+    recorder: Option<Recorder>,
+}
+
+impl WeakSocket {
+    /// Returns the `Socket` if any of its clones is still alive.
+    pub fn upgrade(&self) -> Option<Socket> {
+        self.inner.upgrade().map(|inner| {
+                                     Socket {
+                                         inner,
+                                         recorder: self.recorder.clone(),
+                                     }
+                                 })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Options for creating `DisplaySocket`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplaySocketOptions {
+    /// Permission bits of socket file (e.g. `0o700`). If `None` they are decided by `umask`.
+    pub mode: Option<u32>,
+
+    /// ID of group owning socket file. If `None` group is not changed.
+    pub group: Option<u32>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
 /// Structure representing global socket on server side.
 ///
 /// After client connects to this socket `Socket` is created which can be then used for further
@@ -200,28 +799,58 @@ impl Socket {
 pub struct DisplaySocket {
     fd: RawFd,
     path: std::path::PathBuf,
+    accept_nonblocking: bool,
 }
 
 // -------------------------------------------------------------------------------------------------
 
 impl DisplaySocket {
     /// Creates new `DisplaySocket`.
+    ///
+    /// If `path` starts with `NUL` byte the rest of it is treated as name in abstract namespace.
+    /// Such socket is not visible in file system and does not need to be removed.
     pub fn new(path: &std::path::Path) -> Result<Self, SkylaneError> {
+        Self::new_with_options(path, &DisplaySocketOptions::default())
+    }
+
+    /// Creates new `DisplaySocket` applying given options to socket file.
+    ///
+    /// Options are applied before the socket starts listening so no client can connect with
+    /// permissions decided by `umask`. Options are ignored for abstract sockets.
+    ///
+    /// The socket is non-blocking: `accept` returns error if there are no pending connections (see
+    /// `set_nonblocking`).
+    pub fn new_with_options(path: &std::path::Path,
+                            options: &DisplaySocketOptions)
+                            -> Result<Self, SkylaneError> {
+        // The descriptor is closed on failure until it is passed to `DisplaySocket`.
         let sockfd = try_sock!("Creating",
                                path,
                                socket::socket(socket::AddressFamily::Unix,
                                               socket::SockType::Stream,
-                                              socket::SOCK_CLOEXEC,
-                                              0));
-
-        let unix_addr = try_sock!("Linking", path, socket::UnixAddr::new(path));
-        let sock_addr = socket::SockAddr::Unix(unix_addr);
-        try_sock!("Binding", path, socket::bind(sockfd, &sock_addr));
-        try_sock!("Listening", path, socket::listen(sockfd, 128));
+                                              socket::SockFlag::SOCK_CLOEXEC |
+                                              socket::SockFlag::SOCK_NONBLOCK,
+                                              None));
+
+        let unix_addr = try_sock!("Linking", path, make_unix_addr(path));
+        try_sock!("Binding", path, socket::bind(sockfd.as_raw_fd(), &unix_addr));
+        if !is_abstract(path) {
+            if let Some(group) = options.group {
+                try_sock!("Changing group", path, set_group(path, group));
+            }
+            if let Some(mode) = options.mode {
+                let permissions = std::fs::Permissions::from_mode(mode);
+                try_sock!("Changing mode", path, std::fs::set_permissions(path, permissions));
+            }
+        }
+        try_sock!("Listening",
+                  path,
+                  socket::Backlog::new(128).and_then(|backlog| socket::listen(&sockfd, backlog)));
 
         Ok(DisplaySocket {
-               fd: sockfd,
+               fd: sockfd.into_raw_fd(),
                path: path.to_owned(),
+               accept_nonblocking: true,
            })
     }
 
@@ -233,12 +862,104 @@ impl DisplaySocket {
         Self::new(&path)
     }
 
+    /// Creates new `DisplaySocket` named `name` (e.g. `wayland-1`) in `$XDG_RUNTIME_DIR`.
+    ///
+    /// Clients find the socket when `$WAYLAND_DISPLAY` is set to the name (see `get_name` and
+    /// `get_client_env`).
+    pub fn new_named(name: &str) -> Result<Self, SkylaneError> {
+        if name.is_empty() || name.contains('/') {
+            return Err(SkylaneError::Other(format!("Invalid display name: {:?}", name)));
+        }
+
+        let mut path = std::path::PathBuf::from(std::env::var("XDG_RUNTIME_DIR")?);
+        path.push(name);
+        Self::new(&path)
+    }
+
+    /// Returns path of the socket.
+    pub fn get_path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Returns display name of the socket, i.e. the last component of its path, or `None` for
+    /// abstract sockets.
+    pub fn get_name(&self) -> Option<&str> {
+        if is_abstract(&self.path) {
+            None
+        } else {
+            self.path.file_name().and_then(|name| name.to_str())
+        }
+    }
+
+    /// Returns environment variables which should be set for clients spawned by the server so
+    /// they connect to this socket. Can be passed to `std::process::Command::envs`.
+    ///
+    /// `$WAYLAND_DISPLAY` is set to the name if the socket is in `$XDG_RUNTIME_DIR`, otherwise to
+    /// absolute path. Abstract sockets can not be described this way so no variables are returned.
+    pub fn get_client_env(&self) -> Vec<(&'static str, std::ffi::OsString)> {
+        if is_abstract(&self.path) {
+            return Vec::new();
+        }
+
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(std::path::PathBuf::from);
+        let display = match (runtime_dir, self.path.file_name()) {
+            (Some(ref dir), Some(name)) if self.path.parent() == Some(dir.as_path()) => {
+                name.to_owned()
+            }
+            _ => self.path.as_os_str().to_owned(),
+        };
+        vec![("WAYLAND_DISPLAY", display)]
+    }
+
+    /// Sets or clears `O_NONBLOCK` flag of listening socket. In blocking mode `accept` waits for
+    /// client to connect.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), SkylaneError> {
+        set_fd_nonblocking(self.fd, nonblocking)?;
+        Ok(())
+    }
+
+    /// Decides if descriptors of accepted connections have `O_NONBLOCK` flag set (default).
+    pub fn set_accept_nonblocking(&mut self, nonblocking: bool) {
+        self.accept_nonblocking = nonblocking;
+    }
+
     /// Accepts client connection and return new `Socket`.
+    ///
+    /// Descriptor of the connection is created with `SOCK_CLOEXEC` flag so it does not leak to
+    /// spawned processes and with `SOCK_NONBLOCK` unless disabled with `set_accept_nonblocking`.
     pub fn accept(&self) -> Result<Socket, SkylaneError> {
-        let fd = socket::accept(self.fd)?;
+        let flags = if self.accept_nonblocking {
+            socket::SockFlag::SOCK_CLOEXEC | socket::SockFlag::SOCK_NONBLOCK
+        } else {
+            socket::SockFlag::SOCK_CLOEXEC
+        };
+        let fd = socket::accept4(self.fd, flags)?;
         Ok(Socket::new(fd))
     }
 
+    /// Accepts all pending client connections until there are no more.
+    ///
+    /// Meant for event loops waking up when the socket becomes readable; all connections should
+    /// be accepted on every wake-up.
+    pub fn accept_all(&self) -> Result<Vec<Socket>, SkylaneError> {
+        let mut sockets = Vec::new();
+        loop {
+            match self.accept() {
+                Ok(socket) => sockets.push(socket),
+                Err(ref err) if err.is_would_block() => break,
+                Err(err) => {
+                    if sockets.is_empty() {
+                        return Err(err);
+                    } else {
+                        // Report the error on next call, do not lose accepted connections.
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(sockets)
+    }
+
     /// Returns socket file descriptor.
     pub fn get_fd(&self) -> RawFd {
         self.fd
@@ -249,8 +970,10 @@ impl DisplaySocket {
 
 impl Drop for DisplaySocket {
     fn drop(&mut self) {
-        // Remove socket path. Nothing to do with result.
-        let _ = nix::unistd::unlink(self.path.as_path());
+        // Remove socket path. Nothing to do with result. Abstract sockets have no path.
+        if !is_abstract(&self.path) {
+            let _ = nix::unistd::unlink(self.path.as_path());
+        }
     }
 }
 
@@ -0,0 +1,74 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Connection statistics.
+
+use defs::Direction;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Counters describing traffic on connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of received messages.
+    pub messages_received: u64,
+
+    /// Number of sent messages.
+    pub messages_sent: u64,
+
+    /// Number of received bytes.
+    pub bytes_received: u64,
+
+    /// Number of sent bytes.
+    pub bytes_sent: u64,
+
+    /// Number of received file descriptors.
+    pub fds_received: u64,
+
+    /// Number of sent file descriptors.
+    pub fds_sent: u64,
+
+    /// Number of messages which handlers failed to dispatch.
+    pub dispatch_errors: u64,
+
+    /// Number of writes to the socket.
+    pub flushes: u64,
+
+    /// Number of received file descriptors closed because too many of them were pending (see
+    /// `Connection::set_fd_limit`).
+    pub fds_dropped: u64,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Receiver of per-message metrics.
+///
+/// Called for every received message and every sent message (when it is sent or queued), so
+/// counters and histograms can be maintained per interface and opcode without parsing traces.
+///
+/// See `Connection::set_metrics_sink`.
+pub trait MetricsSink {
+    /// Records message sent to object of given interface (if its `InterfaceMeta` was registered).
+    /// `size` includes message header.
+    fn record_message(&mut self,
+                      interface: Option<&'static str>,
+                      opcode: u16,
+                      size: usize,
+                      direction: Direction);
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,299 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Helpers for testing protocol handlers.
+//!
+//! Available with `testing` feature. `Loopback` plays the role of the peer of tested connection:
+//! it reads messages the connection sent and lets tests check them with concise assertions.
+//!
+//! ```
+//! # extern crate skylane;
+//! # use skylane::client::SkylaneError;
+//! use skylane::client::{Connection, InterfaceMeta, MessageMeta, DISPLAY_ID};
+//! use skylane::testing::{Arg, Loopback};
+//!
+//! static DISPLAY_META: InterfaceMeta = InterfaceMeta {
+//!     name: "wl_display",
+//!     version: 1,
+//!     requests: &[MessageMeta { name: "sync", signature: "n" }],
+//!     events: &[],
+//! };
+//!
+//! # fn run() -> Result<(), SkylaneError> {
+//! let (mut loopback, socket) = Loopback::pair()?;
+//! let mut connection = Connection::new(socket);
+//! loopback.set_interface_meta(DISPLAY_ID, &DISPLAY_META);
+//!
+//! let callback = connection.sync()?;
+//! loopback.expect_request("wl_display", 0, &[Arg::NewId(callback.get_id())]);
+//! loopback.expect_no_more_messages();
+//! # Ok(())
+//! # }
+//! # fn main() { run().unwrap(); }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::io::RawFd;
+
+use byteorder::{ByteOrder, NativeEndian};
+use nix;
+
+use defs::{Header, SkylaneError};
+use fd::OwnedFd;
+use marshal::Marshaller;
+use message::MessageIter;
+use meta::{InterfaceMeta, MessageMeta};
+use object::ObjectId;
+use sockets::{Socket, MAX_FDS};
+use trace::decode_args;
+
+pub use trace::Arg;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Size of buffer used for single read from socket.
+const READ_SIZE: usize = 4096;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Message read by `Loopback`.
+#[derive(Debug)]
+pub struct ReceivedMessage {
+    /// Header of the message.
+    pub header: Header,
+
+    /// Name of interface of target object if its metadata were registered.
+    pub interface: Option<&'static str>,
+
+    /// Name of the message if interface of target object is known.
+    pub name: Option<&'static str>,
+
+    /// Arguments decoded according to signature. Empty if signature is not known.
+    pub args: Vec<Arg>,
+
+    /// Raw arguments.
+    pub bytes: Vec<u8>,
+
+    /// File descriptors passed along with the message.
+    pub fds: Vec<OwnedFd>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Peer of tested connection.
+///
+/// Messages are decoded according to metadata registered with `set_interface_meta`. Metadata of
+/// `wl_display` are not built in; objects without metadata yield messages with raw arguments only.
+pub struct Loopback {
+    socket: Socket,
+    bytes: Vec<u8>,
+    fds: VecDeque<RawFd>,
+    metas: HashMap<ObjectId, &'static InterfaceMeta>,
+}
+
+impl Loopback {
+    /// Constructs new `Loopback` using `socket` connected to tested connection.
+    pub fn new(socket: Socket) -> Self {
+        socket.set_nonblocking(true);
+        Loopback {
+            socket,
+            bytes: Vec::new(),
+            fds: VecDeque::new(),
+            metas: HashMap::new(),
+        }
+    }
+
+    /// Creates pair of connected sockets and returns `Loopback` using one of them and the other
+    /// one to be used by tested connection.
+    pub fn pair() -> Result<(Self, Socket), SkylaneError> {
+        let (socket, peer) = Socket::pair()?;
+        Ok((Loopback::new(socket), peer))
+    }
+
+    /// Returns socket used to read messages.
+    pub fn get_socket(&self) -> Socket {
+        self.socket.clone()
+    }
+
+    /// Registers metadata of interface implemented by object `id`.
+    pub fn set_interface_meta(&mut self, id: ObjectId, meta: &'static InterfaceMeta) {
+        self.metas.insert(id, meta);
+    }
+
+    /// Sends message to tested connection.
+    pub fn send(&self, marshaller: Marshaller) -> Result<(), SkylaneError> {
+        let (bytes, fds) = marshaller.finish()?;
+        self.socket.write_with_control_data(&bytes, &fds)?;
+        Ok(())
+    }
+
+    /// Returns next request sent by tested client. Arguments are decoded according to signatures
+    /// of requests.
+    pub fn next_request(&mut self) -> Result<Option<ReceivedMessage>, SkylaneError> {
+        self.next_message(|meta| meta.requests)
+    }
+
+    /// Returns next event sent by tested server. Arguments are decoded according to signatures
+    /// of events.
+    pub fn next_event(&mut self) -> Result<Option<ReceivedMessage>, SkylaneError> {
+        self.next_message(|meta| meta.events)
+    }
+
+    /// Checks if next message sent by tested client is request `opcode` of `interface` with
+    /// given arguments and returns it.
+    ///
+    /// Panics with description of the difference otherwise.
+    pub fn expect_request(&mut self,
+                          interface: &str,
+                          opcode: u16,
+                          args: &[Arg])
+                          -> ReceivedMessage {
+        let message = self.next_request();
+        check_message(message, "request", interface, opcode, args)
+    }
+
+    /// Checks if next message sent by tested server is event `opcode` of `interface` with given
+    /// arguments and returns it.
+    ///
+    /// Panics with description of the difference otherwise.
+    pub fn expect_event(&mut self, interface: &str, opcode: u16, args: &[Arg]) -> ReceivedMessage {
+        let message = self.next_event();
+        check_message(message, "event", interface, opcode, args)
+    }
+
+    /// Checks if tested connection did not send any more messages.
+    ///
+    /// Panics with header of the first unexpected message otherwise.
+    pub fn expect_no_more_messages(&mut self) {
+        if let Err(err) = self.read_available() {
+            panic!("Failed to read messages: {:?}", err);
+        }
+        if let Some(Ok((header, _))) = MessageIter::new(&self.bytes).next() {
+            panic!("Expected no more messages, got {:?}", header);
+        }
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        for fd in self.fds.drain(..) {
+            let _ = nix::unistd::close(fd);
+        }
+    }
+}
+
+/// Private methods.
+impl Loopback {
+    /// Reads all data available in socket.
+    fn read_available(&mut self) -> Result<(), SkylaneError> {
+        let mut bytes = [0; READ_SIZE];
+        let mut fds = [0; 4 * MAX_FDS];
+        loop {
+            let (num_bytes, num_fds) = match self.socket.receive_message(&mut bytes, &mut fds) {
+                Ok((0, _)) => return Ok(()),
+                Ok(sizes) => sizes,
+                Err(ref err) if err.is_would_block() => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            self.bytes.extend_from_slice(&bytes[..num_bytes]);
+            for i in 0..num_fds {
+                self.fds.push_back(NativeEndian::read_i32(&fds[(4 * i)..(4 * i + 4)]));
+            }
+        }
+    }
+
+    /// Reads and decodes next message. `select` chooses requests or events of interface.
+    fn next_message<F>(&mut self, select: F) -> Result<Option<ReceivedMessage>, SkylaneError>
+        where F: Fn(&'static InterfaceMeta) -> &'static [MessageMeta]
+    {
+        self.read_available()?;
+        let (header, bytes) = match MessageIter::new(&self.bytes).next() {
+            Some(message) => {
+                let (header, bytes) = message?;
+                (header, bytes.to_vec())
+            }
+            None => return Ok(None),
+        };
+        self.bytes.drain(..(header.size as usize));
+
+        let object_id = ObjectId::new(header.object_id);
+        let meta = self.metas.get(&object_id).copied();
+        let message_meta = meta.and_then(|meta| select(meta).get(header.opcode as usize));
+        let signature = message_meta.map_or("", |message_meta| message_meta.signature);
+
+        let num_fds = signature.bytes().filter(|c| *c == b'h').count();
+        let mut fds = Vec::with_capacity(num_fds);
+        for _ in 0..num_fds {
+            match self.fds.pop_front() {
+                Some(fd) => fds.push(OwnedFd::new(fd)),
+                None => {
+                    return Err(SkylaneError::Other(format!("Missing file descriptor ({:?})",
+                                                           header)))
+                }
+            }
+        }
+
+        let args = decode_args(header, &bytes, signature)?;
+        Ok(Some(ReceivedMessage {
+                    header,
+                    interface: meta.map(|meta| meta.name),
+                    name: message_meta.map(|message_meta| message_meta.name),
+                    args,
+                    bytes,
+                    fds,
+                }))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if received message matches expectations. Panics with description of the difference.
+fn check_message(message: Result<Option<ReceivedMessage>, SkylaneError>,
+                 kind: &str,
+                 interface: &str,
+                 opcode: u16,
+                 args: &[Arg])
+                 -> ReceivedMessage {
+    let message = match message {
+        Ok(Some(message)) => message,
+        Ok(None) => panic!("Expected {} {}#{}, got nothing", kind, interface, opcode),
+        Err(err) => panic!("Expected {} {}#{}, got error: {:?}", kind, interface, opcode, err),
+    };
+
+    let got_interface = message.interface.unwrap_or("<unknown>");
+    if got_interface != interface || message.header.opcode != opcode {
+        panic!("Expected {} {}#{}, got {}#{} ({:?})",
+               kind,
+               interface,
+               opcode,
+               got_interface,
+               message.header.opcode,
+               message.header);
+    }
+
+    if message.args.as_slice() != args {
+        panic!("Unexpected arguments of {} {}.{}:\n  expected: {:?}\n       got: {:?}",
+               kind,
+               interface,
+               message.name.unwrap_or("<unknown>"),
+               args,
+               message.args);
+    }
+    message
+}
+
+// -------------------------------------------------------------------------------------------------
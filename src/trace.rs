@@ -0,0 +1,321 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Structured traces of sent and received messages.
+//!
+//! When trace sink is set (see `Connection::set_trace_sink`) every sent and received message is
+//! passed to it along with arguments decoded according to registered metadata (see
+//! `Bundle::set_interface_meta`). `TraceRecord` formats itself in style of `WAYLAND_DEBUG` output
+//! of `libwayland`, while `JsonTraceSink` writes JSON lines meant for external tools.
+
+use std;
+use std::fmt::Write as FmtWrite;
+use std::io::{Cursor, Write};
+use std::time::Instant;
+
+use defs::{Direction, Header, SkylaneError};
+use message::Message;
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Argument of message decoded according to signature.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    /// Signed integer (`i`).
+    Int(i32),
+
+    /// Unsigned integer (`u`).
+    Uint(u32),
+
+    /// Fixed-point number (`f`).
+    Fixed(f64),
+
+    /// String (`s`); `None` if nullable string was null.
+    Str(Option<String>),
+
+    /// Object ID (`o`); null objects have ID `0`.
+    Object(ObjectId),
+
+    /// New object ID (`n`).
+    NewId(ObjectId),
+
+    /// Array (`a`).
+    Array(Vec<u8>),
+
+    /// File descriptor (`h`). Descriptors are passed out of band, so their values are not part of
+    /// the argument.
+    Fd,
+}
+
+impl Arg {
+    /// Returns name of type of the argument as used in JSON traces.
+    pub fn get_type_name(&self) -> &'static str {
+        match *self {
+            Arg::Int(_) => "int",
+            Arg::Uint(_) => "uint",
+            Arg::Fixed(_) => "fixed",
+            Arg::Str(_) => "string",
+            Arg::Object(_) => "object",
+            Arg::NewId(_) => "new_id",
+            Arg::Array(_) => "array",
+            Arg::Fd => "fd",
+        }
+    }
+}
+
+impl std::fmt::Display for Arg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Arg::Int(value) => write!(f, "{}", value),
+            Arg::Uint(value) => write!(f, "{}", value),
+            Arg::Fixed(value) => write!(f, "{:.6}", value),
+            Arg::Str(Some(ref value)) => write!(f, "\"{}\"", value),
+            Arg::Str(None) => write!(f, "nil"),
+            Arg::Object(id) if id.is_null() => write!(f, "nil"),
+            Arg::Object(id) => write!(f, "@{}", id),
+            Arg::NewId(id) => write!(f, "new id @{}", id),
+            Arg::Array(ref value) => write!(f, "array[{}]", value.len()),
+            Arg::Fd => write!(f, "fd"),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Description of sent or received message passed to `TraceSink`.
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    /// Direction of the message.
+    pub direction: Direction,
+
+    /// Header of the message.
+    pub header: Header,
+
+    /// Name of interface of target object if its metadata were registered.
+    pub interface: Option<&'static str>,
+
+    /// Name of the message if interface of target object is known.
+    pub name: Option<&'static str>,
+
+    /// Decoded arguments. `None` if signature of the message is not known or arguments do not
+    /// match it.
+    pub args: Option<Vec<Arg>>,
+}
+
+impl std::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.direction {
+            Direction::Incoming => write!(f, "<- ")?,
+            Direction::Outgoing => write!(f, "-> ")?,
+        }
+        write!(f, "{}@{}.", self.interface.unwrap_or("unknown"), self.header.object_id)?;
+        match self.name {
+            Some(name) => write!(f, "{}(", name)?,
+            None => write!(f, "#{}(", self.header.opcode)?,
+        }
+        match self.args {
+            Some(ref args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+            }
+            None => write!(f, "{} bytes", self.header.size)?,
+        }
+        write!(f, ")")
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Receiver of traces of all sent and received messages.
+///
+/// See `Connection::set_trace_sink`.
+pub trait TraceSink {
+    /// Records sent or received message.
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Trace sink writing one JSON object per message, each in separate line.
+///
+/// Example line:
+///
+/// ```text
+/// {"time":12.5,"direction":"out","object_id":3,"interface":"wl_surface","opcode":1,
+///  "name":"attach","size":20,"args":[{"type":"object","value":5},{"type":"int","value":0},
+///  {"type":"int","value":0}]}
+/// ```
+///
+/// `time` is number of milliseconds since the sink was created. `interface`, `name` and `args` are
+/// `null` if not known. Arrays are written as lists of bytes. File descriptors have no value.
+pub struct JsonTraceSink<W: Write> {
+    output: W,
+    start: Instant,
+}
+
+impl<W: Write> JsonTraceSink<W> {
+    /// Constructs new `JsonTraceSink` writing to `output`.
+    pub fn new(output: W) -> Self {
+        JsonTraceSink {
+            output,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the output.
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> TraceSink for JsonTraceSink<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let elapsed = self.start.elapsed();
+        let time = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1000000.0;
+        let mut line = String::new();
+        let _ = write!(line,
+                       "{{\"time\":{:.3},\"direction\":\"{}\",\"object_id\":{},",
+                       time,
+                       match record.direction {
+                           Direction::Incoming => "in",
+                           Direction::Outgoing => "out",
+                       },
+                       record.header.object_id);
+        let _ = write!(line,
+                       "\"interface\":{},\"opcode\":{},\"name\":{},\"size\":{},\"args\":",
+                       to_json_string(record.interface),
+                       record.header.opcode,
+                       to_json_string(record.name),
+                       record.header.size);
+        match record.args {
+            Some(ref args) => {
+                line.push('[');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        line.push(',');
+                    }
+                    write_arg_json(&mut line, arg);
+                }
+                line.push(']');
+            }
+            None => line.push_str("null"),
+        }
+        line.push_str("}\n");
+
+        // Tracing must not break the connection; write errors are ignored.
+        let _ = self.output.write_all(line.as_bytes());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Decodes arguments of message according to `signature` in `libwayland` notation. File
+/// descriptors are not read from the message.
+pub fn decode_args(header: Header,
+                   bytes: &[u8],
+                   signature: &str)
+                   -> Result<Vec<Arg>, SkylaneError> {
+    let mut no_fds = Cursor::new(&[][..]);
+    let mut message = Message::new(header, bytes, &mut no_fds);
+    let mut args = Vec::new();
+    let mut nullable = false;
+    for kind in signature.bytes() {
+        let arg = match kind {
+            b'?' => {
+                nullable = true;
+                continue;
+            }
+            b'i' => Arg::Int(message.next_int()?),
+            b'u' => Arg::Uint(message.next_uint()?),
+            b'f' => Arg::Fixed(message.next_fixed()?),
+            b's' if nullable => {
+                let bytes = message.next_array()?;
+                if bytes.is_empty() {
+                    Arg::Str(None)
+                } else {
+                    Arg::Str(Some(decode_string(header, bytes)?))
+                }
+            }
+            b's' => Arg::Str(Some(decode_string(header, message.next_array()?)?)),
+            b'o' => Arg::Object(message.next_object()?),
+            b'n' => Arg::NewId(message.next_object()?),
+            b'a' => Arg::Array(message.next_array()?),
+            b'h' => Arg::Fd,
+            _ => continue,
+        };
+        args.push(arg);
+        nullable = false;
+    }
+    Ok(args)
+}
+
+/// Escapes characters not allowed in JSON strings.
+pub fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decodes NUL-terminated string.
+fn decode_string(header: Header, mut bytes: Vec<u8>) -> Result<String, SkylaneError> {
+    if bytes.pop() != Some(0) {
+        return Err(SkylaneError::Other(format!("String not terminated with NUL ({:?})", header)));
+    }
+    String::from_utf8(bytes)
+        .map_err(|err| SkylaneError::Other(format!("Invalid string ({:?}): {:?}", header, err)))
+}
+
+/// Returns quoted and escaped string or `null`.
+fn to_json_string(text: Option<&str>) -> String {
+    match text {
+        Some(text) => format!("\"{}\"", escape_json(text)),
+        None => "null".to_owned(),
+    }
+}
+
+/// Writes argument as JSON object with its type and value.
+fn write_arg_json(line: &mut String, arg: &Arg) {
+    let _ = write!(line, "{{\"type\":\"{}\"", arg.get_type_name());
+    let _ = match *arg {
+        Arg::Int(value) => write!(line, ",\"value\":{}", value),
+        Arg::Uint(value) => write!(line, ",\"value\":{}", value),
+        Arg::Fixed(value) => write!(line, ",\"value\":{}", value),
+        Arg::Str(ref value) => {
+            write!(line, ",\"value\":{}", to_json_string(value.as_ref().map(|s| s.as_str())))
+        }
+        Arg::Object(id) if id.is_null() => write!(line, ",\"value\":null"),
+        Arg::Object(id) | Arg::NewId(id) => write!(line, ",\"value\":{}", id),
+        Arg::Array(ref value) => write!(line, ",\"value\":{:?}", value),
+        Arg::Fd => Ok(()),
+    };
+    line.push('}');
+}
+
+// -------------------------------------------------------------------------------------------------
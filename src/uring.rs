@@ -0,0 +1,475 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Transport submitting socket I/O through `io_uring`.
+//!
+//! Available with `io-uring` feature. Requires Linux 5.8 or newer.
+//!
+//! Registered (fixed) buffers can be used only by `read_fixed`/`write_fixed` operations, not by
+//! `recvmsg`/`sendmsg` which are needed to pass file descriptors. Receive buffers are therefore
+//! provided to the ring (`IORING_OP_PROVIDE_BUFFERS`) instead: they are also handed to the kernel
+//! once upfront and the kernel picks one only when data arrives.
+
+use std;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use nix;
+use nix::libc;
+
+use defs::SkylaneError;
+use connection::Connection;
+use dispatch::DispatchReport;
+use fd::OwnedFd;
+use sockets::{Socket, SCM_MAX_FD};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Default number of receive buffers provided to the ring.
+pub const DEFAULT_NUM_BUFFERS: u16 = 8;
+
+/// Default size of single receive buffer.
+pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+/// Number of submission queue entries.
+const NUM_ENTRIES: u32 = 32;
+
+/// ID of group of provided receive buffers.
+const BUFFER_GROUP: u16 = 0;
+
+/// Index of the socket in table of files registered in the ring.
+const SOCKET_INDEX: u32 = 0;
+
+/// User data of receive operations.
+const RECEIVE_TAG: u64 = 1;
+
+/// User data of send operations.
+const SEND_TAG: u64 = 2;
+
+/// User data of operations providing receive buffers.
+const PROVIDE_TAG: u64 = 3;
+
+/// User data of operations cancelling other operations.
+const CANCEL_TAG: u64 = 4;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Header of `recvmsg`/`sendmsg` operation. Boxed, so its address stays valid while the operation
+/// is in flight.
+struct MessageHeader {
+    header: libc::msghdr,
+    iovec: libc::iovec,
+    control: Vec<u64>,
+}
+
+impl MessageHeader {
+    /// Constructs new `MessageHeader` with control buffer big enough for all descriptors the
+    /// kernel passes with one message (see `SCM_MAX_FD`), so none of them is lost.
+    fn new() -> Box<Self> {
+        let size = SCM_MAX_FD * std::mem::size_of::<RawFd>();
+        let space = unsafe { libc::CMSG_SPACE(size as u32) };
+        let mut message = Box::new(MessageHeader {
+                                       header: unsafe { std::mem::zeroed() },
+                                       iovec: libc::iovec {
+                                           iov_base: std::ptr::null_mut(),
+                                           iov_len: 0,
+                                       },
+                                       control: vec![0; (space as usize + 7) / 8],
+                                   });
+        message.header.msg_iov = &mut message.iovec;
+        message.header.msg_iovlen = 1;
+        message
+    }
+
+    /// Prepares header for receiving data into buffer selected by kernel.
+    fn prepare_receive(&mut self, buffer_size: usize) {
+        self.iovec.iov_base = std::ptr::null_mut();
+        self.iovec.iov_len = buffer_size;
+        self.header.msg_control = self.control.as_mut_ptr() as *mut libc::c_void;
+        self.header.msg_controllen = (8 * self.control.len()) as _;
+        self.header.msg_flags = 0;
+    }
+
+    /// Prepares header for sending `bytes` and `fds`.
+    fn prepare_send(&mut self, bytes: &[u8], fds: &[RawFd]) {
+        self.iovec.iov_base = bytes.as_ptr() as *mut libc::c_void;
+        self.iovec.iov_len = bytes.len();
+        if fds.is_empty() {
+            self.header.msg_control = std::ptr::null_mut();
+            self.header.msg_controllen = 0;
+        } else {
+            let size = std::mem::size_of_val(fds);
+            self.header.msg_control = self.control.as_mut_ptr() as *mut libc::c_void;
+            unsafe {
+                self.header.msg_controllen = libc::CMSG_SPACE(size as u32) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(&self.header);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(size as u32) as _;
+                std::ptr::copy_nonoverlapping(fds.as_ptr() as *const u8,
+                                              libc::CMSG_DATA(cmsg),
+                                              size);
+            }
+        }
+    }
+
+    /// Returns file descriptors received in control data.
+    fn get_received_fds(&self) -> Vec<RawFd> {
+        let mut fds = Vec::new();
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&self.header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    let size = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    for i in 0..(size / std::mem::size_of::<RawFd>()) {
+                        fds.push(std::ptr::read_unaligned(data.add(i)));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&self.header, cmsg);
+            }
+        }
+        fds
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Transport driving I/O of `Connection` through `io_uring`.
+///
+/// Connection is switched to detached I/O mode (see `Connection::set_detached_io`). Data is
+/// received with `recvmsg` operations into buffers provided to the ring upfront, so no buffer has
+/// to be reserved for every pending read, and the socket is registered in the ring to avoid file
+/// table lookups. Messages drained from connection are sent with `sendmsg` along with their file
+/// descriptors.
+///
+/// The API is completion-driven: `submit` queues operations, `complete` dispatches results of
+/// finished ones. The ring file descriptor (`get_fd`) becomes readable when completions are
+/// available, so the transport can be integrated with event loop.
+///
+/// Dropping the transport cancels pending operations and blocks until the kernel completes them,
+/// as until then it may still access the buffers.
+pub struct UringTransport {
+    ring: IoUring,
+    socket: Socket,
+    buffers: Vec<u8>,
+    buffer_size: usize,
+    receive: Box<MessageHeader>,
+    send: Box<MessageHeader>,
+    sending: Vec<u8>,
+    sending_fds: Vec<RawFd>,
+    sending_owned: Vec<OwnedFd>,
+    backlog: Vec<u8>,
+    backlog_fds: Vec<RawFd>,
+    backlog_owned: Vec<OwnedFd>,
+    in_flight: usize,
+    is_receiving: bool,
+    is_sending: bool,
+    is_closed: bool,
+}
+
+impl UringTransport {
+    /// Constructs new `UringTransport` for `connection` with default buffers.
+    pub fn new(connection: &mut Connection) -> Result<Self, SkylaneError> {
+        Self::with_buffers(connection, DEFAULT_NUM_BUFFERS, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Constructs new `UringTransport` for `connection` providing `num_buffers` receive buffers of
+    /// `buffer_size` bytes to the ring.
+    pub fn with_buffers(connection: &mut Connection,
+                        num_buffers: u16,
+                        buffer_size: usize)
+                        -> Result<Self, SkylaneError> {
+        if num_buffers == 0 || buffer_size == 0 || buffer_size > i32::MAX as usize {
+            return Err(SkylaneError::Other("Invalid receive buffers".to_owned()));
+        }
+
+        let socket = connection.get_socket();
+        let ring = IoUring::new(NUM_ENTRIES)?;
+        ring.submitter().register_files(&[socket.get_fd()])?;
+        connection.set_detached_io(true);
+
+        let mut transport = UringTransport {
+            ring,
+            socket,
+            buffers: vec![0; num_buffers as usize * buffer_size],
+            buffer_size,
+            receive: MessageHeader::new(),
+            send: MessageHeader::new(),
+            sending: Vec::new(),
+            sending_fds: Vec::new(),
+            sending_owned: Vec::new(),
+            backlog: Vec::new(),
+            backlog_fds: Vec::new(),
+            backlog_owned: Vec::new(),
+            in_flight: 0,
+            is_receiving: false,
+            is_sending: false,
+            is_closed: false,
+        };
+
+        let entry = opcode::ProvideBuffers::new(transport.buffers.as_mut_ptr(),
+                                                buffer_size as i32,
+                                                num_buffers,
+                                                BUFFER_GROUP,
+                                                0)
+            .build()
+            .user_data(PROVIDE_TAG);
+        transport.push(entry)?;
+        transport.ring.submit_and_wait(1)?;
+        let completion = transport.ring.completion().next();
+        if completion.is_some() {
+            transport.in_flight -= 1;
+        }
+        check_result(completion.map_or(0, |entry| entry.result()))?;
+        Ok(transport)
+    }
+
+    /// Returns file descriptor of the ring. It becomes readable when there are completions to be
+    /// handled by `complete`.
+    pub fn get_fd(&self) -> RawFd {
+        self.ring.as_raw_fd()
+    }
+
+    /// Returns socket of the connection.
+    pub fn get_socket(&self) -> Socket {
+        self.socket.clone()
+    }
+
+    /// Checks if the peer closed the connection.
+    pub fn is_closed(&self) -> bool {
+        self.is_closed
+    }
+
+    /// Queues receive operation if none is pending and send operation with messages drained from
+    /// `connection`, then submits them to the kernel. Returns number of submitted operations.
+    pub fn submit(&mut self, connection: &mut Connection) -> Result<usize, SkylaneError> {
+        let (bytes, fds, owned) = connection.drain_output();
+        self.backlog.extend_from_slice(&bytes);
+        self.backlog_fds.extend_from_slice(&fds);
+        self.backlog_owned.extend(owned);
+
+        if !self.is_receiving && !self.is_closed {
+            self.receive.prepare_receive(self.buffer_size);
+            let entry = opcode::RecvMsg::new(types::Fixed(SOCKET_INDEX), &mut self.receive.header)
+                .flags(libc::MSG_CMSG_CLOEXEC as u32)
+                .buf_group(BUFFER_GROUP)
+                .build()
+                .flags(squeue::Flags::BUFFER_SELECT)
+                .user_data(RECEIVE_TAG);
+            self.push(entry)?;
+            self.is_receiving = true;
+        }
+
+        if !self.is_sending && !self.backlog.is_empty() {
+            self.sending = std::mem::take(&mut self.backlog);
+            self.sending_fds = std::mem::take(&mut self.backlog_fds);
+            self.sending_owned = std::mem::take(&mut self.backlog_owned);
+            self.submit_send()?;
+        }
+
+        Ok(self.ring.submit()?)
+    }
+
+    /// Submits queued operations and waits until at least one of them completes, then handles
+    /// completions.
+    pub fn wait(&mut self, connection: &mut Connection) -> Result<DispatchReport, SkylaneError> {
+        self.submit(connection)?;
+        self.ring.submit_and_wait(1)?;
+        self.complete(connection)
+    }
+
+    /// Handles finished operations: received data is dispatched by `connection`, unsent rest of
+    /// partially sent data is sent again and receive buffers are given back to the ring. Messages
+    /// sent by handlers are submitted afterwards.
+    ///
+    /// Returns report of dispatching of all received data.
+    pub fn complete(&mut self,
+                    connection: &mut Connection)
+                    -> Result<DispatchReport, SkylaneError> {
+        let completions: Vec<cqueue::Entry> = self.ring.completion().collect();
+        self.in_flight -= completions.len();
+        let mut report = DispatchReport::default();
+        for entry in completions {
+            match entry.user_data() {
+                RECEIVE_TAG => {
+                    self.is_receiving = false;
+                    let partial = self.handle_receive(&entry, connection)?;
+                    merge_reports(&mut report, partial);
+                }
+                SEND_TAG => {
+                    self.is_sending = false;
+                    self.handle_send(&entry)?;
+                }
+                _ => check_result(entry.result())?,
+            }
+        }
+        self.submit(connection)?;
+        Ok(report)
+    }
+}
+
+/// Private methods.
+impl UringTransport {
+    /// Pushes `entry` to submission queue submitting queued entries first if it is full.
+    fn push(&mut self, entry: squeue::Entry) -> Result<(), SkylaneError> {
+        if self.ring.submission().is_full() {
+            self.ring.submit()?;
+        }
+        unsafe { self.ring.submission().push(&entry) }
+            .map_err(|_| SkylaneError::Other("Submission queue is full".to_owned()))?;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Cancels pending receive and send operations and waits until all operations in flight
+    /// complete. Returns error if the kernel may still access the buffers.
+    fn cancel_all(&mut self) -> Result<(), SkylaneError> {
+        let mut tags = Vec::new();
+        if self.is_receiving {
+            tags.push(RECEIVE_TAG);
+        }
+        if self.is_sending {
+            tags.push(SEND_TAG);
+        }
+        for tag in tags {
+            self.push(opcode::AsyncCancel::new(tag).build().user_data(CANCEL_TAG))?;
+        }
+
+        while self.in_flight > 0 {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(SkylaneError::from(err)),
+            }
+            let completions: Vec<cqueue::Entry> = self.ring.completion().collect();
+            self.in_flight -= completions.len();
+            for entry in completions {
+                if entry.user_data() == RECEIVE_TAG && entry.result() >= 0 {
+                    for fd in self.receive.get_received_fds() {
+                        let _ = nix::unistd::close(fd);
+                    }
+                }
+            }
+        }
+        self.is_receiving = false;
+        self.is_sending = false;
+        Ok(())
+    }
+
+    /// Queues sending data not sent yet.
+    fn submit_send(&mut self) -> Result<(), SkylaneError> {
+        self.send.prepare_send(&self.sending, &self.sending_fds);
+        let entry = opcode::SendMsg::new(types::Fixed(SOCKET_INDEX), &self.send.header)
+            .flags(libc::MSG_NOSIGNAL as u32)
+            .build()
+            .user_data(SEND_TAG);
+        self.push(entry)?;
+        self.is_sending = true;
+        Ok(())
+    }
+
+    /// Dispatches received data and gives the buffer back to the ring.
+    fn handle_receive(&mut self,
+                      entry: &cqueue::Entry,
+                      connection: &mut Connection)
+                      -> Result<DispatchReport, SkylaneError> {
+        let result = entry.result();
+        check_result(result)?;
+
+        let fds = self.receive.get_received_fds();
+        let buffer_id = match cqueue::buffer_select(entry.flags()) {
+            Some(buffer_id) => buffer_id,
+            None => {
+                // No data, so no buffer was taken.
+                self.is_closed = true;
+                return connection.feed_bytes(&[], &fds);
+            }
+        };
+
+        let start = buffer_id as usize * self.buffer_size;
+        let report = connection.feed_bytes(&self.buffers[start..(start + result as usize)], &fds);
+
+        let entry = opcode::ProvideBuffers::new(self.buffers[start..].as_mut_ptr(),
+                                                self.buffer_size as i32,
+                                                1,
+                                                BUFFER_GROUP,
+                                                buffer_id)
+            .build()
+            .user_data(PROVIDE_TAG);
+        self.push(entry)?;
+
+        if result == 0 {
+            self.is_closed = true;
+        }
+        report
+    }
+
+    /// Drops sent data and queues sending of the rest.
+    fn handle_send(&mut self, entry: &cqueue::Entry) -> Result<(), SkylaneError> {
+        let result = entry.result();
+        check_result(result)?;
+
+        // File descriptors are passed along with the first sent byte, so the owned ones can be
+        // closed now.
+        self.sending_fds.clear();
+        self.sending_owned.clear();
+        self.sending.drain(..(result as usize));
+        if !self.sending.is_empty() {
+            self.submit_send()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UringTransport {
+    fn drop(&mut self) {
+        if self.cancel_all().is_err() {
+            // The kernel may still write to these; leaking them is the only safe option.
+            std::mem::forget(std::mem::take(&mut self.buffers));
+            std::mem::forget(std::mem::take(&mut self.sending));
+            std::mem::forget(std::mem::take(&mut self.sending_owned));
+            std::mem::forget(std::mem::replace(&mut self.receive, MessageHeader::new()));
+            std::mem::forget(std::mem::replace(&mut self.send, MessageHeader::new()));
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Converts result of completed operation to error if it failed.
+fn check_result(result: i32) -> Result<(), SkylaneError> {
+    if result < 0 {
+        Err(SkylaneError::from(std::io::Error::from_raw_os_error(-result)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds results from `partial` report to `report`.
+fn merge_reports(report: &mut DispatchReport, partial: DispatchReport) {
+    report.bytes_read += partial.bytes_read;
+    report.num_dispatched += partial.num_dispatched;
+    report.num_filtered += partial.num_filtered;
+    report.failures.extend(partial.failures);
+    report.reconnected |= partial.reconnected;
+    if partial.posted_error.is_some() {
+        report.posted_error = partial.posted_error;
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,360 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Validation of outgoing messages meant to be used during development.
+//!
+//! When enabled every marshalled message is parsed back and checked against signature of called
+//! method. This helps catching mismatches between generated code and handlers early.
+
+use std::collections::HashMap;
+use std::str;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use defs::Side;
+use endian::Endianness;
+use marshal::HEADER_SIZE;
+use meta::{InterfaceMeta, MessageMeta};
+use object::ObjectId;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Decides what to do with outgoing messages failing validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ValidationMode {
+    /// Messages are not validated.
+    #[default]
+    Off,
+
+    /// Invalid messages are reported to the socket logger (see `Socket::set_logger`) and sent
+    /// anyway.
+    Log,
+
+    /// Sending invalid message panics.
+    Panic,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Decides what to do with outgoing messages not available in version of interface bound for the
+/// object.
+///
+/// Messages are checked only if both version (see `Bundle::set_object_version`) and interface
+/// metadata (see `Bundle::set_interface_meta`) are known for the object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum VersionCheck {
+    /// Messages are not checked.
+    Off,
+
+    /// Unsupported messages are logged and dropped.
+    Drop,
+
+    /// Sending unsupported message returns `SkylaneError::UnsupportedVersion`. Queued messages
+    /// are dropped as queueing can not fail.
+    #[default]
+    Error,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Signatures of messages indexed by opcode registered either directly or as part of interface
+/// metadata.
+#[derive(Clone, Copy)]
+enum Signatures {
+    Plain(&'static [&'static str]),
+    Meta(&'static [MessageMeta]),
+}
+
+impl Signatures {
+    /// Returns signature of message with given opcode.
+    fn get(&self, opcode: u16) -> Option<&'static str> {
+        match *self {
+            Signatures::Plain(signatures) => signatures.get(opcode as usize).copied(),
+            Signatures::Meta(messages) => messages.get(opcode as usize).map(|m| m.signature),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Everything `Validator` knows about single object, used to restore it when transaction is rolled
+/// back.
+#[derive(Clone, Copy, Default)]
+pub struct ObjectSnapshot {
+    signatures: Option<Signatures>,
+    incoming: Option<Signatures>,
+    meta: Option<&'static InterfaceMeta>,
+    version: Option<u32>,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Keeps validation mode and signatures of messages objects may send or receive.
+///
+/// Signatures of sent messages are used for validation. Signatures of received messages are used
+/// to find out how many file descriptors dropped messages carried.
+///
+/// Signatures use the same notation as `libwayland`: `i` - int, `u` - uint, `f` - fixed,
+/// `s` - string, `o` - object, `n` - new ID, `a` - array, `h` - file descriptor. `?` marks
+/// following argument as nullable and numbers (since-version) are ignored.
+pub struct Validator {
+    mode: ValidationMode,
+    version_check: VersionCheck,
+    signatures: HashMap<ObjectId, Signatures>,
+    incoming: HashMap<ObjectId, Signatures>,
+    metas: HashMap<ObjectId, &'static InterfaceMeta>,
+    interfaces: HashMap<&'static str, &'static InterfaceMeta>,
+    versions: HashMap<ObjectId, u32>,
+}
+
+impl Validator {
+    /// Constructs new `Validator`.
+    pub fn new() -> Self {
+        Validator {
+            mode: ValidationMode::default(),
+            version_check: VersionCheck::default(),
+            signatures: HashMap::new(),
+            incoming: HashMap::new(),
+            metas: HashMap::new(),
+            interfaces: HashMap::new(),
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Returns validation mode.
+    pub fn get_mode(&self) -> ValidationMode {
+        self.mode
+    }
+
+    /// Sets validation mode.
+    pub fn set_mode(&mut self, mode: ValidationMode) {
+        self.mode = mode;
+    }
+
+    /// Returns version check mode.
+    pub fn get_version_check(&self) -> VersionCheck {
+        self.version_check
+    }
+
+    /// Sets version check mode.
+    pub fn set_version_check(&mut self, version_check: VersionCheck) {
+        self.version_check = version_check;
+    }
+
+    /// Registers signatures of messages sent on behalf of given object indexed by opcode.
+    pub fn set_signatures(&mut self, object_id: ObjectId, signatures: &'static [&'static str]) {
+        self.signatures.insert(object_id, Signatures::Plain(signatures));
+    }
+
+    /// Registers signatures of messages received by given object indexed by opcode.
+    pub fn set_incoming_signatures(&mut self,
+                                   object_id: ObjectId,
+                                   signatures: &'static [&'static str]) {
+        self.incoming.insert(object_id, Signatures::Plain(signatures));
+    }
+
+    /// Registers metadata of interface implemented by given object. Signatures of sent and
+    /// received messages are taken from it according to `side` of connection.
+    pub fn set_meta(&mut self, object_id: ObjectId, meta: &'static InterfaceMeta, side: Side) {
+        self.signatures.insert(object_id, Signatures::Meta(meta.get_outgoing(side)));
+        self.incoming.insert(object_id, Signatures::Meta(meta.get_incoming(side)));
+        self.metas.insert(object_id, meta);
+        self.interfaces.insert(meta.name, meta);
+    }
+
+    /// Returns metadata of interface with given name if it was registered for any object, even
+    /// one already removed.
+    pub fn find_interface(&self, name: &str) -> Option<&'static InterfaceMeta> {
+        self.interfaces.get(name).cloned()
+    }
+
+    /// Returns metadata of interface implemented by given object if registered.
+    pub fn get_meta(&self, object_id: ObjectId) -> Option<&'static InterfaceMeta> {
+        self.metas.get(&object_id).cloned()
+    }
+
+    /// Sets version of interface bound for given object.
+    pub fn set_version(&mut self, object_id: ObjectId, version: u32) {
+        self.versions.insert(object_id, version);
+    }
+
+    /// Returns version of interface bound for given object if set.
+    pub fn get_version(&self, object_id: ObjectId) -> Option<u32> {
+        self.versions.get(&object_id).cloned()
+    }
+
+    /// Checks if message with given opcode sent by given `side` on behalf of given object is
+    /// available in version bound for the object. Returns `(since, version)` if it is not.
+    pub fn check_version(&self,
+                         object_id: ObjectId,
+                         opcode: u16,
+                         side: Side)
+                         -> Option<(u32, u32)> {
+        let version = match self.versions.get(&object_id) {
+            Some(version) => *version,
+            None => return None,
+        };
+        self.metas
+            .get(&object_id)
+            .and_then(|meta| meta.get_outgoing(side).get(opcode as usize))
+            .map(|message| message.get_since())
+            .and_then(|since| if since > version { Some((since, version)) } else { None })
+    }
+
+    /// Forgets signatures of messages sent on behalf of given object.
+    pub fn remove_outgoing_signatures(&mut self, object_id: ObjectId) {
+        self.signatures.remove(&object_id);
+    }
+
+    /// Forgets all signatures, metadata and version of given object.
+    pub fn remove_signatures(&mut self, object_id: ObjectId) {
+        self.signatures.remove(&object_id);
+        self.incoming.remove(&object_id);
+        self.metas.remove(&object_id);
+        self.versions.remove(&object_id);
+    }
+
+    /// Returns everything known about given object.
+    pub fn snapshot(&self, object_id: ObjectId) -> ObjectSnapshot {
+        ObjectSnapshot {
+            signatures: self.signatures.get(&object_id).cloned(),
+            incoming: self.incoming.get(&object_id).cloned(),
+            meta: self.metas.get(&object_id).cloned(),
+            version: self.versions.get(&object_id).cloned(),
+        }
+    }
+
+    /// Replaces everything known about given object with `snapshot` taken earlier.
+    pub fn restore(&mut self, object_id: ObjectId, snapshot: ObjectSnapshot) {
+        self.remove_signatures(object_id);
+        if let Some(signatures) = snapshot.signatures {
+            self.signatures.insert(object_id, signatures);
+        }
+        if let Some(incoming) = snapshot.incoming {
+            self.incoming.insert(object_id, incoming);
+        }
+        if let Some(meta) = snapshot.meta {
+            self.metas.insert(object_id, meta);
+        }
+        if let Some(version) = snapshot.version {
+            self.versions.insert(object_id, version);
+        }
+    }
+
+    /// Returns number of file descriptors carried by message with given opcode received by given
+    /// object or `None` if signature is not known.
+    pub fn get_incoming_fd_count(&self, object_id: ObjectId, opcode: u16) -> Option<usize> {
+        self.incoming
+            .get(&object_id)
+            .and_then(|signatures| signatures.get(opcode))
+            .map(|signature| signature.bytes().filter(|c| *c == b'h').count())
+    }
+
+    /// Checks if marshalled message is well formed and matches both signature recorded while
+    /// marshalling and registered signature of the method (if any).
+    pub fn validate(&self, bytes: &[u8], num_fds: usize, recorded: &[u8]) -> Result<(), String> {
+        let header = match Endianness::native().read_header(bytes) {
+            Some(header) => header,
+            None => return Err(format!("message shorter than header ({} bytes)", bytes.len())),
+        };
+        let object_id = ObjectId::new(header.object_id);
+        let opcode = header.opcode;
+        let size = header.size as usize;
+        if object_id.is_null() {
+            return Err(format!("message addressed to null object (opcode: {})", opcode));
+        }
+        if size != bytes.len() {
+            return Err(format!("size in header ({}) differs from message size ({})",
+                               size,
+                               bytes.len()));
+        }
+
+        if let Some(signatures) = self.signatures.get(&object_id) {
+            if let Some(signature) = signatures.get(opcode) {
+                let expected = normalize(signature);
+                if expected != recorded {
+                    return Err(format!("arguments '{}' do not match signature '{}' \
+                                        (object: {}, opcode: {})",
+                                       String::from_utf8_lossy(recorded),
+                                       signature,
+                                       object_id,
+                                       opcode));
+                }
+            } else {
+                return Err(format!("unknown opcode {} for object {}", opcode, object_id));
+            }
+        }
+
+        parse(&bytes[HEADER_SIZE..], num_fds, recorded)
+            .map_err(|err| format!("{} (object: {}, opcode: {})", err, object_id, opcode))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Strips nullability markers and version numbers from signature leaving only argument types.
+fn normalize(signature: &str) -> Vec<u8> {
+    signature.bytes().filter(|c| b'a' <= *c && *c <= b'z').collect()
+}
+
+/// Parses message body according to signature.
+fn parse(body: &[u8], num_fds: usize, signature: &[u8]) -> Result<(), String> {
+    let mut pos = 0;
+    let mut fds = 0;
+    for (i, kind) in signature.iter().enumerate() {
+        match *kind {
+            b'i' | b'u' | b'f' | b'o' | b'n' => {
+                if pos + 4 > body.len() {
+                    return Err(format!("argument {} exceeds message", i));
+                }
+                pos += 4;
+            }
+            b's' | b'a' => {
+                if pos + 4 > body.len() {
+                    return Err(format!("argument {} exceeds message", i));
+                }
+                let len = NativeEndian::read_u32(&body[pos..pos + 4]) as usize;
+                let start = pos + 4;
+                let end = start + ((len + 3) & !3);
+                if end > body.len() {
+                    return Err(format!("argument {} exceeds message", i));
+                }
+                if *kind == b's' && len > 0 {
+                    let string = &body[start..start + len];
+                    if string[len - 1] != 0 {
+                        return Err(format!("string argument {} not terminated with NUL", i));
+                    }
+                    if str::from_utf8(&string[..len - 1]).is_err() {
+                        return Err(format!("string argument {} is not valid UTF-8", i));
+                    }
+                }
+                pos = end;
+            }
+            b'h' => fds += 1,
+            other => return Err(format!("unknown argument type '{}'", other as char)),
+        }
+    }
+
+    if pos != body.len() {
+        Err(format!("{} trailing bytes after arguments", body.len() - pos))
+    } else if fds != num_fds {
+        Err(format!("{} file descriptors attached, {} expected", num_fds, fds))
+    } else {
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
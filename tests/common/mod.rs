@@ -0,0 +1,85 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Helpers shared by integration tests.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use skylane::server::{Bundle, Connection, Marshaller, Message, Object, SkylaneError, Socket, Task,
+                      DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler doing nothing.
+pub struct Dummy;
+
+impl Object for Dummy {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Ok(Task::None)
+    }
+}
+
+/// Function run by `Runner`.
+type RunFn = Box<dyn FnOnce(&mut Bundle)>;
+
+/// Handler running function once with `Bundle` of the connection.
+struct Runner {
+    run: Option<RunFn>,
+}
+
+impl Object for Runner {
+    fn dispatch_message(&mut self,
+                        bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        if let Some(run) = self.run.take() {
+            run(bundle);
+        }
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Constructs connection in detached I/O mode. Returns also the peer socket.
+pub fn detached_connection() -> (Socket, Connection) {
+    let (peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    (peer, connection)
+}
+
+/// Runs `f` with `Bundle` of the connection the way handlers do, while dispatching message fed to
+/// display object, and returns its result. Display object is replaced.
+pub fn run_in_handler<F, T>(connection: &mut Connection, f: F) -> T
+    where F: FnOnce(&mut Bundle) -> T + 'static,
+          T: 'static
+{
+    let result = Rc::new(RefCell::new(None));
+    let handler_result = result.clone();
+    let run: RunFn = Box::new(move |bundle| *handler_result.borrow_mut() = Some(f(bundle)));
+    connection.add_object(DISPLAY_ID, Box::new(Runner { run: Some(run) }));
+
+    let (bytes, fds) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+    connection.feed_bytes(&bytes, &fds).expect("feed");
+    let result = result.borrow_mut().take();
+    result.expect("handler was run")
+}
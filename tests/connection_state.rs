@@ -0,0 +1,105 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of connection state transitions.
+
+extern crate skylane;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use skylane::server::{Connection, ConnectionState, DisconnectReason, Marshaller, SkylaneError,
+                      Socket, DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that posting error moves connection to `Erroring`, calls disconnect handler and
+/// refuses further messages, while terminated connection refuses also dispatching.
+#[test]
+fn posted_error_ends_connection() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    let reasons = Rc::new(RefCell::new(Vec::new()));
+    let handler_reasons = reasons.clone();
+    connection.set_disconnect_handler(Some(Box::new(move |reason| {
+                                                        handler_reasons.borrow_mut().push(reason)
+                                                    })));
+    let controller = connection.get_controller();
+
+    connection.post_error(DISPLAY_ID, 1, "bad").expect("post error");
+    assert_eq!(connection.state(), ConnectionState::Erroring);
+    assert_eq!(*reasons.borrow(),
+               vec![DisconnectReason::ProtocolError {
+                        object_id: DISPLAY_ID,
+                        code: 1,
+                        message: "bad".to_owned(),
+                    }]);
+
+    let (bytes, fds) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+    match controller.send_event(&bytes, &fds) {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+    match connection.post_error(DISPLAY_ID, 1, "bad") {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+    assert_eq!(reasons.borrow().len(), 1);
+
+    connection.terminate().expect("terminate");
+    assert_eq!(connection.state(), ConnectionState::Closed);
+    match connection.dispatch_pending() {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that posting error through `Controller` ends connection the same way as posting it
+/// through `Connection`.
+#[test]
+fn error_posted_by_controller_ends_connection() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    let reasons = Rc::new(RefCell::new(Vec::new()));
+    let handler_reasons = reasons.clone();
+    connection.set_disconnect_handler(Some(Box::new(move |reason| {
+                                                        handler_reasons.borrow_mut().push(reason)
+                                                    })));
+    let controller = connection.get_controller();
+
+    controller.post_error(DISPLAY_ID, 2, "bad").expect("post error");
+    assert_eq!(connection.state(), ConnectionState::Erroring);
+    assert!(connection.has_posted_error());
+    assert_eq!(*reasons.borrow(),
+               vec![DisconnectReason::ProtocolError {
+                        object_id: DISPLAY_ID,
+                        code: 2,
+                        message: "bad".to_owned(),
+                    }]);
+
+    match connection.post_error(DISPLAY_ID, 2, "bad") {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+    match controller.post_error(DISPLAY_ID, 2, "bad") {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+    assert_eq!(reasons.borrow().len(), 1);
+}
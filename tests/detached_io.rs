@@ -0,0 +1,81 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of feeding received data to connection in detached I/O mode.
+
+extern crate skylane;
+
+use std::io::Read;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use skylane::server::{Connection, Marshaller, SkylaneError, Socket, DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns descriptor of one end of stream pair and the other end. The other end reads end of
+/// stream once the descriptor is closed.
+fn make_fd() -> (RawFd, UnixStream) {
+    let (fd, peer) = UnixStream::pair().expect("stream pair");
+    peer.set_read_timeout(Some(Duration::from_secs(1))).expect("set timeout");
+    (fd.into_raw_fd(), peer)
+}
+
+/// Checks that descriptor returned by `make_fd` was closed.
+fn assert_closed(mut peer: UnixStream) {
+    let mut buf = [0; 1];
+    assert_eq!(peer.read(&mut buf).expect("read end of stream"), 0);
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that data can not be fed unless detached I/O mode is enabled and that descriptors are
+/// not leaked then.
+#[test]
+fn feeding_requires_detached_io() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    let (fd, fd_peer) = make_fd();
+    let (bytes, _) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+
+    match connection.feed_bytes(&bytes, &[fd]) {
+        Err(SkylaneError::Other(_)) => {}
+        other => panic!("Expected error, got {:?}", other),
+    }
+    assert_closed(fd_peer);
+}
+
+/// Checks that descriptors fed to ended connection or along with end of stream are closed.
+#[test]
+fn descriptors_are_closed_on_early_return() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+
+    let (fd, fd_peer) = make_fd();
+    connection.feed_bytes(&[], &[fd]).expect("feed end of stream");
+    assert_closed(fd_peer);
+
+    let (fd, fd_peer) = make_fd();
+    let (bytes, _) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+    match connection.feed_bytes(&bytes, &[fd]) {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+    assert_closed(fd_peer);
+}
@@ -0,0 +1,67 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of tracking nested dispatch loops.
+
+extern crate skylane;
+
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use skylane::server::{Bundle, Connection, Marshaller, Message, Object, SkylaneError, Socket, Task,
+                      DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler panicking on first message and counting the following ones.
+struct Panicking {
+    count: Rc<Cell<usize>>,
+}
+
+impl Object for Panicking {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        self.count.set(self.count.get() + 1);
+        if self.count.get() == 1 {
+            panic!("Handler failure");
+        }
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that dispatch depth is restored when a handler panics, so the connection can still
+/// dispatch messages afterwards.
+#[test]
+fn dispatch_depth_is_restored_after_panic() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    let count = Rc::new(Cell::new(0));
+    connection.add_object(DISPLAY_ID, Box::new(Panicking { count: count.clone() }));
+
+    let (bytes, fds) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+    let result = panic::catch_unwind(AssertUnwindSafe(|| connection.feed_bytes(&bytes, &fds)));
+    assert!(result.is_err());
+    assert_eq!(count.get(), 1);
+
+    connection.feed_bytes(&bytes, &fds).expect("feed after panic");
+    assert!(count.get() > 1);
+}
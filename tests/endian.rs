@@ -0,0 +1,66 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of byte order of encoded messages.
+
+extern crate skylane;
+
+use skylane::server::{check_native_endianness, Endianness, Header};
+
+// -------------------------------------------------------------------------------------------------
+
+fn header() -> Header {
+    Header {
+        object_id: 0x01020304,
+        opcode: 0x0506,
+        size: 0x0708,
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if marshaller and parser agree on native byte order.
+#[test]
+fn test_native_endianness() {
+    check_native_endianness().unwrap();
+}
+
+/// Checks if header is encoded in little endian with size in upper half of second word.
+#[test]
+fn test_little_endian_header() {
+    let mut bytes = [0; 8];
+    Endianness::Little.write_header(&header(), &mut bytes);
+    assert_eq!(bytes, [0x04, 0x03, 0x02, 0x01, 0x06, 0x05, 0x08, 0x07]);
+    assert_eq!(Endianness::Little.read_header(&bytes), Some(header()));
+}
+
+/// Checks if header is encoded in big endian with size in upper half of second word.
+#[test]
+fn test_big_endian_header() {
+    let mut bytes = [0; 8];
+    Endianness::Big.write_header(&header(), &mut bytes);
+    assert_eq!(bytes, [0x01, 0x02, 0x03, 0x04, 0x07, 0x08, 0x05, 0x06]);
+    assert_eq!(Endianness::Big.read_header(&bytes), Some(header()));
+}
+
+/// Checks if too short buffer is not decoded.
+#[test]
+fn test_truncated_header() {
+    assert_eq!(Endianness::native().read_header(&[0; 7]), None);
+}
+
+// -------------------------------------------------------------------------------------------------
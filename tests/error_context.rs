@@ -0,0 +1,105 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of attaching received messages to errors.
+
+extern crate skylane;
+
+use skylane::server::{Bundle, Connection, Header, Marshaller, Message, Object, SkylaneError, Socket,
+                      Task, DISPLAY_ID, MAX_CONTEXT_BYTES};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler failing on every message.
+struct Failing;
+
+impl Object for Failing {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Err(SkylaneError::Other("Handler failure".to_owned()))
+    }
+}
+
+/// Constructs connection with error context enabled and failing handler of display.
+fn make_connection() -> (Socket, Connection) {
+    let (peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    connection.set_error_context(true);
+    connection.add_object(DISPLAY_ID, Box::new(Failing));
+    (peer, connection)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that error of parsing message with malformed size carries the bytes following the
+/// header, not only the header.
+#[test]
+fn malformed_message_context_contains_body() {
+    let (_peer, mut connection) = make_connection();
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&1u32.to_ne_bytes());
+    bytes.extend_from_slice(&(4u32 << 16).to_ne_bytes());
+    bytes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+    match connection.feed_bytes(&bytes, &[]) {
+        Err(SkylaneError::Context { header, bytes, .. }) => {
+            assert_eq!(header.size, 4);
+            assert!(bytes.ends_with("deadbeef"), "{}", bytes);
+        }
+        other => panic!("Expected error with context, got {:?}", other),
+    }
+}
+
+/// Checks that long messages attached to errors are truncated.
+#[test]
+fn long_message_context_is_truncated() {
+    let (_peer, mut connection) = make_connection();
+    let mut marshaller = Marshaller::new(DISPLAY_ID, 0);
+    marshaller.put_array(&[0xab; 4 * MAX_CONTEXT_BYTES]);
+    let (bytes, fds) = marshaller.finish().expect("finish message");
+
+    let report = connection.feed_bytes(&bytes, &fds).expect("feed");
+    assert_eq!(report.failures.len(), 1);
+    match report.failures[0].error {
+        SkylaneError::Context { ref bytes, ref error, .. } => {
+            assert!(bytes.ends_with(" ..."), "{}", bytes);
+            assert_eq!(bytes.matches("ab").count(), MAX_CONTEXT_BYTES - 12);
+            assert!(matches!(**error, SkylaneError::Other(_)));
+        }
+        ref other => panic!("Expected error with context, got {:?}", other),
+    }
+}
+
+/// Checks that errors wrapped with context are classified by the original error.
+#[test]
+fn wrapped_error_is_classified_by_root_cause() {
+    let header = Header {
+        object_id: 1,
+        opcode: 0,
+        size: 8,
+    };
+    let would_block = SkylaneError::from(std::io::Error::from(std::io::ErrorKind::WouldBlock))
+        .with_message_context(header, &[0; 8]);
+    assert!(would_block.is_would_block());
+    assert!(!would_block.is_disconnected());
+
+    let closed = SkylaneError::Closed.with_message_context(header, &[0; 8]);
+    assert!(closed.is_disconnected());
+    assert!(!closed.is_would_block());
+}
@@ -0,0 +1,127 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of limit on number of pending file descriptors.
+
+extern crate skylane;
+
+use std::cell::Cell;
+use std::fs::File;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::rc::Rc;
+
+use skylane::server::{Bundle, Connection, FdLimit, FdOverflowPolicy, Marshaller, Message, Object,
+                      SkylaneError, Socket, Task, DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler taking one file descriptor from every message and counting messages.
+struct FdCounter {
+    count: Rc<Cell<usize>>,
+}
+
+impl Object for FdCounter {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        message.next_fd()?;
+        self.count.set(self.count.get() + 1);
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns message to display carrying one file descriptor.
+fn message_with_fd() -> (Vec<u8>, Vec<RawFd>) {
+    let fd = File::open("/dev/null").expect("open /dev/null").into_raw_fd();
+    let mut marshaller = Marshaller::new(DISPLAY_ID, 0);
+    marshaller.put_fd(fd);
+    marshaller.finish().expect("finish message")
+}
+
+/// Returns detached connection with fd limit and handler counting dispatched messages.
+fn setup(limit: FdLimit) -> (Connection, Socket, Rc<Cell<usize>>) {
+    let (peer, socket) = Socket::pair().expect("socket pair");
+    let count = Rc::new(Cell::new(0));
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    connection.set_fd_limit(Some(limit));
+    connection.add_object(DISPLAY_ID, Box::new(FdCounter { count: count.clone() }));
+    (connection, peer, count)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that messages received before the limit was exceeded are not dispatched with wrong file
+/// descriptors after it was.
+#[test]
+fn overflow_discards_pending_messages() {
+    let (mut connection, _peer, count) = setup(FdLimit::new(1, FdOverflowPolicy::Error));
+    connection.pause();
+
+    let (bytes, fds) = message_with_fd();
+    connection.feed_bytes(&bytes, &fds).expect("feed within limit");
+    let (bytes, fds) = message_with_fd();
+    match connection.feed_bytes(&bytes, &fds) {
+        Err(SkylaneError::LimitExceeded { .. }) => {}
+        other => panic!("Expected exceeded limit, got {:?}", other),
+    }
+    assert_eq!(connection.stats().fds_dropped, 2);
+
+    connection.resume();
+    let report = connection.dispatch_pending().expect("dispatch");
+    assert_eq!(report.num_dispatched, 0);
+    assert_eq!(count.get(), 0);
+
+    // Data received after overflow are discarded as well.
+    let (bytes, fds) = message_with_fd();
+    match connection.feed_bytes(&bytes, &fds) {
+        Err(SkylaneError::LimitExceeded { .. }) => {}
+        other => panic!("Expected exceeded limit, got {:?}", other),
+    }
+    let report = connection.dispatch_pending().expect("dispatch");
+    assert_eq!(report.num_dispatched, 0);
+    assert_eq!(count.get(), 0);
+    assert_eq!(connection.stats().fds_dropped, 3);
+    assert!(connection.is_alive());
+}
+
+/// Checks that messages within the limit are dispatched and overflow with `Disconnect` policy
+/// terminates the connection.
+#[test]
+fn overflow_disconnects() {
+    let (mut connection, _peer, count) = setup(FdLimit::new(1, FdOverflowPolicy::Disconnect));
+
+    let (bytes, fds) = message_with_fd();
+    let report = connection.feed_bytes(&bytes, &fds).expect("feed within limit");
+    assert_eq!(report.num_dispatched, 1);
+    assert_eq!(count.get(), 1);
+
+    let (mut bytes, mut fds) = message_with_fd();
+    let (more_bytes, more_fds) = message_with_fd();
+    bytes.extend(more_bytes);
+    fds.extend(more_fds);
+    match connection.feed_bytes(&bytes, &fds) {
+        Err(SkylaneError::LimitExceeded { .. }) => {}
+        other => panic!("Expected exceeded limit, got {:?}", other),
+    }
+    assert_eq!(count.get(), 1);
+    assert!(connection.is_disconnected());
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,144 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Compatibility tests against `libwayland`.
+//!
+//! Tests are skipped unless enabled with environment variables:
+//!
+//!  - `SKYLANE_TEST_COMPOSITOR` - name of socket (like `$WAYLAND_DISPLAY`) of running compositor
+//!    using `libwayland`; `skylane` client performs initial handshake with it,
+//!
+//!  - `SKYLANE_TEST_CLIENT` - path to `libwayland` client listing globals (e.g. `wayland-info`);
+//!    it is spawned and connected to `skylane` server.
+
+extern crate skylane;
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use skylane::client;
+use skylane::server;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Interface of global advertised by test server.
+const TEST_INTERFACE: &str = "skylane_test_global";
+
+/// Version of global advertised by test server.
+const TEST_VERSION: u32 = 3;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns path of socket of compositor named `name`.
+fn get_socket_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(std::env::var("XDG_RUNTIME_DIR").expect("XDG_RUNTIME_DIR"));
+    path.push(name);
+    path
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler of test global ignoring all requests.
+struct TestObject;
+
+impl server::Object for TestObject {}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Connects to `libwayland` compositor, gets registry, waits for globals and binds
+/// `wl_compositor`.
+#[test]
+fn skylane_client_with_libwayland_compositor() {
+    let name = match std::env::var("SKYLANE_TEST_COMPOSITOR") {
+        Ok(name) => name,
+        Err(_) => return,
+    };
+
+    let path = get_socket_path(&name);
+    let (mut connection, registry) = client::connect(Some(&path)).expect("connect");
+    let globals = registry.get_globals();
+    assert!(!globals.is_empty(), "Compositor advertised no globals");
+    for global in globals.iter() {
+        assert!(global.name > 0);
+        assert!(global.version > 0);
+        assert!(!global.interface.is_empty());
+        assert!(!global.interface.contains('\0'));
+    }
+
+    let compositor = registry.find("wl_compositor").expect("wl_compositor not advertised");
+    registry.bind(&mut connection, &compositor, 1, Box::new(TestObject))
+        .expect("bind");
+
+    // Compositor would post error and disconnect if the request was malformed.
+    connection.roundtrip().expect("roundtrip after bind");
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Runs `skylane` server and checks if `libwayland` client sees its global.
+#[test]
+fn libwayland_client_with_skylane_server() {
+    let program = match std::env::var("SKYLANE_TEST_CLIENT") {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+
+    let display = server::DisplaySocket::new_named("skylane-compat-test").expect("display");
+    display.set_nonblocking(false).expect("blocking display");
+
+    let registry = server::GlobalRegistry::new();
+    registry.add_global(TEST_INTERFACE,
+                        TEST_VERSION,
+                        Box::new(|_, _, _| Ok(Box::new(TestObject) as Box<dyn server::Object>)))
+        .expect("add global");
+
+    let child = Command::new(program)
+        .envs(display.get_client_env())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("spawn client");
+
+    let socket = display.accept().expect("accept");
+    let info = server::ClientInfo::new(&socket, display.get_name());
+    let mut connection = server::Connection::new_server(socket, registry.get_factory(info));
+    loop {
+        let socket = connection.get_socket();
+        if !socket.wait_readable(Some(Duration::from_secs(5))).expect("wait") {
+            panic!("Client did not finish in time");
+        }
+        match connection.process_events_with_report() {
+            Ok(ref report) if report.bytes_read == 0 => break,
+            Ok(ref report) => {
+                assert!(report.is_ok(), "Dispatch failed: {:?}", report.failures);
+                assert!(report.posted_error.is_none());
+            }
+            Err(ref err) if err.is_would_block() => {}
+            Err(ref err) if err.is_disconnected() => break,
+            Err(err) => panic!("Processing failed: {:?}", err),
+        }
+    }
+
+    let output = child.wait_with_output().expect("wait for client");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(TEST_INTERFACE),
+            "Client did not list test global:\n{}",
+            stdout);
+}
+
+// -------------------------------------------------------------------------------------------------
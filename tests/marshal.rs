@@ -0,0 +1,172 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Property-based tests checking that marshalled messages demarshal to the same arguments.
+
+extern crate byteorder;
+#[macro_use]
+extern crate quickcheck;
+extern crate skylane;
+
+use std::io::Cursor;
+use std::os::unix::io::RawFd;
+
+use byteorder::{ByteOrder, NativeEndian, WriteBytesExt};
+use quickcheck::{Arbitrary, Gen};
+
+use skylane::server::{Header, Marshaller, Message, ObjectId, MAX_MESSAGE_SIZE};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Single argument of a message.
+#[derive(Clone, Debug, PartialEq)]
+enum Arg {
+    Int(i32),
+    Uint(u32),
+    Fixed(i32),
+    Object(u32),
+    NewId(u32),
+    Str(String),
+    Array(Vec<u8>),
+    Fd(RawFd),
+}
+
+impl Arbitrary for Arg {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        match g.next_u32() % 8 {
+            0 => Arg::Int(i32::arbitrary(g)),
+            1 => Arg::Uint(u32::arbitrary(g)),
+            2 => Arg::Fixed(i32::arbitrary(g)),
+            3 => Arg::Object(u32::arbitrary(g)),
+            4 => Arg::NewId(u32::arbitrary(g)),
+            5 => Arg::Str(String::arbitrary(g)),
+            6 => Arg::Array(Vec::<u8>::arbitrary(g)),
+            _ => Arg::Fd(RawFd::arbitrary(g)),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Marshals `args` and returns message bytes and file descriptors.
+fn marshal(object_id: u32, opcode: u16, args: &[Arg]) -> (Vec<u8>, Vec<RawFd>) {
+    let mut marshaller = Marshaller::new(ObjectId::from_raw_unchecked(object_id), opcode);
+    for arg in args {
+        match *arg {
+            Arg::Int(value) => marshaller.put_int(value),
+            Arg::Uint(value) => marshaller.put_uint(value),
+            Arg::Fixed(value) => marshaller.put_fixed(value as f64 / 256.0),
+            Arg::Object(value) => marshaller.put_object(ObjectId::from_raw_unchecked(value)),
+            Arg::NewId(value) => marshaller.put_new_id(ObjectId::from_raw_unchecked(value)),
+            Arg::Str(ref value) => marshaller.put_string(value),
+            Arg::Array(ref value) => marshaller.put_array(value),
+            Arg::Fd(value) => marshaller.put_fd(value),
+        }
+    }
+    marshaller.finish().expect("finish message")
+}
+
+/// Reads back arguments of the same types as `expected` from marshalled message.
+fn demarshal(bytes: &[u8], fds: &[RawFd], expected: &[Arg]) -> (Header, Vec<Arg>, bool) {
+    let header = Header {
+        object_id: NativeEndian::read_u32(&bytes[0..4]),
+        opcode: NativeEndian::read_u16(&bytes[4..6]),
+        size: NativeEndian::read_u16(&bytes[6..8]),
+    };
+
+    let mut raw_fds = Vec::new();
+    for fd in fds {
+        raw_fds.write_i32::<NativeEndian>(*fd).unwrap();
+    }
+    let mut fds_buf = Cursor::new(&raw_fds[..]);
+
+    let mut message = Message::new(header, &bytes[8..], &mut fds_buf);
+    let mut args = Vec::new();
+    for arg in expected {
+        args.push(match *arg {
+                      Arg::Int(_) => Arg::Int(message.next_int().unwrap()),
+                      Arg::Uint(_) => Arg::Uint(message.next_uint().unwrap()),
+                      Arg::Fixed(_) => Arg::Fixed((message.next_fixed().unwrap() * 256.0) as i32),
+                      Arg::Object(_) => Arg::Object(message.next_object().unwrap().get_value()),
+                      Arg::NewId(_) => Arg::NewId(message.next_new_id().unwrap().get_value()),
+                      Arg::Str(_) => Arg::Str(message.next_string().unwrap()),
+                      Arg::Array(_) => Arg::Array(message.next_array().unwrap()),
+                      Arg::Fd(_) => Arg::Fd(message.next_fd().unwrap().into_raw()),
+                  });
+    }
+
+    let (_, args_buf, fds_buf) = message.as_raw_parts();
+    let consumed = args_buf.position() as usize == args_buf.get_ref().len() &&
+                   fds_buf.position() as usize == fds_buf.get_ref().len();
+    (header, args, consumed)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+quickcheck! {
+    /// Checks that any sequence of arguments survives marshalling and demarshalling.
+    fn round_trip(object_id: u32, opcode: u16, args: Vec<Arg>) -> bool {
+        let (bytes, fds) = marshal(object_id, opcode, &args);
+        let (header, demarshalled, consumed) = demarshal(&bytes, &fds, &args);
+        header.object_id == object_id && header.opcode == opcode &&
+        header.size as usize == bytes.len() && demarshalled == args && consumed
+    }
+
+    /// Checks that messages are padded to 4-byte boundary.
+    fn padding(args: Vec<Arg>) -> bool {
+        let (bytes, _) = marshal(1, 0, &args);
+        bytes.len() % 4 == 0
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks strings and arrays with lengths around 4-byte boundaries, including empty string and
+/// strings with multi-byte characters.
+#[test]
+fn padding_edge_cases() {
+    let strings = ["", "a", "ab", "abc", "abcd", "żółw", "日本", "🦀🦀", "a\u{0}b"];
+    for string in strings.iter() {
+        let args = vec![Arg::Str(string.to_string()), Arg::Uint(7)];
+        let (bytes, fds) = marshal(1, 0, &args);
+        assert_eq!(bytes.len(), 8 + 4 + ((string.len() + 1 + 3) & !3) + 4);
+        assert_eq!(demarshal(&bytes, &fds, &args).1, args);
+    }
+
+    for len in 0..9 {
+        let args = vec![Arg::Array(vec![0xAB; len]), Arg::Fd(3), Arg::Int(-1)];
+        let (bytes, fds) = marshal(1, 0, &args);
+        assert_eq!(bytes.len(), 8 + 4 + ((len + 3) & !3) + 4);
+        assert_eq!(demarshal(&bytes, &fds, &args).1, args);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that messages too long for size field of the header are refused instead of truncated.
+#[test]
+fn too_long_message_is_refused() {
+    let mut marshaller = Marshaller::new(ObjectId::from_raw_unchecked(1), 0);
+    marshaller.put_array(&vec![0; MAX_MESSAGE_SIZE]);
+    assert!(marshaller.finalize().is_err());
+    assert!(marshaller.finish().is_err());
+
+    let mut marshaller = Marshaller::new(ObjectId::from_raw_unchecked(1), 0);
+    marshaller.put_array(&vec![0; 65520]);
+    let (bytes, _) = marshaller.finish().expect("finish message");
+    assert_eq!(bytes.len(), 65532);
+}
@@ -0,0 +1,123 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of splitting received bytes into messages.
+
+extern crate byteorder;
+extern crate skylane;
+
+use byteorder::{NativeEndian, WriteBytesExt};
+
+use skylane::server::MessageIter;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Appends message with given header fields and arguments to `bytes`.
+fn push_message(bytes: &mut Vec<u8>, object_id: u32, opcode: u16, size: u16, args: &[u8]) {
+    bytes.write_u32::<NativeEndian>(object_id).unwrap();
+    bytes.write_u16::<NativeEndian>(opcode).unwrap();
+    bytes.write_u16::<NativeEndian>(size).unwrap();
+    bytes.extend_from_slice(args);
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn complete_messages() {
+    let mut bytes = Vec::new();
+    push_message(&mut bytes, 1, 0, 12, &[1, 2, 3, 4]);
+    push_message(&mut bytes, 3, 2, 8, &[]);
+
+    let mut messages = MessageIter::new(&bytes);
+    let (header, args) = messages.next().unwrap().unwrap();
+    assert_eq!((header.object_id, header.opcode, header.size), (1, 0, 12));
+    assert_eq!(args, &[1, 2, 3, 4]);
+    assert_eq!(messages.get_position(), 12);
+
+    let (header, args) = messages.next().unwrap().unwrap();
+    assert_eq!((header.object_id, header.opcode, header.size), (3, 2, 8));
+    assert!(args.is_empty());
+
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), bytes.len());
+    assert!(messages.get_remaining().is_empty());
+    assert!(!messages.is_truncated());
+}
+
+#[test]
+fn truncated_arguments() {
+    let mut bytes = Vec::new();
+    push_message(&mut bytes, 1, 0, 8, &[]);
+    push_message(&mut bytes, 2, 1, 16, &[1, 2, 3, 4]);
+
+    let mut messages = MessageIter::new(&bytes);
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), 8);
+    assert_eq!(messages.get_remaining().len(), 12);
+    assert!(messages.is_truncated());
+}
+
+#[test]
+fn truncated_header() {
+    let mut bytes = Vec::new();
+    push_message(&mut bytes, 1, 0, 8, &[]);
+    bytes.extend_from_slice(&[1, 0, 0]);
+
+    let mut messages = MessageIter::new(&bytes);
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), 8);
+    assert!(messages.is_truncated());
+}
+
+#[test]
+fn malformed_size() {
+    let mut bytes = Vec::new();
+    push_message(&mut bytes, 1, 0, 8, &[]);
+    push_message(&mut bytes, 2, 0, 4, &[]);
+    push_message(&mut bytes, 3, 0, 8, &[]);
+
+    let mut messages = MessageIter::new(&bytes);
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), 8);
+    assert!(!messages.is_truncated());
+
+    let mut bytes = Vec::new();
+    push_message(&mut bytes, 1, 0, 8, &[]);
+    push_message(&mut bytes, 2, 0, 10, &[1, 2]);
+    push_message(&mut bytes, 3, 0, 8, &[]);
+
+    let mut messages = MessageIter::new(&bytes);
+    assert!(messages.next().unwrap().is_ok());
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), 8);
+    assert!(!messages.is_truncated());
+}
+
+#[test]
+fn empty_buffer() {
+    let mut messages = MessageIter::new(&[]);
+    assert!(messages.next().is_none());
+    assert_eq!(messages.get_position(), 0);
+    assert!(!messages.is_truncated());
+}
+
+// -------------------------------------------------------------------------------------------------
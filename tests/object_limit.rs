@@ -0,0 +1,55 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of limit on number of objects.
+
+extern crate skylane;
+
+mod common;
+
+use skylane::server::{ObjectId, Side, SkylaneError};
+
+use common::{detached_connection, run_in_handler, Dummy};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that objects exceeding the limit are refused before they are registered.
+#[test]
+fn limit_is_checked_before_adding() {
+    let (_peer, mut connection) = detached_connection();
+    connection.set_side(Some(Side::Server));
+    connection.set_max_objects(Some(3));
+
+    let (results, replaced) = run_in_handler(&mut connection, |bundle| {
+        let results = (2..5)
+            .map(|id| bundle.add_remote_object(ObjectId::from_raw_unchecked(id), Box::new(Dummy)))
+            .collect::<Vec<_>>();
+
+        // Replacing registered object does not increase their number.
+        let replaced = bundle.add_remote_object(ObjectId::from_raw_unchecked(3), Box::new(Dummy));
+        (results, replaced)
+    });
+
+    assert!(results[0].is_ok());
+    assert!(results[1].is_ok());
+    match results[2] {
+        Err(SkylaneError::LimitExceeded { .. }) => {}
+        ref other => panic!("Expected exceeded limit, got {:?}", other),
+    }
+    assert!(connection.get_weak_ref(ObjectId::from_raw_unchecked(4)).is_none());
+    assert!(replaced.is_ok());
+}
@@ -0,0 +1,62 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of ownership of file descriptors passed with outgoing messages.
+
+extern crate skylane;
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::path::Path;
+
+use skylane::server::{Connection, Marshaller, OwnedFd, Socket, DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if file descriptor `fd` is open in this process.
+fn is_open(fd: i32) -> bool {
+    Path::new(&format!("/proc/self/fd/{}", fd)).exists()
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that descriptor owned by queued message stays open after the marshaller is gone and is
+/// handed out separately from borrowed ones in detached mode.
+#[test]
+fn drained_owned_fds_are_returned_separately() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    let controller = connection.get_controller();
+
+    let borrowed = File::open("/dev/null").expect("open /dev/null");
+    let owned = OwnedFd::new(File::open("/dev/null").expect("open /dev/null").into_raw_fd());
+    let owned_raw = owned.get_fd();
+
+    let mut marshaller = Marshaller::new(DISPLAY_ID, 0);
+    marshaller.put_fd(borrowed.as_raw_fd());
+    marshaller.put_owned_fd(owned);
+    controller.queue_message(marshaller);
+    assert!(is_open(owned_raw));
+
+    let (bytes, fds, owned) = connection.drain_output();
+    assert_eq!(bytes.len(), 8);
+    assert_eq!(fds.len(), 2);
+    assert_eq!(owned.len(), 1);
+    assert_eq!(owned[0].get_fd(), owned_raw);
+    assert!(fds.contains(&owned_raw));
+}
@@ -0,0 +1,104 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of client-side `Proxy`.
+
+extern crate skylane;
+
+use skylane::client::{Bundle, Connection, Message, MessageIter, Object, Proxy, Side,
+                      SkylaneError, Socket, Task};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Marker type of tested interface.
+struct Surface;
+
+/// Handler doing nothing.
+struct Dummy;
+
+impl Object for Dummy {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns object IDs, opcodes and arguments of all messages sent by `connection`.
+fn drain_messages(connection: &mut Connection) -> Vec<(u32, u16, Vec<u8>)> {
+    let (bytes, _, _) = connection.drain_output();
+    MessageIter::new(&bytes)
+        .map(|message| {
+                 let (header, args) = message.expect("parse message");
+                 (header.object_id, header.opcode, args.to_vec())
+             })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if requests creating and destroying objects are sent and objects are registered and
+/// removed accordingly.
+#[test]
+fn proxy_creates_and_destroys_objects() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+    connection.set_side(Some(Side::Client));
+
+    let mut compositor: Proxy<()> = connection.create_proxy(Box::new(Dummy)).expect("proxy");
+    compositor.set_version(4);
+    assert_eq!(compositor.get_version(), Some(4));
+
+    let surface: Proxy<Surface> = compositor.send_constructor(0, Box::new(Dummy), |m, id| {
+            m.put_new_id(id);
+        })
+        .expect("create surface");
+    assert!(surface.is_alive());
+    let surface_id = surface.get_id();
+
+    surface.queue_request(2, |m| m.put_int(7));
+    let surface_copy = surface.clone();
+    surface.destroy(1).expect("destroy surface");
+    assert!(!surface_copy.is_alive());
+    assert!(connection.get_weak_ref(surface_id).is_none());
+
+    let compositor_id = compositor.get_id().get_value();
+    let surface_id = surface_id.get_value();
+    let new_id = surface_id.to_ne_bytes().to_vec();
+    let seven = 7i32.to_ne_bytes().to_vec();
+    assert_eq!(drain_messages(&mut connection),
+               vec![(compositor_id, 0, new_id),
+                    (surface_id, 2, seven),
+                    (surface_id, 1, Vec::new())]);
+}
+
+/// Checks if handler of events can be replaced.
+#[test]
+fn proxy_replaces_handler() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.set_detached_io(true);
+
+    let mut proxy: Proxy<Surface> = connection.create_proxy(Box::new(Dummy)).expect("proxy");
+    proxy.set_handler(Box::new(Dummy)).expect("replace handler");
+    proxy.remove();
+    assert!(!proxy.is_alive());
+    assert!(proxy.set_handler(Box::new(Dummy)).is_err());
+}
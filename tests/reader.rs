@@ -0,0 +1,65 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of reading from socket.
+
+extern crate skylane;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use skylane::server::{Connection, Marshaller, Socket, DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that reading mode set on any clone of socket applies to all of them.
+#[test]
+fn blocking_mode_is_shared_by_clones() {
+    let (_peer, socket) = Socket::pair().expect("socket pair");
+    let connection = Connection::new(socket);
+    assert!(connection.get_socket().is_nonblocking());
+
+    connection.get_socket().set_nonblocking(false);
+    assert!(!connection.get_socket().is_nonblocking());
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that thread blocked on reading does not prevent dispatching in other thread.
+#[test]
+fn blocked_read_does_not_block_dispatch() {
+    let (peer, socket) = Socket::pair().expect("socket pair");
+    let mut connection = Connection::new(socket);
+    connection.get_socket().set_nonblocking(false);
+
+    let intent = connection.prepare_read().expect("prepare read");
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::spawn(move || {
+                                   sender.send(()).expect("send");
+                                   intent.read_events()
+                               });
+    receiver.recv().expect("receive");
+    thread::sleep(Duration::from_millis(50));
+
+    connection.dispatch_pending().expect("dispatch");
+
+    let (bytes, fds) = Marshaller::new(DISPLAY_ID, 0).finish().expect("finish message");
+    peer.write_with_control_data(&bytes, &fds).expect("write");
+    let size = thread.join().expect("join").expect("read");
+    assert_eq!(size, bytes.len());
+}
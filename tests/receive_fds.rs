@@ -0,0 +1,103 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of receiving file descriptors from socket.
+
+extern crate skylane;
+
+use std::cell::Cell;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+
+use skylane::server::{Bundle, Connection, Marshaller, Message, Object, SkylaneError, Socket, Task,
+                      DISPLAY_ID};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler taking number of file descriptors from the first argument and then the descriptors.
+struct FdCounter {
+    count: Rc<Cell<usize>>,
+}
+
+impl Object for FdCounter {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        let num_fds = message.next_uint()?;
+        for _ in 0..num_fds {
+            message.next_fd()?;
+            self.count.set(self.count.get() + 1);
+        }
+        Ok(Task::None)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Sends message with `num_fds` copies of `fd` through `peer`.
+fn send_fds(peer: &Socket, fd: RawFd, num_fds: usize) {
+    let mut marshaller = Marshaller::new(DISPLAY_ID, 0);
+    marshaller.put_uint(num_fds as u32);
+    for _ in 0..num_fds {
+        marshaller.put_fd(fd);
+    }
+    let (bytes, fds) = marshaller.finish().expect("finish message");
+    peer.write_with_control_data(&bytes, &fds).expect("write");
+}
+
+/// Returns connection with handler counting received file descriptors.
+fn setup() -> (Connection, Socket, Rc<Cell<usize>>) {
+    let (peer, socket) = Socket::pair().expect("socket pair");
+    let count = Rc::new(Cell::new(0));
+    let mut connection = Connection::new(socket);
+    connection.add_object(DISPLAY_ID, Box::new(FdCounter { count: count.clone() }));
+    (connection, peer, count)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that all descriptors passed along with one message reach the handler.
+#[test]
+fn many_fds_are_received() {
+    let (mut connection, peer, count) = setup();
+    let file = File::open("/dev/null").expect("open /dev/null");
+
+    send_fds(&peer, file.as_raw_fd(), 8);
+    connection.process_events().expect("process events");
+    assert_eq!(count.get(), 8);
+
+    send_fds(&peer, file.as_raw_fd(), 28);
+    connection.process_events().expect("process events");
+    assert_eq!(count.get(), 36);
+}
+
+/// Checks that descriptors which do not fit in receive buffer are reported instead of being
+/// silently dropped.
+#[test]
+fn lost_fds_are_reported() {
+    let (mut connection, peer, count) = setup();
+    let file = File::open("/dev/null").expect("open /dev/null");
+
+    send_fds(&peer, file.as_raw_fd(), 40);
+    match connection.process_events() {
+        Err(SkylaneError::LimitExceeded { .. }) => {}
+        other => panic!("Expected exceeded limit, got {:?}", other),
+    }
+    assert_eq!(count.get(), 0);
+    assert_eq!(connection.stats().fds_dropped, 40);
+}
@@ -0,0 +1,112 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of re-establishing connection on client side.
+
+extern crate skylane;
+
+use std::cell::Cell;
+use std::os::unix::net::UnixListener;
+use std::rc::Rc;
+
+use skylane::client::{Bundle, Connection, ConnectionState, Controller, Message, Object, ObjectId,
+                      RebindCallback, ReconnectPolicy, SkylaneError, Socket, Task};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler removing another object when dropped.
+struct DropProbe {
+    controller: Controller,
+    dropped: Rc<Cell<bool>>,
+}
+
+impl Object for DropProbe {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Ok(Task::None)
+    }
+}
+
+impl Drop for DropProbe {
+    fn drop(&mut self) {
+        self.controller.remove_object(ObjectId::from_raw_unchecked(3));
+        self.dropped.set(true);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that handlers of old objects may use the connection when dropped on reconnection and
+/// that handles obtained before reconnection do not reach the new connection.
+#[test]
+fn reconnect_invalidates_old_handles() {
+    let dir = std::env::temp_dir().join(format!("skylane-reconnect-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create directory");
+    let path = dir.join("socket");
+    let _ = std::fs::remove_file(&path);
+    let _listener = UnixListener::bind(&path).expect("bind");
+
+    let mut connection = Connection::new(Socket::connect(&path).expect("connect"));
+    let rebind: RebindCallback = Box::new(|_connection| Ok(()));
+    connection.set_reconnect_policy(ReconnectPolicy::new(Some(path.clone())), rebind);
+
+    let mut old_controller = connection.get_controller();
+    let dropped = Rc::new(Cell::new(false));
+    let probe = DropProbe {
+        controller: connection.get_controller(),
+        dropped: dropped.clone(),
+    };
+    connection.add_object(ObjectId::from_raw_unchecked(2), Box::new(probe));
+
+    connection.reconnect().expect("reconnect");
+    assert!(dropped.get());
+    assert!(connection.get_weak_ref(ObjectId::from_raw_unchecked(2)).is_none());
+
+    old_controller.add_object(ObjectId::from_raw_unchecked(4), Box::new(DropProbe {
+                                  controller: old_controller.clone(),
+                                  dropped: Rc::new(Cell::new(false)),
+                              }));
+    assert!(connection.get_weak_ref(ObjectId::from_raw_unchecked(4)).is_none());
+    match old_controller.send_event(&[], &[]) {
+        Err(SkylaneError::Closed) => {}
+        other => panic!("Expected closed connection, got {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Checks that connection is left closed if rebinding fails.
+#[test]
+fn failed_rebind_leaves_connection_closed() {
+    let dir = std::env::temp_dir().join(format!("skylane-rebind-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create directory");
+    let path = dir.join("socket");
+    let _ = std::fs::remove_file(&path);
+    let _listener = UnixListener::bind(&path).expect("bind");
+
+    let mut connection = Connection::new(Socket::connect(&path).expect("connect"));
+    let rebind: RebindCallback =
+        Box::new(|_connection| Err(SkylaneError::Other("Rebind failure".to_owned())));
+    connection.set_reconnect_policy(ReconnectPolicy::new(Some(path.clone())), rebind);
+
+    assert!(connection.reconnect().is_err());
+    assert_eq!(connection.state(), ConnectionState::Closed);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
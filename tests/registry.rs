@@ -0,0 +1,65 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of server-side `wl_registry` implementation.
+
+extern crate skylane;
+
+use skylane::client;
+use skylane::server;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler of test globals ignoring all requests.
+struct TestObject;
+
+impl server::Object for TestObject {}
+
+/// Returns factory of test global.
+fn test_factory() -> server::GlobalFactory {
+    Box::new(|_, _, _| Ok(Box::new(TestObject) as Box<dyn server::Object>))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that registry shared between connections does not keep dropped connections open.
+#[test]
+fn dropped_connection_is_released() {
+    let registry = server::GlobalRegistry::new();
+    registry.add_global("wl_compositor", 4, test_factory()).expect("add global");
+
+    let (client_socket, server_socket) = server::Socket::pair().expect("socket pair");
+    let info = server::ClientInfo::new(&server_socket, None);
+    let mut server = server::Connection::new_server(server_socket, registry.get_factory(info));
+
+    let mut client = client::Connection::new(client_socket);
+    client.set_side(Some(client::Side::Client));
+    client.add_object(client::DISPLAY_ID, Box::new(client::ClientDisplay));
+    let client_registry = client::Registry::new(&mut client).expect("get registry");
+    server.process_events().expect("process requests");
+    client.process_events().expect("process events");
+    assert_eq!(client_registry.get_globals().len(), 1);
+
+    // Socket is closed once the server drops the connection, so the client sees end of stream.
+    drop(server);
+    assert_eq!(client.read_events().expect("read"), 0);
+
+    // Globals can still be added and removed.
+    let name = registry.add_global("wl_output", 3, test_factory()).expect("add global");
+    assert!(registry.remove_global(name));
+}
+
+// -------------------------------------------------------------------------------------------------
@@ -0,0 +1,113 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of `Loopback` test harness.
+
+#![cfg(feature = "testing")]
+
+extern crate skylane;
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+use skylane::server::{Connection, InterfaceMeta, MessageMeta, ObjectId, DISPLAY_ID};
+use skylane::testing::{Arg, Loopback};
+
+// -------------------------------------------------------------------------------------------------
+
+static OUTPUT_META: InterfaceMeta = InterfaceMeta {
+    name: "test_output",
+    version: 1,
+    requests: &[],
+    events: &[MessageMeta { name: "geometry", signature: "iusa" },
+              MessageMeta { name: "keymap", signature: "uhu" }],
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks if events sent by tested server are decoded and matched against expectations.
+#[test]
+fn expect_event_decodes_arguments() {
+    let (mut loopback, socket) = Loopback::pair().expect("loopback pair");
+    let connection = Connection::new(socket);
+    let controller = connection.get_controller();
+    let output_id = ObjectId::from_raw_unchecked(5);
+    loopback.set_interface_meta(output_id, &OUTPUT_META);
+
+    let keymap = File::open("/dev/null").expect("open /dev/null");
+    controller.send(output_id, 0, |m| {
+        m.put_int(-3);
+        m.put_uint(7);
+        m.put_string("monitor");
+        m.put_array(&[1, 2, 3]);
+    });
+    controller.send(output_id, 1, |m| {
+        m.put_uint(1);
+        m.put_fd(keymap.as_raw_fd());
+        m.put_uint(42);
+    });
+    controller.flush().expect("flush");
+
+    let geometry = loopback.expect_event("test_output",
+                                         0,
+                                         &[Arg::Int(-3),
+                                           Arg::Uint(7),
+                                           Arg::Str(Some("monitor".to_owned())),
+                                           Arg::Array(vec![1, 2, 3])]);
+    assert_eq!(geometry.name, Some("geometry"));
+    assert!(geometry.fds.is_empty());
+
+    let keymap = loopback.expect_event("test_output", 1, &[Arg::Uint(1), Arg::Fd, Arg::Uint(42)]);
+    assert_eq!(keymap.name, Some("keymap"));
+    assert_eq!(keymap.fds.len(), 1);
+
+    loopback.expect_no_more_messages();
+}
+
+/// Checks if unexpected arguments cause panic.
+#[test]
+#[should_panic(expected = "Unexpected arguments of event test_output.geometry")]
+fn expect_event_panics_on_mismatch() {
+    let (mut loopback, socket) = Loopback::pair().expect("loopback pair");
+    let connection = Connection::new(socket);
+    let controller = connection.get_controller();
+    let output_id = ObjectId::from_raw_unchecked(5);
+    loopback.set_interface_meta(output_id, &OUTPUT_META);
+
+    controller.send(output_id, 0, |m| {
+        m.put_int(1);
+        m.put_uint(2);
+        m.put_string("monitor");
+        m.put_array(&[]);
+    });
+    controller.flush().expect("flush");
+
+    loopback.expect_event("test_output", 0, &[Arg::Int(0)]);
+}
+
+/// Checks if messages left unread cause panic.
+#[test]
+#[should_panic(expected = "Expected no more messages")]
+fn expect_no_more_messages_panics_on_pending_message() {
+    let (mut loopback, socket) = Loopback::pair().expect("loopback pair");
+    let connection = Connection::new(socket);
+    let controller = connection.get_controller();
+
+    controller.send(DISPLAY_ID, 1, |m| m.put_uint(3));
+    controller.flush().expect("flush");
+
+    loopback.expect_no_more_messages();
+}
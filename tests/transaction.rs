@@ -0,0 +1,99 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of transactions grouping changes of objects.
+
+extern crate skylane;
+
+mod common;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use skylane::server::{Bundle, Controller, Message, Object, ObjectId, SkylaneError, Task};
+
+use common::{detached_connection, run_in_handler, Dummy};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Handler using the connection when dropped.
+struct Toucher {
+    controller: Controller,
+    dropped: Rc<Cell<bool>>,
+}
+
+impl Object for Toucher {
+    fn dispatch_message(&mut self,
+                        _bundle: &mut Bundle,
+                        _message: &mut Message)
+                        -> Result<Task, SkylaneError> {
+        Ok(Task::None)
+    }
+}
+
+impl Drop for Toucher {
+    fn drop(&mut self) {
+        self.controller.remove_object(ObjectId::from_raw_unchecked(9));
+        self.dropped.set(true);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that rollback restores metadata and zombie state and drops objects safely.
+#[test]
+fn rollback_restores_objects() {
+    let (_peer, mut connection) = detached_connection();
+    let existing = ObjectId::from_raw_unchecked(5);
+    let zombie = ObjectId::from_raw_unchecked(7);
+    let added = ObjectId::from_raw_unchecked(8);
+    let dropped = Rc::new(Cell::new(false));
+    let toucher = Toucher {
+        controller: connection.get_controller(),
+        dropped: dropped.clone(),
+    };
+
+    let scenario = move |bundle: &mut Bundle| {
+        bundle.add_object(existing, Box::new(Dummy));
+        bundle.set_object_version(existing, 2);
+        bundle.add_object(zombie, Box::new(Dummy));
+        bundle.remove_object(zombie);
+        let was_zombie = bundle.is_zombie(zombie);
+
+        let result: Result<(), SkylaneError> = bundle.transaction(|bundle| {
+            bundle.add_object(existing, Box::new(Dummy));
+            bundle.set_object_version(existing, 7);
+            bundle.add_object(zombie, Box::new(Dummy));
+            bundle.add_object(added, Box::new(toucher));
+            Err(SkylaneError::Other("failed".to_owned()))
+        });
+        (was_zombie,
+         result,
+         bundle.get_object_version(existing),
+         bundle.is_zombie(zombie),
+         bundle.get_weak_ref(added).is_some())
+    };
+    let (was_zombie, result, version, is_zombie, is_added) = run_in_handler(&mut connection,
+                                                                           scenario);
+
+    assert!(was_zombie);
+    assert!(result.is_err());
+    assert!(dropped.get());
+    assert_eq!(version, Some(2));
+    assert!(is_zombie);
+    assert!(!is_added);
+}
@@ -0,0 +1,63 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Tests of `io_uring` transport. Built only with `io-uring` feature.
+
+#![cfg(feature = "io-uring")]
+
+extern crate skylane;
+
+use std::time::Duration;
+
+use skylane::client;
+use skylane::server;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Sends `wl_display.sync` from client to server driven by `UringTransport` and checks if the
+/// callback is fired by events sent back through the ring.
+#[test]
+fn sync_round_trip() {
+    let (client_socket, server_socket) = server::Socket::pair().expect("socket pair");
+
+    let registry = server::GlobalRegistry::new();
+    let info = server::ClientInfo::new(&server_socket, None);
+    let mut server = server::Connection::new_server(server_socket, registry.get_factory(info));
+    let mut transport = server::UringTransport::new(&mut server).expect("transport");
+    assert!(server.is_detached_io());
+
+    let mut client = client::Connection::new(client_socket);
+    client.set_side(Some(client::Side::Client));
+    client.add_object(client::DISPLAY_ID, Box::new(client::ClientDisplay));
+    let callback = client.sync().expect("sync");
+
+    let report = transport.wait(&mut server).expect("wait");
+    assert!(report.is_ok(), "Dispatch failed: {:?}", report.failures);
+    assert_eq!(report.num_dispatched, 1);
+
+    let socket = client.get_socket();
+    while !callback.is_done() {
+        assert!(socket.wait_readable(Some(Duration::from_secs(5))).expect("wait readable"),
+                "Server did not respond in time");
+        client.process_events().expect("process events");
+    }
+    assert!(!transport.is_closed());
+
+    // Receive operation is still pending; dropping must cancel it.
+    drop(transport);
+}
+
+// -------------------------------------------------------------------------------------------------
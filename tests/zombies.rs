@@ -0,0 +1,49 @@
+// Copyright 2016-2017 The Perceptia Project Developers
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Tests of tracking removed objects.
+
+extern crate skylane;
+
+mod common;
+
+use skylane::server::{ObjectId, Side, SERVER_START_ID};
+
+use common::{detached_connection, run_in_handler, Dummy};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Checks that zombies of objects created by server, whose removal is never confirmed by client,
+/// do not accumulate endlessly.
+#[test]
+fn server_zombies_are_reaped() {
+    let (_peer, mut connection) = detached_connection();
+    connection.set_side(Some(Side::Server));
+
+    let first = ObjectId::from_raw_unchecked(SERVER_START_ID.get_value());
+    let last = ObjectId::from_raw_unchecked(SERVER_START_ID.get_value() + 999);
+    let (is_first_zombie, is_last_zombie) = run_in_handler(&mut connection, move |bundle| {
+        for i in 0..1000 {
+            let id = ObjectId::from_raw_unchecked(first.get_value() + i);
+            bundle.add_object(id, Box::new(Dummy));
+            bundle.remove_object(id);
+        }
+        (bundle.is_zombie(first), bundle.is_zombie(last))
+    });
+    assert!(!is_first_zombie);
+    assert!(is_last_zombie);
+}